@@ -30,6 +30,11 @@ pub fn ax_read_file(file: &mut AxFileHandle, buf: &mut [u8]) -> AxResult<usize>
     file.0.read(buf)
 }
 
+#[cfg(feature = "multitask")]
+pub async fn ax_read_file_async(file: &mut AxFileHandle, buf: &mut [u8]) -> AxResult<usize> {
+    file.0.read_async(buf).await
+}
+
 pub fn ax_read_file_at(file: &AxFileHandle, offset: u64, buf: &mut [u8]) -> AxResult<usize> {
     file.0.read_at(offset, buf)
 }
@@ -12,6 +12,15 @@ cfg_alloc! {
     }
 }
 
+#[cfg(feature = "heap-profile")]
+pub use axalloc::{
+    dump_heap_profile as ax_dump_heap_profile, set_heap_profiling as ax_set_heap_profiling,
+    set_heap_tracing as ax_set_heap_tracing,
+};
+
+#[cfg(all(feature = "debug-heap", feature = "paging"))]
+pub use axalloc::set_guard_pages as ax_set_guard_pages;
+
 cfg_dma! {
     pub use axdma::DMAInfo;
 
@@ -64,6 +64,25 @@ pub fn ax_tcp_shutdown(socket: &AxTcpSocketHandle) -> AxResult {
     socket.0.shutdown()
 }
 
+#[cfg(feature = "multitask")]
+pub async fn ax_tcp_accept_async(
+    socket: &AxTcpSocketHandle,
+) -> AxResult<(AxTcpSocketHandle, SocketAddr)> {
+    let new_sock = socket.0.accept_async().await?;
+    let addr = new_sock.peer_addr()?;
+    Ok((AxTcpSocketHandle(new_sock), addr))
+}
+
+#[cfg(feature = "multitask")]
+pub async fn ax_tcp_send_async(socket: &AxTcpSocketHandle, buf: &[u8]) -> AxResult<usize> {
+    socket.0.send_async(buf).await
+}
+
+#[cfg(feature = "multitask")]
+pub async fn ax_tcp_recv_async(socket: &AxTcpSocketHandle, buf: &mut [u8]) -> AxResult<usize> {
+    socket.0.recv_async(buf).await
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // UDP socket
 ////////////////////////////////////////////////////////////////////////////////
@@ -39,6 +39,9 @@ cfg_task! {
         }
     }
 
+    /// A set of CPUs that a task is allowed to run on.
+    pub type AxCpuMask = axtask::CpuMask;
+
     /// A handle to a wait queue.
     ///
     /// A wait queue is used to store sleeping tasks waiting for a certain event
@@ -82,6 +85,66 @@ cfg_task! {
         }
     }
 
+    pub fn ax_spawn_with_affinity<F>(
+        f: F,
+        name: alloc::string::String,
+        stack_size: usize,
+        cpumask: AxCpuMask,
+    ) -> crate::AxResult<AxTaskHandle>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if cpumask.is_empty() {
+            return axerrno::ax_err!(InvalidInput, "ax_spawn_with_affinity: empty CPU mask");
+        }
+        let mut task = axtask::TaskInner::new(f, name, stack_size);
+        task.set_cpumask(cpumask);
+        let inner = axtask::spawn_task(task);
+        Ok(AxTaskHandle {
+            id: inner.id().as_u64(),
+            inner,
+        })
+    }
+
+    pub fn ax_get_affinity(task: Option<&AxTaskHandle>) -> AxCpuMask {
+        match task {
+            Some(task) => axtask::get_affinity(&task.inner),
+            None => axtask::current().cpumask(),
+        }
+    }
+
+    pub fn ax_set_affinity(task: Option<&AxTaskHandle>, cpumask: AxCpuMask) -> crate::AxResult {
+        let ok = match task {
+            Some(task) => axtask::set_affinity(&task.inner, cpumask),
+            None => axtask::set_current_affinity(cpumask),
+        };
+        if ok {
+            Ok(())
+        } else {
+            axerrno::ax_err!(InvalidInput, "ax_set_affinity: no online CPU in the mask")
+        }
+    }
+
+    pub fn ax_scheduler_name() -> &'static str {
+        axtask::scheduler_name()
+    }
+
+    pub fn ax_select_scheduler(name: &str) -> crate::AxResult {
+        if axtask::select_scheduler(name) {
+            Ok(())
+        } else {
+            axerrno::ax_err!(NotFound, "ax_select_scheduler: no such scheduler")
+        }
+    }
+
+    pub fn ax_with_current_local(
+        key: usize,
+        init: impl FnOnce() -> alloc::boxed::Box<dyn core::any::Any>,
+        f: impl FnOnce(&dyn core::any::Any),
+    ) -> bool {
+        axtask::with_current_local(key, init, |var| f(&**var)).is_some()
+    }
+
     pub fn ax_wait_queue_wait(
         wq: &AxWaitQueueHandle,
         until_condition: impl Fn() -> bool,
@@ -75,6 +75,26 @@ pub mod mem {
         pub unsafe fn ax_dealloc(ptr: NonNull<u8>, layout: Layout);
     }
 
+    define_api! {
+        @cfg "heap-profile";
+        /// Switches heap profiling on or off, which records the allocations
+        /// by their call sites. It is off by default.
+        pub fn ax_set_heap_profiling(enabled: bool);
+        /// Switches heap tracing on or off, which prints every allocation and
+        /// deallocation to the console. It is off by default.
+        pub fn ax_set_heap_tracing(enabled: bool);
+        /// Prints a report of the recorded heap statistics to the console.
+        pub fn ax_dump_heap_profile();
+    }
+
+    #[cfg(feature = "paging")]
+    define_api! {
+        @cfg "debug-heap";
+        /// Switches guard pages on or off for new allocations of at least one
+        /// page. It is on by default with the `debug-heap` feature.
+        pub fn ax_set_guard_pages(enabled: bool);
+    }
+
     define_api_type! {
         @cfg "dma";
         pub type DMAInfo;
@@ -121,6 +141,7 @@ pub mod task {
         @cfg "multitask";
         pub type AxTaskHandle;
         pub type AxWaitQueueHandle;
+        pub type AxCpuMask;
     }
 
     define_api! {
@@ -156,6 +177,42 @@ pub mod task {
         /// Sets the priority of the current task.
         pub fn ax_set_current_priority(prio: isize) -> crate::AxResult;
 
+        /// Spawns a new task that is only allowed to run on the CPUs in
+        /// `cpumask`.
+        pub fn ax_spawn_with_affinity(
+            f: impl FnOnce() + Send + 'static,
+            name: alloc::string::String,
+            stack_size: usize,
+            cpumask: AxCpuMask,
+        ) -> crate::AxResult<AxTaskHandle>;
+        /// Returns the CPUs that the given task (or the current task if `task`
+        /// is `None`) is allowed to run on.
+        pub fn ax_get_affinity(task: Option<&AxTaskHandle>) -> AxCpuMask;
+        /// Sets the CPUs that the given task (or the current task if `task` is
+        /// `None`) is allowed to run on.
+        ///
+        /// The task is migrated if it is not allowed to run on its current
+        /// CPU any more.
+        pub fn ax_set_affinity(task: Option<&AxTaskHandle>, cpumask: AxCpuMask) -> crate::AxResult;
+
+        /// Returns the name of the scheduler of normal tasks in use.
+        pub fn ax_scheduler_name() -> &'static str;
+        /// Selects the scheduler of normal tasks by its name (e.g., `"fifo"`,
+        /// `"rr"` or `"cfs"`), the ready tasks are moved to it at once.
+        pub fn ax_select_scheduler(name: &str) -> crate::AxResult;
+
+        /// Calls `f` with the task-local variable of the current task
+        /// identified by `key` (e.g., the address of a `static`), which is
+        /// initialized by `init` on the first access.
+        ///
+        /// The variables are dropped when the task exits. Returns `false`
+        /// without calling `f` if they are accessed after that.
+        pub fn ax_with_current_local(
+            key: usize,
+            init: impl FnOnce() -> alloc::boxed::Box<dyn core::any::Any>,
+            f: impl FnOnce(&dyn core::any::Any),
+        ) -> bool;
+
         /// Blocks the current task and put it into the wait queue, until the
         /// given condition becomes true, or the the given duration has elapsed
         /// (if specified).
@@ -255,6 +312,17 @@ pub mod fs {
         /// Changes the current working directory to the specified path.
         pub fn ax_set_current_dir(path: &str) -> AxResult;
     }
+
+    define_api! {
+        @cfg "fs";
+
+        /// Reads the file like [`ax_read_file`], but in chunks, and yields to
+        /// other futures between them.
+        ///
+        /// The future can be run on the executor of `axtask::future`.
+        #[cfg(feature = "multitask")]
+        pub async fn ax_read_file_async(file: &mut AxFileHandle, buf: &mut [u8]) -> AxResult<usize>;
+    }
 }
 
 /// Networking primitives for TCP/UDP communication.
@@ -350,6 +418,24 @@ pub mod net {
         /// packets to the NIC.
         pub fn ax_poll_interfaces() -> AxResult;
     }
+
+    define_api! {
+        @cfg "net";
+
+        // Async TCP socket, the futures can be run on the executor of
+        // `axtask::future`.
+
+        /// Accepts a new connection like [`ax_tcp_accept`], but waits
+        /// asynchronously.
+        #[cfg(feature = "multitask")]
+        pub async fn ax_tcp_accept_async(socket: &AxTcpSocketHandle) -> AxResult<(AxTcpSocketHandle, SocketAddr)>;
+        /// Transmits data like [`ax_tcp_send`], but waits asynchronously.
+        #[cfg(feature = "multitask")]
+        pub async fn ax_tcp_send_async(socket: &AxTcpSocketHandle, buf: &[u8]) -> AxResult<usize>;
+        /// Receives data like [`ax_tcp_recv`], but waits asynchronously.
+        #[cfg(feature = "multitask")]
+        pub async fn ax_tcp_recv_async(socket: &AxTcpSocketHandle, buf: &mut [u8]) -> AxResult<usize>;
+    }
 }
 
 /// Graphics manipulation operations.
@@ -2,16 +2,8 @@ fn main() {
     use std::io::Write;
 
     fn gen_pthread_mutex(out_file: &str) -> std::io::Result<()> {
-        // TODO: generate size and initial content automatically.
-        let (mutex_size, mutex_init) = if cfg!(feature = "multitask") {
-            if cfg!(feature = "smp") {
-                (6, "{0, 0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 6]>(axsync::Mutex::new(()))
-            } else {
-                (5, "{0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 5]>(axsync::Mutex::new(()))
-            }
-        } else {
-            (1, "{0}")
-        };
+        // The owner ID of an unlocked mutex (see `PthreadMutex`).
+        let (mutex_size, mutex_init) = (1, "{0}");
 
         let mut output = Vec::new();
         writeln!(
@@ -48,10 +40,14 @@ typedef struct {{
             "pthread_attr_t",
             "pthread_mutex_t",
             "pthread_mutexattr_t",
+            "pid_t",
+            "cpu_set_t",
+            "sched_param",
             "epoll_event",
             "iovec",
             "clockid_t",
             "rlimit",
+            "rusage",
             "aibuf",
         ];
         let allow_vars = [
@@ -66,6 +62,8 @@ typedef struct {{
             "EPOLL_CTL_.*",
             "EPOLL.*",
             "RLIMIT_.*",
+            "RUSAGE_.*",
+            "SCHED_.*",
             "EAI_.*",
             "MAXADDRS",
         ];
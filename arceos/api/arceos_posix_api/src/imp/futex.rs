@@ -0,0 +1,112 @@
+use core::ffi::c_int;
+use core::sync::atomic::AtomicU32;
+use core::time::Duration;
+
+use axerrno::{LinuxError, LinuxResult};
+use axtask::{FutexError, FUTEX_BITSET_MATCH_ANY};
+
+use crate::ctypes;
+
+const FUTEX_WAIT: c_int = 0;
+const FUTEX_WAKE: c_int = 1;
+const FUTEX_REQUEUE: c_int = 3;
+const FUTEX_CMP_REQUEUE: c_int = 4;
+const FUTEX_WAIT_BITSET: c_int = 9;
+const FUTEX_WAKE_BITSET: c_int = 10;
+const FUTEX_PRIVATE_FLAG: c_int = 128;
+const FUTEX_CLOCK_REALTIME: c_int = 256;
+
+fn futex_err(err: FutexError) -> LinuxError {
+    match err {
+        FutexError::WouldBlock => LinuxError::EAGAIN,
+        FutexError::TimedOut => LinuxError::ETIMEDOUT,
+        FutexError::InvalidInput => LinuxError::EINVAL,
+    }
+}
+
+fn futex_ref<'a>(uaddr: *mut u32) -> LinuxResult<&'a AtomicU32> {
+    if uaddr.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    if !uaddr.is_aligned() {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(unsafe { AtomicU32::from_ptr(uaddr) })
+}
+
+/// Converts the timeout of `FUTEX_WAIT` (relative) or `FUTEX_WAIT_BITSET`
+/// (absolute) to a duration from now.
+fn wait_timeout(timeout: *const ctypes::timespec, op: c_int) -> LinuxResult<Option<Duration>> {
+    if timeout.is_null() {
+        return Ok(None);
+    }
+    let ts = unsafe { *timeout };
+    if ts.tv_sec < 0 || !(0..1_000_000_000).contains(&ts.tv_nsec) {
+        return Err(LinuxError::EINVAL);
+    }
+    let dur = Duration::from(ts);
+    if op & !FUTEX_CLOCK_REALTIME != FUTEX_WAIT_BITSET {
+        return Ok(Some(dur));
+    }
+    let now = if op & FUTEX_CLOCK_REALTIME != 0 {
+        axhal::time::wall_time()
+    } else {
+        axhal::time::monotonic_time()
+    };
+    Ok(Some(dur.saturating_sub(now)))
+}
+
+/// Waits on or wakes up the tasks waiting on the futex word `uaddr`.
+///
+/// The operations `FUTEX_WAIT`, `FUTEX_WAKE`, `FUTEX_REQUEUE`,
+/// `FUTEX_CMP_REQUEUE`, `FUTEX_WAIT_BITSET` and `FUTEX_WAKE_BITSET` are
+/// supported, with the same arguments as Linux. For the requeue operations,
+/// `timeout` is the maximum number of tasks to requeue (`val2`). All futexes
+/// are private to the address space, `FUTEX_PRIVATE_FLAG` is ignored.
+pub unsafe fn sys_futex(
+    uaddr: *mut u32,
+    op: c_int,
+    val: u32,
+    timeout: *const ctypes::timespec,
+    uaddr2: *mut u32,
+    val3: u32,
+) -> c_int {
+    debug!(
+        "sys_futex <= {:#x} {} {} {:#x}",
+        uaddr as usize, op, val, timeout as usize
+    );
+    syscall_body!(sys_futex, {
+        let futex = futex_ref(uaddr)?;
+        let op = op & !FUTEX_PRIVATE_FLAG;
+        let cmd = op & !FUTEX_CLOCK_REALTIME;
+        if op & FUTEX_CLOCK_REALTIME != 0 && cmd != FUTEX_WAIT_BITSET {
+            return Err(LinuxError::ENOSYS);
+        }
+        // The counts are `int`s, e.g., `INT_MAX` to wake up all.
+        let count = (val as c_int).max(0) as usize;
+        match cmd {
+            FUTEX_WAIT | FUTEX_WAIT_BITSET => {
+                let bitset = if cmd == FUTEX_WAIT {
+                    FUTEX_BITSET_MATCH_ANY
+                } else {
+                    val3
+                };
+                let timeout = wait_timeout(timeout, op)?;
+                axtask::futex_wait(futex, val, timeout, bitset).map_err(futex_err)?;
+                Ok(0)
+            }
+            FUTEX_WAKE => {
+                axtask::futex_wake(futex, count, FUTEX_BITSET_MATCH_ANY).map_err(futex_err)
+            }
+            FUTEX_WAKE_BITSET => axtask::futex_wake(futex, count, val3).map_err(futex_err),
+            FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+                let futex2 = futex_ref(uaddr2)?;
+                let requeue_count = (timeout as usize as c_int).max(0) as usize;
+                let expected = (cmd == FUTEX_CMP_REQUEUE).then_some(val3);
+                axtask::futex_requeue(futex, count, futex2, requeue_count, expected)
+                    .map_err(futex_err)
+            }
+            _ => Err(LinuxError::ENOSYS),
+        }
+    })
+}
@@ -103,6 +103,13 @@ impl Pthread {
     }
 }
 
+/// Returns the task of the thread with the given ID.
+pub(crate) fn find_task(tid: u64) -> Option<AxTaskRef> {
+    let threads = TID_TO_PTHREAD.read();
+    let ptr = threads.get(&tid)?.0 as *const Pthread;
+    Some(unsafe { &*ptr }.inner.clone())
+}
+
 /// Returns the `pthread` struct of current thread.
 pub fn sys_pthread_self() -> ctypes::pthread_t {
     Pthread::current().expect("fail to get current thread") as *const Pthread as _
@@ -147,6 +154,34 @@ pub unsafe fn sys_pthread_join(thread: ctypes::pthread_t, retval: *mut *mut c_vo
     })
 }
 
+/// Sets the CPUs that the given thread is allowed to run on.
+pub unsafe fn sys_pthread_setaffinity_np(
+    thread: ctypes::pthread_t,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_pthread_setaffinity_np <= {:#x}", thread as usize);
+    syscall_body!(sys_pthread_setaffinity_np, {
+        let thread = unsafe { &*(thread as *const Pthread) };
+        unsafe { super::task::set_task_affinity(&thread.inner, cpusetsize, cpuset)? };
+        Ok(0)
+    })
+}
+
+/// Gets the CPUs that the given thread is allowed to run on.
+pub unsafe fn sys_pthread_getaffinity_np(
+    thread: ctypes::pthread_t,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_pthread_getaffinity_np <= {:#x}", thread as usize);
+    syscall_body!(sys_pthread_getaffinity_np, {
+        let thread = unsafe { &*(thread as *const Pthread) };
+        unsafe { super::task::get_task_affinity(&thread.inner, cpusetsize, cpuset)? };
+        Ok(0)
+    })
+}
+
 #[derive(Clone, Copy)]
 struct ForceSendSync<T>(T);
 
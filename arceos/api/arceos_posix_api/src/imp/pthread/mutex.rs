@@ -1,31 +1,39 @@
 use crate::{ctypes, utils::check_null_mut_ptr};
 
 use axerrno::LinuxResult;
-use axsync::Mutex;
 
 use core::ffi::c_int;
-use core::mem::{size_of, ManuallyDrop};
+use core::mem::size_of;
+use core::sync::atomic::AtomicU64;
 
 static_assertions::const_assert_eq!(
     size_of::<ctypes::pthread_mutex_t>(),
     size_of::<PthreadMutex>()
 );
 
+/// A mutex on a futex word holding the ID of the owner, which only enters the
+/// futex wait queues when it is contended.
+///
+/// It supports priority inheritance as [`axsync::Mutex`] does.
 #[repr(C)]
-pub struct PthreadMutex(Mutex<()>);
+pub struct PthreadMutex {
+    owner_id: AtomicU64,
+}
 
 impl PthreadMutex {
     const fn new() -> Self {
-        Self(Mutex::new(()))
+        Self {
+            owner_id: AtomicU64::new(0),
+        }
     }
 
     fn lock(&self) -> LinuxResult {
-        let _guard = ManuallyDrop::new(self.0.lock());
+        axtask::futex_lock_pi(&self.owner_id);
         Ok(())
     }
 
     fn unlock(&self) -> LinuxResult {
-        unsafe { self.0.force_unlock() };
+        axtask::futex_unlock_pi(&self.owner_id);
         Ok(())
     }
 }
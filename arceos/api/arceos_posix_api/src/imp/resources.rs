@@ -1,4 +1,5 @@
 use crate::ctypes;
+use crate::utils::check_null_mut_ptr;
 use axerrno::LinuxError;
 use core::ffi::c_int;
 
@@ -49,3 +50,38 @@ pub unsafe fn sys_setrlimit(resource: c_int, rlimits: *mut crate::ctypes::rlimit
         Ok(0)
     })
 }
+
+/// Get resource usage
+///
+/// Only the CPU times and the numbers of context switches are reported.
+/// `RUSAGE_SELF` counts all threads, as there is only one process.
+pub unsafe fn sys_getrusage(who: c_int, usage: *mut ctypes::rusage) -> c_int {
+    debug!("sys_getrusage <= {} {:#x}", who, usage as usize);
+    syscall_body!(sys_getrusage, {
+        check_null_mut_ptr(usage)?;
+        let mut ru = ctypes::rusage::default();
+        if who == ctypes::RUSAGE_SELF as c_int || who == ctypes::RUSAGE_THREAD as c_int {
+            #[cfg(feature = "multitask")]
+            {
+                let stats = if who == ctypes::RUSAGE_SELF as c_int {
+                    axtask::total_sched_stats()
+                } else {
+                    axtask::current().sched_stats()
+                };
+                ru.ru_utime = stats.utime.into();
+                ru.ru_stime = stats.stime.into();
+                ru.ru_nvcsw = stats.voluntary_switches as _;
+                ru.ru_nivcsw = stats.involuntary_switches as _;
+            }
+            // The only task runs since booting.
+            #[cfg(not(feature = "multitask"))]
+            {
+                ru.ru_utime = axhal::time::monotonic_time().into();
+            }
+        } else if who != ctypes::RUSAGE_CHILDREN as c_int {
+            return Err(LinuxError::EINVAL);
+        }
+        unsafe { *usage = ru };
+        Ok(0)
+    })
+}
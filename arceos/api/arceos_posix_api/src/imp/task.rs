@@ -1,4 +1,12 @@
-use core::ffi::c_int;
+use core::ffi::{c_int, c_ulong};
+use core::ops::RangeInclusive;
+
+use axerrno::{LinuxError, LinuxResult};
+
+use crate::ctypes;
+use crate::utils::{check_null_mut_ptr, check_null_ptr};
+
+const BITS_PER_WORD: usize = c_ulong::BITS as usize;
 
 /// Relinquish the CPU, and switches to another task.
 ///
@@ -38,3 +46,293 @@ pub fn sys_exit(exit_code: c_int) -> ! {
     #[cfg(not(feature = "multitask"))]
     axhal::misc::terminate();
 }
+
+/// Returns the words of a `cpu_set_t` of `cpusetsize` bytes.
+unsafe fn cpu_set_words<'a>(
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> LinuxResult<&'a [c_ulong]> {
+    check_null_ptr(cpuset)?;
+    let len = cpusetsize / core::mem::size_of::<c_ulong>();
+    Ok(unsafe { core::slice::from_raw_parts(cpuset as *const c_ulong, len) })
+}
+
+fn cpu_isset(words: &[c_ulong], cpu_id: usize) -> bool {
+    words
+        .get(cpu_id / BITS_PER_WORD)
+        .is_some_and(|w| w & (1 << (cpu_id % BITS_PER_WORD)) != 0)
+}
+
+/// Fills a `cpu_set_t` of `cpusetsize` bytes with the CPUs that `contains`
+/// returns true for. It must be large enough for all CPUs.
+unsafe fn write_cpu_set(
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+    contains: impl Fn(usize) -> bool,
+) -> LinuxResult {
+    check_null_mut_ptr(cpuset)?;
+    let len = cpusetsize / core::mem::size_of::<c_ulong>();
+    if len * BITS_PER_WORD < axconfig::SMP {
+        return Err(LinuxError::EINVAL);
+    }
+    let words = unsafe { core::slice::from_raw_parts_mut(cpuset as *mut c_ulong, len) };
+    words.fill(0);
+    for cpu_id in (0..axconfig::SMP).filter(|&cpu_id| contains(cpu_id)) {
+        words[cpu_id / BITS_PER_WORD] |= 1 << (cpu_id % BITS_PER_WORD);
+    }
+    Ok(())
+}
+
+/// Sets the CPUs that `task` is allowed to run on from a `cpu_set_t`.
+#[cfg(feature = "multitask")]
+pub(crate) unsafe fn set_task_affinity(
+    task: &axtask::AxTaskRef,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> LinuxResult {
+    let words = unsafe { cpu_set_words(cpusetsize, cpuset)? };
+    let mut cpumask = axtask::CpuMask::new();
+    for cpu_id in 0..axconfig::SMP {
+        cpumask.set(cpu_id, cpu_isset(words, cpu_id));
+    }
+    if axtask::set_affinity(task, cpumask) {
+        Ok(())
+    } else {
+        Err(LinuxError::EINVAL)
+    }
+}
+
+/// Stores the CPUs that `task` is allowed to run on into a `cpu_set_t`.
+#[cfg(feature = "multitask")]
+pub(crate) unsafe fn get_task_affinity(
+    task: &axtask::AxTaskRef,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> LinuxResult {
+    let cpumask = axtask::get_affinity(task);
+    unsafe { write_cpu_set(cpusetsize, cpuset, |cpu_id| cpumask.get(cpu_id)) }
+}
+
+/// Finds the task of the given thread ID, 0 means the current thread.
+#[cfg(feature = "multitask")]
+fn task_by_pid(pid: ctypes::pid_t) -> LinuxResult<axtask::AxTaskRef> {
+    let curr = axtask::current();
+    if pid == 0 || pid as u64 == curr.id().as_u64() {
+        Ok(curr.as_task_ref().clone())
+    } else {
+        super::pthread::find_task(pid as u64).ok_or(LinuxError::ESRCH)
+    }
+}
+
+/// Sets the CPUs that the thread `pid` (or the current thread if `pid` is 0)
+/// is allowed to run on.
+///
+/// For single-threaded configuration (`multitask` feature is disabled), the
+/// mask must contain the current CPU.
+pub unsafe fn sys_sched_setaffinity(
+    pid: ctypes::pid_t,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_sched_setaffinity <= {} {}", pid, cpusetsize);
+    syscall_body!(sys_sched_setaffinity,
+        #[cfg(feature = "multitask")]
+        {
+            unsafe { set_task_affinity(&task_by_pid(pid)?, cpusetsize, cpuset)? };
+            Ok(0)
+        }
+        #[cfg(not(feature = "multitask"))]
+        {
+            if pid != 0 && pid != 2 {
+                return Err(LinuxError::ESRCH);
+            }
+            let words = unsafe { cpu_set_words(cpusetsize, cpuset)? };
+            if cpu_isset(words, axhal::cpu::this_cpu_id()) {
+                Ok(0)
+            } else {
+                Err(LinuxError::EINVAL)
+            }
+        }
+    )
+}
+
+/// Gets the CPUs that the thread `pid` (or the current thread if `pid` is 0)
+/// is allowed to run on.
+pub unsafe fn sys_sched_getaffinity(
+    pid: ctypes::pid_t,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> c_int {
+    debug!("sys_sched_getaffinity <= {} {}", pid, cpusetsize);
+    syscall_body!(sys_sched_getaffinity,
+        #[cfg(feature = "multitask")]
+        {
+            unsafe { get_task_affinity(&task_by_pid(pid)?, cpusetsize, cpuset)? };
+            Ok(0)
+        }
+        #[cfg(not(feature = "multitask"))]
+        {
+            if pid != 0 && pid != 2 {
+                return Err(LinuxError::ESRCH);
+            }
+            let this_cpu_id = axhal::cpu::this_cpu_id();
+            unsafe { write_cpu_set(cpusetsize, cpuset, |cpu_id| cpu_id == this_cpu_id)? };
+            Ok(0)
+        }
+    )
+}
+
+/// The highest real-time priority.
+#[cfg(feature = "multitask")]
+const MAX_RT_PRIO: c_int = axtask::MAX_RT_PRIO as c_int;
+/// The highest real-time priority, the same as `axtask::MAX_RT_PRIO` with
+/// the `multitask` feature.
+#[cfg(not(feature = "multitask"))]
+const MAX_RT_PRIO: c_int = 99;
+
+/// Returns the range of the priorities of `policy`.
+fn priority_range(policy: c_int) -> LinuxResult<RangeInclusive<c_int>> {
+    match policy as u32 {
+        ctypes::SCHED_OTHER => Ok(0..=0),
+        ctypes::SCHED_FIFO | ctypes::SCHED_RR => Ok(1..=MAX_RT_PRIO),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+/// Reads the priority in `param`, and checks that it is valid for `policy`.
+unsafe fn read_priority(policy: c_int, param: *const ctypes::sched_param) -> LinuxResult<c_int> {
+    check_null_ptr(param)?;
+    let priority = unsafe { (*param).sched_priority };
+    if priority_range(policy)?.contains(&priority) {
+        Ok(priority)
+    } else {
+        Err(LinuxError::EINVAL)
+    }
+}
+
+#[cfg(feature = "multitask")]
+fn to_sched_policy(policy: c_int) -> axtask::SchedPolicy {
+    match policy as u32 {
+        ctypes::SCHED_FIFO => axtask::SchedPolicy::Fifo,
+        ctypes::SCHED_RR => axtask::SchedPolicy::RoundRobin,
+        _ => axtask::SchedPolicy::Normal,
+    }
+}
+
+#[cfg(feature = "multitask")]
+fn from_sched_policy(policy: axtask::SchedPolicy) -> c_int {
+    let policy = match policy {
+        axtask::SchedPolicy::Normal => ctypes::SCHED_OTHER,
+        axtask::SchedPolicy::Fifo => ctypes::SCHED_FIFO,
+        axtask::SchedPolicy::RoundRobin => ctypes::SCHED_RR,
+    };
+    policy as c_int
+}
+
+/// Sets the scheduling policy and the priority of the thread `pid` (or the
+/// current thread if `pid` is 0).
+///
+/// `SCHED_FIFO` and `SCHED_RR` threads have priorities from 1 to 99, and
+/// always run before `SCHED_OTHER` threads, whose priority must be 0.
+pub unsafe fn sys_sched_setscheduler(
+    pid: ctypes::pid_t,
+    policy: c_int,
+    param: *const ctypes::sched_param,
+) -> c_int {
+    debug!("sys_sched_setscheduler <= {} {}", pid, policy);
+    syscall_body!(sys_sched_setscheduler,
+        #[cfg(feature = "multitask")]
+        {
+            let priority = unsafe { read_priority(policy, param)? };
+            let task = task_by_pid(pid)?;
+            axtask::set_scheduler(&task, to_sched_policy(policy), priority as u8);
+            Ok(0)
+        }
+        // The only task always runs, whatever its policy is.
+        #[cfg(not(feature = "multitask"))]
+        {
+            if pid != 0 && pid != 2 {
+                return Err(LinuxError::ESRCH);
+            }
+            unsafe { read_priority(policy, param)? };
+            Ok(0)
+        }
+    )
+}
+
+/// Gets the scheduling policy of the thread `pid` (or the current thread if
+/// `pid` is 0).
+pub fn sys_sched_getscheduler(pid: ctypes::pid_t) -> c_int {
+    debug!("sys_sched_getscheduler <= {}", pid);
+    syscall_body!(sys_sched_getscheduler,
+        #[cfg(feature = "multitask")]
+        {
+            let (policy, _) = axtask::get_scheduler(&task_by_pid(pid)?);
+            Ok(from_sched_policy(policy))
+        }
+        #[cfg(not(feature = "multitask"))]
+        {
+            if pid != 0 && pid != 2 {
+                return Err(LinuxError::ESRCH);
+            }
+            Ok(ctypes::SCHED_OTHER as c_int)
+        }
+    )
+}
+
+/// Sets the priority of the thread `pid` (or the current thread if `pid` is
+/// 0), keeping its scheduling policy.
+pub unsafe fn sys_sched_setparam(pid: ctypes::pid_t, param: *const ctypes::sched_param) -> c_int {
+    debug!("sys_sched_setparam <= {}", pid);
+    syscall_body!(sys_sched_setparam,
+        #[cfg(feature = "multitask")]
+        {
+            let task = task_by_pid(pid)?;
+            let (policy, _) = axtask::get_scheduler(&task);
+            let priority = unsafe { read_priority(from_sched_policy(policy), param)? };
+            axtask::set_scheduler(&task, policy, priority as u8);
+            Ok(0)
+        }
+        #[cfg(not(feature = "multitask"))]
+        {
+            if pid != 0 && pid != 2 {
+                return Err(LinuxError::ESRCH);
+            }
+            unsafe { read_priority(ctypes::SCHED_OTHER as c_int, param)? };
+            Ok(0)
+        }
+    )
+}
+
+/// Gets the priority of the thread `pid` (or the current thread if `pid` is
+/// 0), not including the priority it inherits from mutexes.
+pub unsafe fn sys_sched_getparam(pid: ctypes::pid_t, param: *mut ctypes::sched_param) -> c_int {
+    debug!("sys_sched_getparam <= {}", pid);
+    syscall_body!(sys_sched_getparam, {
+        check_null_mut_ptr(param)?;
+        #[cfg(feature = "multitask")]
+        let (_, priority) = axtask::get_scheduler(&task_by_pid(pid)?);
+        #[cfg(not(feature = "multitask"))]
+        let priority = if pid == 0 || pid == 2 {
+            0
+        } else {
+            return Err(LinuxError::ESRCH);
+        };
+        unsafe { (*param).sched_priority = priority as c_int };
+        Ok(0)
+    })
+}
+
+/// Returns the highest priority of `policy`.
+pub fn sys_sched_get_priority_max(policy: c_int) -> c_int {
+    syscall_body!(sys_sched_get_priority_max, {
+        Ok(*priority_range(policy)?.end())
+    })
+}
+
+/// Returns the lowest priority of `policy`.
+pub fn sys_sched_get_priority_min(policy: c_int) -> c_int {
+    syscall_body!(sys_sched_get_priority_min, {
+        Ok(*priority_range(policy)?.start())
+    })
+}
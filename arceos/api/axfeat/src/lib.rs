@@ -7,18 +7,24 @@
 //!     - `fp_simd`: Enable floating point and SIMD support.
 //! - Interrupts:
 //!     - `irq`: Enable interrupt handling support.
+//!     - `tickless`: Stop the periodic timer tick on idle CPUs.
 //! - Memory
 //!     - `alloc`: Enable dynamic memory allocation.
 //!     - `alloc-tlsf`: Use the TLSF allocator.
 //!     - `alloc-slab`: Use the slab allocator.
 //!     - `alloc-buddy`: Use the buddy system allocator.
+//!     - `heap-profile`: Record per-call-site heap usage statistics and allocation traces.
+//!     - `debug-heap`: Catch heap overflows and use-after-free.
+//!     - `alt_alloc`: Use the bump allocator as the global allocator.
+//!     - `myalloc`: Allow users to define their custom global allocator.
 //!     - `paging`: Enable page table manipulation.
 //!     - `tls`: Enable thread-local storage.
 //! - Task management
 //!     - `multitask`: Enable multi-threading support.
-//!     - `sched_fifo`: Use the FIFO cooperative scheduler.
-//!     - `sched_rr`: Use the Round-robin preemptive scheduler.
-//!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler.
+//!     - `sched_fifo`: Use the FIFO cooperative scheduler by default.
+//!     - `sched_rr`: Use the Round-robin preemptive scheduler by default.
+//!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler by default.
+//!     - `stack-usage`: Record the maximum kernel stack usage of each task.
 //! - Upperlayer stacks (fs, net, display)
 //!     - `fs`: Enable file system support.
 //!     - `myfs`: Allow users to define their custom filesystems to override the default.
@@ -24,6 +24,8 @@ const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("help", do_help),
     ("ls", do_ls),
     ("mkdir", do_mkdir),
+    #[cfg(feature = "ps")]
+    ("ps", do_ps),
     ("pwd", do_pwd),
     ("rm", do_rm),
     ("uname", do_uname),
@@ -238,6 +240,57 @@ fn do_cd(mut args: &str) {
     }
 }
 
+#[cfg(feature = "ps")]
+fn do_ps(_args: &str) {
+    fn show_task(tid: u64) -> io::Result<()> {
+        let status = fs::read_to_string(&format!("/proc/{}/status", tid))?;
+        let field = |key: &str| {
+            status
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(k, _)| *k == key)
+                .map_or("-", |(_, v)| v.trim())
+        };
+        let state = field("State").split_whitespace().next().unwrap_or("-");
+        let time = field("Runtime").trim_end_matches(" us");
+        println!(
+            "{:>5} {:>5} {:>3} {:>4} {:>10} {:>7} {}",
+            tid,
+            state,
+            field("Cpu"),
+            field("Priority"),
+            time,
+            field("StackHighWater"),
+            field("Name"),
+        );
+        Ok(())
+    }
+
+    let mut tids = match fs::read_dir("/proc") {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name();
+                path_to_str!(name).parse::<u64>().ok()
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            print_err!("ps", "/proc", e);
+            return;
+        }
+    };
+    tids.sort();
+
+    println!(
+        "{:>5} {:>5} {:>3} {:>4} {:>10} {:>7} NAME",
+        "PID", "STATE", "CPU", "PRIO", "TIME(us)", "STACK"
+    );
+    for tid in tids {
+        // Skip the tasks that have exited since the directory was read.
+        let _ = show_task(tid);
+    }
+}
+
 fn do_pwd(_args: &str) {
     let pwd = std::env::current_dir().unwrap();
     println!("{}", path_to_str!(pwd));
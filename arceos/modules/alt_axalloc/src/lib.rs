@@ -1,121 +1,229 @@
-#![no_std]
+//! The bump allocator backend of [ArceOS](https://github.com/arceos-org/arceos).
+//!
+//! Linking this crate registers a two-stage allocator as the custom allocator
+//! of `axalloc` through [`MyAllocatorIf`]. It is only used if the `myalloc`
+//! feature of `axalloc` is enabled, which is done by the `alt_alloc` feature
+//! of `axruntime`, rather than by this crate.
+//!
+//! The first region, given by `axalloc::global_init`, is managed by the bump
+//! [`EarlyAllocator`], which serves the allocations during boot. The regions
+//! added later are managed by a buddy allocator, which serves all the
+//! allocations it can. Once the boot is done, [`release_free_region`] hands
+//! the unused middle region of the early allocator over to the buddy
+//! allocator, so that it is not stranded.
+
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
-extern crate alloc;
 
-use allocator::{AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
+use allocator::{AllocError, AllocResult, BaseAllocator, BuddyByteAllocator};
+use allocator::{ByteAllocator, PageAllocator};
+use axalloc::MyAllocatorIf;
 use bump_allocator::EarlyAllocator;
-use core::alloc::{GlobalAlloc, Layout};
+use core::alloc::Layout;
+use core::ops::Range;
 use core::ptr::NonNull;
 use kspin::SpinNoIrq;
 
 const PAGE_SIZE: usize = 0x1000;
 
-/// The global allocator used by ArceOS.
-pub struct GlobalAllocator {
-    inner: SpinNoIrq<EarlyAllocator<PAGE_SIZE>>,
+/// The maximum number of regions that the buddy allocator manages.
+const MAX_REGIONS: usize = 16;
+
+struct TwoStageAllocator {
+    early: EarlyAllocator<PAGE_SIZE>,
+    buddy: BuddyByteAllocator,
+    /// The regions added to the buddy allocator.
+    regions: [Range<usize>; MAX_REGIONS],
+    num_regions: usize,
 }
 
-impl GlobalAllocator {
-    /// Creates an empty [`GlobalAllocator`].
-    pub const fn new() -> Self {
+impl TwoStageAllocator {
+    const fn new() -> Self {
         Self {
-            inner: SpinNoIrq::new(EarlyAllocator::new()),
+            early: EarlyAllocator::new(),
+            buddy: BuddyByteAllocator::new(),
+            regions: [const { 0..0 }; MAX_REGIONS],
+            num_regions: 0,
         }
     }
 
-    /// Returns the name of the allocator.
-    pub const fn name(&self) -> &'static str {
-        "early"
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        if self.num_regions == MAX_REGIONS {
+            return Err(AllocError::NoMemory);
+        }
+        self.buddy.add_memory(start, size)?;
+        self.regions[self.num_regions] = start..start + size;
+        self.num_regions += 1;
+        Ok(())
+    }
+
+    /// Whether `addr` was allocated by the buddy allocator.
+    fn in_buddy(&self, addr: usize) -> bool {
+        self.regions[..self.num_regions]
+            .iter()
+            .any(|r| r.contains(&addr))
+    }
+
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        self.buddy
+            .alloc(layout)
+            .or_else(|_| self.early.alloc(layout))
     }
 
-    /// Initializes the allocator with the given region.
-    pub fn init(&self, start_vaddr: usize, size: usize) {
-        self.inner.lock().init(start_vaddr, size);
+    fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+        if self.in_buddy(pos.as_ptr() as usize) {
+            self.buddy.dealloc(pos, layout)
+        } else {
+            self.early.dealloc(pos, layout)
+        }
     }
 
-    /// Add the given region to the allocator.
-    pub fn add_memory(&self, _start_vaddr: usize, _size: usize) -> AllocResult {
-        unimplemented!()
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if align_pow2 % PAGE_SIZE != 0 || !align_pow2.is_power_of_two() {
+            return Err(AllocError::InvalidParam);
+        }
+        let size = num_pages
+            .checked_mul(PAGE_SIZE)
+            .ok_or(AllocError::NoMemory)?;
+        // Blocks of the buddy allocator are aligned to their sizes rounded up
+        // to powers of two, so they are aligned as well, and can be freed
+        // without knowing the alignment.
+        if align_pow2 <= size.next_power_of_two() {
+            let layout = Layout::from_size_align(size, PAGE_SIZE).unwrap();
+            if let Ok(ptr) = self.buddy.alloc(layout) {
+                return Ok(ptr.as_ptr() as usize);
+            }
+        }
+        self.early.alloc_pages(num_pages, align_pow2)
+    }
+
+    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        if self.in_buddy(pos) {
+            let layout = Layout::from_size_align(num_pages * PAGE_SIZE, PAGE_SIZE).unwrap();
+            self.buddy
+                .dealloc(NonNull::new(pos as *mut u8).unwrap(), layout)
+        } else {
+            self.early.dealloc_pages(pos, num_pages)
+        }
     }
+}
 
-    /// Allocate arbitrary number of bytes. Returns the left bound of the
-    /// allocated region.
-    pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
-        self.inner.lock().alloc(layout)
+static ALLOCATOR: SpinNoIrq<TwoStageAllocator> = SpinNoIrq::new(TwoStageAllocator::new());
+
+/// Hands the unused middle region of the early allocator over to the global
+/// allocator of `axalloc`, i.e., to the buddy allocator.
+///
+/// The early allocator keeps serving from the memory below the region, and
+/// freeing what it has allocated. It should be called once the boot-time
+/// allocations are done.
+pub fn release_free_region() -> AllocResult {
+    let region = ALLOCATOR.lock().early.take_free_region();
+    match region {
+        Some((start, size)) => {
+            debug!(
+                "release free region of early allocator: [{:#x}, {:#x})",
+                start,
+                start + size
+            );
+            axalloc::global_add_memory(start, size)
+        }
+        None => Ok(()),
     }
+}
 
-    /// Gives back the allocated region to the byte allocator.
-    pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
-        self.inner.lock().dealloc(pos, layout)
+struct MyAllocatorIfImpl;
+
+#[crate_interface::impl_interface]
+impl MyAllocatorIf for MyAllocatorIfImpl {
+    fn name() -> &'static str {
+        "early"
     }
 
-    /// Allocates contiguous pages.
-    pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
-        self.inner.lock().alloc_pages(num_pages, align_pow2)
+    fn init(start_vaddr: usize, size: usize) {
+        ALLOCATOR.lock().early.init(start_vaddr, size);
     }
 
-    /// Gives back the allocated pages starts from `pos` to the page allocator.
-    /// [`alloc_pages`]: GlobalAllocator::alloc_pages
-    pub fn dealloc_pages(&self, pos: usize, num_pages: usize) {
-        self.inner.lock().dealloc_pages(pos, num_pages)
+    fn add_memory(start_vaddr: usize, size: usize) -> AllocResult {
+        ALLOCATOR.lock().add_memory(start_vaddr, size)
     }
 
-    /// Returns the number of allocated bytes in the byte allocator.
-    pub fn used_bytes(&self) -> usize {
-        self.inner.lock().used_bytes()
+    fn alloc(layout: Layout) -> AllocResult<NonNull<u8>> {
+        ALLOCATOR.lock().alloc(layout)
     }
 
-    /// Returns the number of available bytes in the byte allocator.
-    pub fn available_bytes(&self) -> usize {
-        self.inner.lock().available_bytes()
+    fn dealloc(pos: NonNull<u8>, layout: Layout) {
+        ALLOCATOR.lock().dealloc(pos, layout)
     }
 
-    /// Returns the number of allocated pages in the page allocator.
-    pub fn used_pages(&self) -> usize {
-        self.inner.lock().used_pages()
+    fn alloc_pages(num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        ALLOCATOR.lock().alloc_pages(num_pages, align_pow2)
     }
 
-    /// Returns the number of available pages in the page allocator.
-    pub fn available_pages(&self) -> usize {
-        self.inner.lock().available_pages()
+    fn dealloc_pages(pos: usize, num_pages: usize) {
+        ALLOCATOR.lock().dealloc_pages(pos, num_pages)
     }
-}
 
-unsafe impl GlobalAlloc for GlobalAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if let Ok(ptr) = GlobalAllocator::alloc(self, layout) {
-            ptr.as_ptr()
-        } else {
-            alloc::alloc::handle_alloc_error(layout)
-        }
+    fn used_bytes() -> usize {
+        let allocator = ALLOCATOR.lock();
+        allocator.early.used_bytes() + allocator.buddy.used_bytes()
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        GlobalAllocator::dealloc(self, NonNull::new(ptr).expect("dealloc null ptr"), layout)
+    fn available_bytes() -> usize {
+        let allocator = ALLOCATOR.lock();
+        allocator.early.available_bytes() + allocator.buddy.available_bytes()
     }
-}
 
-#[cfg_attr(all(target_os = "none", not(test)), global_allocator)]
-static GLOBAL_ALLOCATOR: GlobalAllocator = GlobalAllocator::new();
+    // The pages allocated by the buddy allocator are counted in bytes.
 
-/// Returns the reference to the global allocator.
-pub fn global_allocator() -> &'static GlobalAllocator {
-    &GLOBAL_ALLOCATOR
-}
+    fn used_pages() -> usize {
+        ALLOCATOR.lock().early.used_pages()
+    }
 
-/// Initializes the global allocator with the given memory region.
-pub fn global_init(start_vaddr: usize, size: usize) {
-    debug!(
-        "initialize global allocator at: [{:#x}, {:#x})",
-        start_vaddr,
-        start_vaddr + size
-    );
-    GLOBAL_ALLOCATOR.init(start_vaddr, size);
+    fn available_pages() -> usize {
+        let allocator = ALLOCATOR.lock();
+        allocator.early.available_pages() + allocator.buddy.available_bytes() / PAGE_SIZE
+    }
 }
 
-/// Add the given memory region to the global allocator.
-pub fn global_add_memory(_start_vaddr: usize, _size: usize) -> AllocResult {
-    unimplemented!()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 0x10_0000;
+
+    #[test]
+    fn test_release_free_region() {
+        let layout = Layout::from_size_align(SIZE, SIZE).unwrap();
+        let base = unsafe { std::alloc::alloc(layout) } as usize;
+        axalloc::global_init(base, SIZE);
+        let global = axalloc::global_allocator();
+
+        // Boot-time allocations come from both ends of the early allocator.
+        let bytes = global.alloc(Layout::new::<u64>()).unwrap();
+        assert_eq!(bytes.as_ptr() as usize, base);
+        let page = global.alloc_pages(1, PAGE_SIZE).unwrap();
+        assert_eq!(page, base + SIZE - PAGE_SIZE);
+
+        release_free_region().unwrap();
+        let free = base + PAGE_SIZE..base + SIZE - PAGE_SIZE;
+        assert_eq!(ALLOCATOR.lock().regions[0], free);
+        assert_eq!(global.used_pages(), 1);
+        assert_eq!(release_free_region(), Ok(()));
+
+        // Now served by the buddy allocator from the released region.
+        let pages = global.alloc_pages(4, PAGE_SIZE).unwrap();
+        assert!(free.contains(&pages));
+        let ptr = global.alloc(Layout::new::<[u8; 64]>()).unwrap();
+        assert!(free.contains(&(ptr.as_ptr() as usize)));
+        let used = global.used_bytes();
+        global.dealloc_pages(pages, 4);
+        global.dealloc(ptr, Layout::new::<[u8; 64]>());
+        assert!(global.used_bytes() < used);
+
+        // The early allocator still frees its own allocations.
+        global.dealloc(bytes, Layout::new::<u64>());
+        assert_eq!(ALLOCATOR.lock().early.used_bytes(), 0);
+    }
 }
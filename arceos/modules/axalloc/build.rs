@@ -0,0 +1,41 @@
+/// Returns whether the crate is built with `-C force-frame-pointers`.
+fn frame_pointers_forced() -> bool {
+    let rustflags = std::env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+    let mut codegen_opts = Vec::new();
+    let mut flags = rustflags.split('\x1f');
+    while let Some(flag) = flags.next() {
+        if flag == "-C" || flag == "--codegen" {
+            codegen_opts.extend(flags.next());
+        } else if let Some(opt) = flag.strip_prefix("-C") {
+            codegen_opts.push(opt);
+        }
+    }
+    // The last one wins.
+    codegen_opts
+        .iter()
+        .rev()
+        .find_map(|opt| match opt.split_once('=') {
+            Some(("force-frame-pointers", value)) => {
+                Some(matches!(value, "yes" | "y" | "on" | "true"))
+            }
+            None if *opt == "force-frame-pointers" => Some(true),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+fn main() {
+    // Call sites of allocations are found by walking the frame pointers,
+    // which is only safe if all code is built with them.
+    let forced = frame_pointers_forced();
+    if forced {
+        println!("cargo:rustc-cfg=frame_pointers");
+    } else if std::env::var_os("CARGO_FEATURE_HEAP_PROFILE").is_some() {
+        println!(
+            "cargo:warning=`heap-profile` without `-C force-frame-pointers=yes`, \
+             call sites will not be recorded"
+        );
+    }
+    println!("cargo::rustc-check-cfg=cfg(frame_pointers)");
+    println!("cargo:rerun-if-env-changed=CARGO_ENCODED_RUSTFLAGS");
+}
@@ -0,0 +1,130 @@
+//! Finding the call sites of allocations by walking the frame pointers.
+//!
+//! Call sites can only be resolved if the kernel is built with
+//! `-C force-frame-pointers=yes`, which is detected by the build script.
+//! Otherwise the frame pointer registers may hold arbitrary values, so no
+//! call site is reported.
+//!
+//! The call site is the first return address outside the allocation path,
+//! i.e., outside this crate, the `alloc` crate and the allocator shims
+//! (`__rust_alloc`, etc). The linker script of `axhal` places their text in
+//! `[_salloc_text, _ealloc_text)`, so the result does not depend on how the
+//! frames on the path are inlined.
+
+/// Maximum number of frames to walk before giving up.
+#[cfg(any(frame_pointers, test))]
+const MAX_FRAMES: usize = 32;
+
+#[cfg(frame_pointers)]
+#[inline(always)]
+fn frame_pointer() -> usize {
+    let fp: usize;
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            unsafe { core::arch::asm!("mov {}, rbp", out(reg) fp) };
+        } else if #[cfg(target_arch = "aarch64")] {
+            unsafe { core::arch::asm!("mov {}, x29", out(reg) fp) };
+        } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+            unsafe { core::arch::asm!("mv {}, s0", out(reg) fp) };
+        } else {
+            fp = 0;
+        }
+    }
+    fp
+}
+
+/// Returns `(return address, previous frame pointer)` saved in the frame.
+///
+/// # Safety
+///
+/// `fp` must be a valid frame pointer.
+#[cfg(any(frame_pointers, test))]
+unsafe fn unwind_frame(fp: usize) -> (usize, usize) {
+    let fp = fp as *const usize;
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
+            (fp.sub(1).read(), fp.sub(2).read())
+        } else {
+            (fp.add(1).read(), fp.read())
+        }
+    }
+}
+
+/// Walks the frames starting from `fp`, and returns the first return address
+/// that is not in the allocation path, or `0` if it cannot be found.
+///
+/// # Safety
+///
+/// `fp` must be a valid frame pointer, and so must be the ones saved in the
+/// frames until the returned one.
+#[cfg(any(frame_pointers, test))]
+unsafe fn find_caller(mut fp: usize, in_alloc_path: impl Fn(usize) -> bool) -> usize {
+    for _ in 0..MAX_FRAMES {
+        if fp == 0 || fp % core::mem::align_of::<usize>() != 0 {
+            return 0;
+        }
+        let (ra, prev_fp) = unwind_frame(fp);
+        if !in_alloc_path(ra) {
+            return ra;
+        }
+        fp = prev_fp;
+    }
+    0
+}
+
+/// Returns the return address of the allocation call site, or `0` if it
+/// cannot be found.
+#[inline(never)]
+pub(crate) fn caller_addr() -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(frame_pointers)] {
+            extern "C" {
+                fn _salloc_text();
+                fn _ealloc_text();
+            }
+            let alloc_text = _salloc_text as usize.._ealloc_text as usize;
+            unsafe { find_caller(frame_pointer(), |ra| alloc_text.contains(&ra)) }
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALLOC_TEXT: core::ops::Range<usize> = 0x1000..0x2000;
+
+    /// Builds a frame saving `prev_fp` and `ra`, and returns its frame pointer.
+    fn frame(slots: &mut [usize; 2], prev_fp: usize, ra: usize) -> usize {
+        *slots = [prev_fp, ra];
+        let base = slots.as_ptr() as usize;
+        if cfg!(any(target_arch = "riscv32", target_arch = "riscv64")) {
+            base + 2 * core::mem::size_of::<usize>()
+        } else {
+            base
+        }
+    }
+
+    #[test]
+    fn test_find_caller() {
+        let (mut user, mut liballoc, mut shim, mut axalloc) = ([0; 2], [0; 2], [0; 2], [0; 2]);
+        let user_fp = frame(&mut user, 0, 0x8000);
+        let liballoc_fp = frame(&mut liballoc, user_fp, 0x5010);
+        let shim_fp = frame(&mut shim, liballoc_fp, 0x1f00);
+        let axalloc_fp = frame(&mut axalloc, shim_fp, 0x1010);
+        let in_alloc_path = |ra| ALLOC_TEXT.contains(&ra);
+
+        // The frames of the allocator and `alloc` are skipped, however deep.
+        let caller = unsafe { find_caller(axalloc_fp, in_alloc_path) };
+        assert_eq!(caller, 0x5010);
+        assert_eq!(unsafe { find_caller(liballoc_fp, in_alloc_path) }, 0x5010);
+
+        // The walk stops at the end of the chain.
+        frame(&mut liballoc, user_fp, 0x1020);
+        frame(&mut user, 0, 0x1030);
+        assert_eq!(unsafe { find_caller(axalloc_fp, in_alloc_path) }, 0);
+        assert_eq!(unsafe { find_caller(axalloc_fp + 1, in_alloc_path) }, 0);
+    }
+}
@@ -0,0 +1,466 @@
+//! Guarded allocations for debugging (enabled by the `debug-heap` feature).
+//!
+//! Every allocation made through the global allocator is laid out as:
+//!
+//! ```text
+//! | header | front red zone | data | back red zone |
+//! ```
+//!
+//! New data is filled with `0xcd` to expose reads of uninitialized memory,
+//! and the red zones are filled with `0xfd`. On deallocation, the header and
+//! the red zones are verified, then the data is poisoned with `0xdd` and kept
+//! in a small quarantine for a while, so that writes after free can be
+//! detected when it is evicted.
+//!
+//! With the `paging` feature, large allocations can be placed at the end of
+//! their own pages, followed by an unmapped guard page, so that overflows
+//! fault immediately (see [`set_guard_pages`]).
+//!
+//! Any violation results in a panic reporting the layout and the call sites
+//! of the allocation, which are found by walking the frame pointers.
+
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::NonNull;
+
+use allocator::{AllocError, AllocResult};
+use kspin::SpinNoIrq;
+
+use crate::caller::caller_addr;
+use crate::GlobalAllocator;
+
+/// Byte pattern of newly allocated memory.
+const ALLOC_BYTE: u8 = 0xcd;
+/// Byte pattern of freed memory.
+const FREE_BYTE: u8 = 0xdd;
+/// Byte pattern of red zones.
+const REDZONE_BYTE: u8 = 0xfd;
+
+/// Minimum size of the red zone on each side of the data.
+const REDZONE_SIZE: usize = 16;
+/// Number of freed allocations kept in the quarantine.
+const QUARANTINE_LEN: usize = 64;
+/// Freed allocations larger than this are released immediately.
+const MAX_QUARANTINE_SIZE: usize = 4096;
+
+const HEADER_MAGIC: usize = 0xa110_c8ed;
+const HEADER_FREED: usize = 0xdead_f4ee;
+const HEADER_SIZE: usize = size_of::<Header>();
+
+/// Stored at the beginning of each allocation.
+#[repr(C)]
+struct Header {
+    magic: usize,
+    size: usize,
+    caller: usize,
+}
+
+/// A freed allocation in the quarantine.
+#[derive(Clone, Copy)]
+struct Freed {
+    ptr: usize,
+    layout: Layout,
+    alloc_site: usize,
+    free_site: usize,
+}
+
+struct Quarantine {
+    entries: [Option<Freed>; QUARANTINE_LEN],
+    next: usize,
+}
+
+impl Quarantine {
+    const fn new() -> Self {
+        Self {
+            entries: [None; QUARANTINE_LEN],
+            next: 0,
+        }
+    }
+
+    fn find(&self, ptr: usize) -> Option<Freed> {
+        self.entries.iter().flatten().find(|f| f.ptr == ptr).copied()
+    }
+
+    /// Puts `freed` into the quarantine, returns the evicted entry if it is
+    /// full.
+    fn push(&mut self, freed: Freed) -> Option<Freed> {
+        let evicted = self.entries[self.next].replace(freed);
+        self.next = (self.next + 1) % QUARANTINE_LEN;
+        evicted
+    }
+}
+
+static QUARANTINE: SpinNoIrq<Quarantine> = SpinNoIrq::new(Quarantine::new());
+
+/// Returns the offset of the data from the beginning of the allocation.
+fn data_offset(layout: &Layout) -> usize {
+    (HEADER_SIZE + REDZONE_SIZE).next_multiple_of(layout.align())
+}
+
+/// Returns the layout of the whole allocation, including the header and the
+/// red zones.
+fn outer_layout(layout: &Layout) -> AllocResult<Layout> {
+    let size = data_offset(layout)
+        .checked_add(layout.size())
+        .and_then(|size| size.checked_add(REDZONE_SIZE))
+        .ok_or(AllocError::InvalidParam)?;
+    let align = layout.align().max(align_of::<Header>());
+    Layout::from_size_align(size, align).map_err(|_| AllocError::InvalidParam)
+}
+
+fn fill(start: usize, len: usize, byte: u8) {
+    unsafe { core::ptr::write_bytes(start as *mut u8, byte, len) };
+}
+
+/// Returns the offset of the first byte in the range that is not `byte`.
+fn find_mismatch(start: usize, len: usize, byte: u8) -> Option<usize> {
+    let bytes = unsafe { core::slice::from_raw_parts(start as *const u8, len) };
+    bytes.iter().position(|&b| b != byte)
+}
+
+fn violation(
+    what: &str,
+    addr: usize,
+    ptr: usize,
+    layout: Layout,
+    alloc_site: usize,
+    free_site: usize,
+) -> ! {
+    panic!(
+        "debug-heap: {} at {:#x} (allocation {:#x}, {:?}), allocated at {:#x}, freed at {:#x}",
+        what, addr, ptr, layout, alloc_site, free_site
+    );
+}
+
+/// Allocates memory through the global allocator with red zones.
+pub(crate) fn alloc(allocator: &GlobalAllocator, layout: Layout) -> AllocResult<NonNull<u8>> {
+    let caller = caller_addr();
+    #[cfg(feature = "paging")]
+    if let Some(ptr) = guarded::alloc(allocator, layout, caller) {
+        return Ok(ptr);
+    }
+
+    let offset = data_offset(&layout);
+    let outer = outer_layout(&layout)?;
+    let base = allocator.alloc(outer)?.as_ptr() as usize;
+    let ptr = base + offset;
+    let header = Header {
+        magic: HEADER_MAGIC,
+        size: layout.size(),
+        caller,
+    };
+    unsafe { (base as *mut Header).write(header) };
+    fill(base + HEADER_SIZE, offset - HEADER_SIZE, REDZONE_BYTE);
+    fill(ptr, layout.size(), ALLOC_BYTE);
+    fill(
+        ptr + layout.size(),
+        outer.size() - offset - layout.size(),
+        REDZONE_BYTE,
+    );
+    Ok(unsafe { NonNull::new_unchecked(ptr as *mut u8) })
+}
+
+/// Verifies and poisons the memory allocated by [`alloc`], then puts it into
+/// the quarantine.
+pub(crate) fn dealloc(allocator: &GlobalAllocator, pos: NonNull<u8>, layout: Layout) {
+    let caller = caller_addr();
+    let ptr = pos.as_ptr() as usize;
+    #[cfg(feature = "paging")]
+    if guarded::dealloc(allocator, ptr, layout, caller) {
+        return;
+    }
+
+    let freed = QUARANTINE.lock().find(ptr);
+    if let Some(freed) = freed {
+        violation(
+            "double free",
+            ptr,
+            ptr,
+            layout,
+            freed.alloc_site,
+            freed.free_site,
+        );
+    }
+    let alloc_site = check(ptr, layout, caller);
+    fill(ptr, layout.size(), FREE_BYTE);
+
+    let freed = Freed {
+        ptr,
+        layout,
+        alloc_site,
+        free_site: caller,
+    };
+    let evicted = if layout.size() > MAX_QUARANTINE_SIZE {
+        Some(freed)
+    } else {
+        QUARANTINE.lock().push(freed)
+    };
+    if let Some(evicted) = evicted {
+        release(allocator, evicted);
+    }
+}
+
+/// Verifies the header and the red zones, returns the allocation site.
+fn check(ptr: usize, layout: Layout, free_site: usize) -> usize {
+    let offset = data_offset(&layout);
+    let base = ptr - offset;
+    let header = unsafe { &*(base as *const Header) };
+    if header.magic != HEADER_MAGIC {
+        let what = if header.magic == HEADER_FREED {
+            "double free"
+        } else {
+            "invalid free or corrupted header"
+        };
+        violation(what, base, ptr, layout, 0, free_site);
+    }
+    let alloc_site = header.caller;
+    if header.size != layout.size() {
+        violation(
+            "deallocation with a wrong size",
+            ptr,
+            ptr,
+            layout,
+            alloc_site,
+            free_site,
+        );
+    }
+    let front = base + HEADER_SIZE;
+    if let Some(off) = find_mismatch(front, offset - HEADER_SIZE, REDZONE_BYTE) {
+        violation(
+            "heap underflow",
+            front + off,
+            ptr,
+            layout,
+            alloc_site,
+            free_site,
+        );
+    }
+    let back = ptr + layout.size();
+    if let Some(off) = find_mismatch(back, REDZONE_SIZE, REDZONE_BYTE) {
+        violation(
+            "heap overflow",
+            back + off,
+            ptr,
+            layout,
+            alloc_site,
+            free_site,
+        );
+    }
+    alloc_site
+}
+
+/// Verifies the poisoned memory leaving the quarantine, and gives it back to
+/// the global allocator.
+fn release(allocator: &GlobalAllocator, freed: Freed) {
+    let Freed {
+        ptr,
+        layout,
+        alloc_site,
+        free_site,
+    } = freed;
+    if let Some(off) = find_mismatch(ptr, layout.size(), FREE_BYTE) {
+        violation(
+            "use after free",
+            ptr + off,
+            ptr,
+            layout,
+            alloc_site,
+            free_site,
+        );
+    }
+    check(ptr, layout, free_site);
+
+    let base = ptr - data_offset(&layout);
+    unsafe { (*(base as *mut Header)).magic = HEADER_FREED };
+    let outer = outer_layout(&layout).unwrap();
+    allocator.dealloc(unsafe { NonNull::new_unchecked(base as *mut u8) }, outer);
+}
+
+#[cfg(feature = "paging")]
+pub use self::guarded::{report_guard_page_fault, set_guard_pages};
+
+#[cfg(feature = "paging")]
+mod guarded {
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use kspin::SpinNoIrq;
+
+    use super::{fill, find_mismatch, violation, ALLOC_BYTE, REDZONE_BYTE};
+    use crate::{GlobalAllocator, PAGE_SIZE};
+
+    /// Maximum number of allocations with guard pages at the same time.
+    /// Further allocations only get red zones.
+    const MAX_GUARDED: usize = 64;
+
+    #[derive(Clone, Copy)]
+    struct GuardedAlloc {
+        ptr: usize,
+        base: usize,
+        num_pages: usize,
+        layout: Layout,
+        caller: usize,
+    }
+
+    impl GuardedAlloc {
+        fn guard_page(&self) -> usize {
+            self.base + (self.num_pages - 1) * PAGE_SIZE
+        }
+    }
+
+    static GUARD_PAGES: AtomicBool = AtomicBool::new(false);
+    static GUARDED: SpinNoIrq<[Option<GuardedAlloc>; MAX_GUARDED]> =
+        SpinNoIrq::new([None; MAX_GUARDED]);
+
+    fn set_guard_page(vaddr: usize, guard: bool) -> bool {
+        crate_interface::call_interface!(crate::GuardPageIf::set_guard_page(vaddr, guard))
+    }
+
+    /// Places allocations of at least one page at the end of their own pages,
+    /// followed by an unmapped guard page, if guard pages are enabled.
+    pub(super) fn alloc(
+        allocator: &GlobalAllocator,
+        layout: Layout,
+        caller: usize,
+    ) -> Option<NonNull<u8>> {
+        if !GUARD_PAGES.load(Ordering::Relaxed)
+            || layout.size() < PAGE_SIZE
+            || layout.align() > PAGE_SIZE
+        {
+            return None;
+        }
+        let data_pages = layout.size().div_ceil(PAGE_SIZE);
+        let num_pages = data_pages + 1;
+        let base = allocator.alloc_pages(num_pages, PAGE_SIZE).ok()?;
+        let guard = base + data_pages * PAGE_SIZE;
+        let ptr = (guard - layout.size()) & !(layout.align() - 1);
+        let alloc = GuardedAlloc {
+            ptr,
+            base,
+            num_pages,
+            layout,
+            caller,
+        };
+
+        let slot = {
+            let mut guarded = GUARDED.lock();
+            let slot = guarded.iter().position(|g| g.is_none());
+            if let Some(i) = slot {
+                guarded[i] = Some(alloc);
+            }
+            slot
+        };
+        let Some(slot) = slot else {
+            allocator.dealloc_pages(base, num_pages);
+            return None;
+        };
+        // do not hold the lock while modifying the page table.
+        if !set_guard_page(guard, true) {
+            GUARDED.lock()[slot] = None;
+            allocator.dealloc_pages(base, num_pages);
+            return None;
+        }
+
+        fill(base, ptr - base, REDZONE_BYTE);
+        fill(ptr, layout.size(), ALLOC_BYTE);
+        fill(ptr + layout.size(), guard - ptr - layout.size(), REDZONE_BYTE);
+        Some(unsafe { NonNull::new_unchecked(ptr as *mut u8) })
+    }
+
+    /// Verifies and releases the allocation if it has a guard page, returns
+    /// `false` if it does not.
+    pub(super) fn dealloc(
+        allocator: &GlobalAllocator,
+        ptr: usize,
+        layout: Layout,
+        free_site: usize,
+    ) -> bool {
+        let alloc = {
+            let mut guarded = GUARDED.lock();
+            match guarded
+                .iter_mut()
+                .find(|g| g.is_some_and(|g| g.ptr == ptr))
+            {
+                Some(slot) => slot.take().unwrap(),
+                None => return false,
+            }
+        };
+        if alloc.layout.size() != layout.size() {
+            violation(
+                "deallocation with a wrong size",
+                ptr,
+                ptr,
+                layout,
+                alloc.caller,
+                free_site,
+            );
+        }
+        let guard = alloc.guard_page();
+        let end = ptr + layout.size();
+        if let Some(off) = find_mismatch(alloc.base, ptr - alloc.base, REDZONE_BYTE) {
+            violation(
+                "heap underflow",
+                alloc.base + off,
+                ptr,
+                layout,
+                alloc.caller,
+                free_site,
+            );
+        }
+        if let Some(off) = find_mismatch(end, guard - end, REDZONE_BYTE) {
+            violation(
+                "heap overflow",
+                end + off,
+                ptr,
+                layout,
+                alloc.caller,
+                free_site,
+            );
+        }
+
+        if set_guard_page(guard, false) {
+            allocator.dealloc_pages(alloc.base, alloc.num_pages);
+        } else {
+            // the pages cannot be reused while the guard page is unmapped.
+            warn!(
+                "debug-heap: failed to remove guard page {:#x}, leaking {} pages",
+                guard, alloc.num_pages
+            );
+        }
+        true
+    }
+
+    /// Enables or disables guard pages for new allocations of at least one
+    /// page.
+    ///
+    /// It is off by default, and switched on by `axruntime` once the kernel
+    /// address space has been set up. At most 64 allocations can have guard
+    /// pages at the same time.
+    pub fn set_guard_pages(enabled: bool) {
+        GUARD_PAGES.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Reports the allocation whose guard page contains `vaddr`, which is
+    /// supposed to be called on kernel page faults.
+    ///
+    /// Returns `false` if `vaddr` is not in any guard page.
+    pub fn report_guard_page_fault(vaddr: usize) -> bool {
+        // the lock may be held by the faulting code.
+        let Some(guarded) = GUARDED.try_lock() else {
+            return false;
+        };
+        let Some(alloc) = guarded
+            .iter()
+            .flatten()
+            .find(|g| (g.guard_page()..g.guard_page() + PAGE_SIZE).contains(&vaddr))
+        else {
+            return false;
+        };
+        error!(
+            "debug-heap: heap overflow at {:#x} (allocation {:#x}, {:?}), allocated at {:#x}",
+            vaddr, alloc.ptr, alloc.layout, alloc.caller
+        );
+        true
+    }
+}
@@ -0,0 +1,187 @@
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::NonNull;
+
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator};
+
+use crate::{DefaultByteAllocator, PAGE_SIZE};
+
+/// Maximum number of expansion regions that can be given back to the page
+/// allocator. Further expansions are merged into the main heap.
+const MAX_HEAP_REGIONS: usize = 32;
+
+/// Size of the trailing pages of an expansion region, which hold the byte
+/// allocator of that region.
+pub(crate) const REGION_HEADER_SIZE: usize =
+    size_of::<DefaultByteAllocator>().next_multiple_of(PAGE_SIZE);
+
+/// A memory region obtained from the page allocator to expand the heap.
+///
+/// It is managed by its own byte allocator, which is stored at the end of the
+/// region, so that the whole region can be released once it is completely
+/// free.
+struct HeapRegion {
+    start: usize,
+    size: usize,
+    balloc: NonNull<DefaultByteAllocator>,
+}
+
+// Safety: the byte allocator is only accessed through `ByteHeap`, which is
+// protected by a lock.
+unsafe impl Send for HeapRegion {}
+
+impl HeapRegion {
+    /// # Safety
+    ///
+    /// The region `[start, start + size)` must be valid and unused, and
+    /// larger than [`REGION_HEADER_SIZE`].
+    unsafe fn new(start: usize, size: usize) -> Self {
+        let heap_size = size - REGION_HEADER_SIZE;
+        let balloc = (start + heap_size) as *mut DefaultByteAllocator;
+        balloc.write(DefaultByteAllocator::new());
+        (*balloc).init(start, heap_size);
+        Self {
+            start,
+            size,
+            balloc: NonNull::new_unchecked(balloc),
+        }
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.start + self.size).contains(&addr)
+    }
+
+    fn balloc(&self) -> &DefaultByteAllocator {
+        unsafe { self.balloc.as_ref() }
+    }
+
+    fn balloc_mut(&mut self) -> &mut DefaultByteAllocator {
+        unsafe { self.balloc.as_mut() }
+    }
+
+    fn is_free(&self) -> bool {
+        self.balloc().used_bytes() == 0
+    }
+
+    /// Destroys the byte allocator of the region, and returns the region as
+    /// `(start, size)`.
+    fn release(self) -> (usize, usize) {
+        unsafe { self.balloc.as_ptr().drop_in_place() };
+        (self.start, self.size)
+    }
+}
+
+/// The byte heap of [`GlobalAllocator`](crate::GlobalAllocator).
+///
+/// It consists of a main byte allocator, which holds the initial heap, and
+/// several expansion regions which can be given back to the page allocator
+/// when they are completely free.
+pub(crate) struct ByteHeap {
+    main: DefaultByteAllocator,
+    regions: [Option<HeapRegion>; MAX_HEAP_REGIONS],
+}
+
+impl ByteHeap {
+    pub const fn new() -> Self {
+        Self {
+            main: DefaultByteAllocator::new(),
+            regions: [const { None }; MAX_HEAP_REGIONS],
+        }
+    }
+
+    pub fn init(&mut self, start: usize, size: usize) {
+        self.main.init(start, size);
+    }
+
+    /// Adds a region allocated from the page allocator to the heap.
+    ///
+    /// If there is no room to track it as a separate region, it is merged
+    /// into the main heap and will never be released.
+    pub fn expand(&mut self, start: usize, size: usize) -> AllocResult {
+        if size > REGION_HEADER_SIZE {
+            if let Some(slot) = self.regions.iter_mut().find(|r| r.is_none()) {
+                *slot = Some(unsafe { HeapRegion::new(start, size) });
+                return Ok(());
+            }
+        }
+        self.main.add_memory(start, size)
+    }
+
+    pub fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        if let Ok(ptr) = self.main.alloc(layout) {
+            return Ok(ptr);
+        }
+        self.regions
+            .iter_mut()
+            .flatten()
+            .find_map(|r| r.balloc_mut().alloc(layout).ok())
+            .ok_or(AllocError::NoMemory)
+    }
+
+    /// Deallocates the memory, returns `true` if it makes an expansion region
+    /// completely free.
+    pub fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) -> bool {
+        let addr = pos.as_ptr() as usize;
+        match self.regions.iter_mut().flatten().find(|r| r.contains(addr)) {
+            Some(region) => {
+                region.balloc_mut().dealloc(pos, layout);
+                region.is_free()
+            }
+            None => {
+                self.main.dealloc(pos, layout);
+                false
+            }
+        }
+    }
+
+    /// Returns the total size of completely free expansion regions.
+    pub fn free_region_bytes(&self) -> usize {
+        self.regions
+            .iter()
+            .flatten()
+            .filter(|r| r.is_free())
+            .map(|r| r.size)
+            .sum()
+    }
+
+    /// Removes all completely free expansion regions from the heap, and calls
+    /// `f` with `(start, size)` of each of them.
+    pub fn take_free_regions(&mut self, mut f: impl FnMut(usize, usize)) {
+        for slot in self.regions.iter_mut() {
+            if slot.as_ref().is_some_and(|r| r.is_free()) {
+                let (start, size) = slot.take().unwrap().release();
+                f(start, size);
+            }
+        }
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.main.total_bytes()
+            + self
+                .regions
+                .iter()
+                .flatten()
+                .map(|r| r.balloc().total_bytes())
+                .sum::<usize>()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.main.used_bytes()
+            + self
+                .regions
+                .iter()
+                .flatten()
+                .map(|r| r.balloc().used_bytes())
+                .sum::<usize>()
+    }
+
+    pub fn available_bytes(&self) -> usize {
+        self.main.available_bytes()
+            + self
+                .regions
+                .iter()
+                .flatten()
+                .map(|r| r.balloc().available_bytes())
+                .sum::<usize>()
+    }
+}
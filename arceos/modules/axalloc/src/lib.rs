@@ -4,25 +4,92 @@
 //! [`core::alloc::GlobalAlloc`]. A static global variable of type
 //! [`GlobalAllocator`] is defined with the `#[global_allocator]` attribute, to
 //! be registered as the standard library’s default allocator.
+//!
+//! # Cargo Features
+//!
+//! - `tlsf`, `slab`, `buddy`: Select the byte allocator of the default
+//!   [`GlobalAllocator`]. `tlsf` is **enabled** by default.
+//! - `myalloc`: Allow users to define their custom allocator to replace the
+//!   default one. In this case, [`MyAllocatorIf`] is required to be
+//!   implemented, and all allocation requests are forwarded to it.
+//! - `smp`: Enable per-CPU object caches in the default allocator.
+//! - `heap-profile`: Record per-call-site heap usage statistics, and trace
+//!   allocations for replaying.
+//! - `debug-heap`: Surround allocations with red zones and poison freed
+//!   memory, to catch heap overflows and use-after-free.
+//! - `paging`: Allow large allocations to be followed by unmapped guard pages
+//!   in the `debug-heap` mode. In this case, [`GuardPageIf`] is required to be
+//!   implemented.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
 extern crate alloc;
 
+mod oom;
 mod page;
 
-use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
+#[cfg(any(feature = "heap-profile", feature = "debug-heap"))]
+mod caller;
+#[cfg(feature = "debug-heap")]
+mod debug;
+#[cfg(feature = "heap-profile")]
+mod profile;
+
+use allocator::AllocResult;
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
-use kspin::SpinNoIrq;
 
 const PAGE_SIZE: usize = 0x1000;
-const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
 
+pub use oom::{
+    register_shrinker, set_oom_handler, unregister_shrinker, OomAction, OomHandler, Shrinker,
+};
 pub use page::GlobalPage;
 
+/// Memory zones, which the page allocator can be asked to allocate from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryZone {
+    /// Memory whose bus address is below `axconfig::DMA32_ZONE_END`, which
+    /// is accessible to devices with 32-bit DMA addresses.
+    Dma32,
+    /// All other memory.
+    Normal,
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "myalloc")] {
+        mod myalloc;
+        pub use myalloc::GlobalAllocator;
+    } else {
+        mod heap;
+        #[cfg(feature = "smp")]
+        mod magazine;
+        mod page_heap;
+
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        use kspin::SpinNoIrq;
+
+        use self::heap::{ByteHeap, REGION_HEADER_SIZE};
+        use self::page_heap::PageHeap;
+
+        const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
+        const DEFAULT_TRIM_THRESHOLD: usize = 0x10_0000; // 1 M
+
+        #[cfg(feature = "smp")]
+        pub use magazine::{cpu_cache_stats, CpuCacheStats};
+    }
+}
+
+#[cfg(all(feature = "debug-heap", feature = "paging"))]
+pub use debug::{report_guard_page_fault, set_guard_pages};
+#[cfg(feature = "heap-profile")]
+pub use profile::{
+    dump_heap_profile, for_each_caller, heap_profiling_enabled, heap_stats, reset_heap_profile,
+    set_heap_profiling, set_heap_tracing, CallerStats, HeapStats, NUM_SIZE_CLASSES,
+};
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "slab")] {
         /// The default byte allocator.
@@ -36,6 +103,53 @@ cfg_if::cfg_if! {
     }
 }
 
+/// The interface to define custom memory allocators in user apps.
+///
+/// All functions are called with the global allocator as the only entry
+/// point, so the implementation is responsible for its own locking.
+///
+/// It is always defined so that allocator crates can implement it, but only
+/// used if the `myalloc` feature is enabled.
+#[crate_interface::def_interface]
+pub trait MyAllocatorIf {
+    /// Returns the name of the allocator.
+    fn name() -> &'static str;
+    /// Initializes the allocator with the given region.
+    fn init(start_vaddr: usize, size: usize);
+    /// Adds the given region to the allocator.
+    fn add_memory(start_vaddr: usize, size: usize) -> AllocResult;
+
+    /// Allocates arbitrary number of bytes.
+    fn alloc(layout: Layout) -> AllocResult<NonNull<u8>>;
+    /// Gives back the region allocated by [`alloc`](MyAllocatorIf::alloc).
+    fn dealloc(pos: NonNull<u8>, layout: Layout);
+    /// Allocates contiguous pages, aligned to `align_pow2`.
+    fn alloc_pages(num_pages: usize, align_pow2: usize) -> AllocResult<usize>;
+    /// Gives back the pages allocated by
+    /// [`alloc_pages`](MyAllocatorIf::alloc_pages).
+    fn dealloc_pages(pos: usize, num_pages: usize);
+
+    /// Returns the number of allocated bytes.
+    fn used_bytes() -> usize;
+    /// Returns the number of available bytes.
+    fn available_bytes() -> usize;
+    /// Returns the number of allocated pages.
+    fn used_pages() -> usize;
+    /// Returns the number of available pages.
+    fn available_pages() -> usize;
+}
+
+/// The interface to unmap and remap the guard pages used by the `debug-heap`
+/// mode, which should be implemented by the memory management module.
+#[cfg(feature = "paging")]
+#[crate_interface::def_interface]
+pub trait GuardPageIf {
+    /// Unmaps the kernel page at `vaddr` (`guard` is `true`), or maps it back.
+    ///
+    /// Returns `false` if it cannot be done at the moment.
+    fn set_guard_page(vaddr: usize, guard: bool) -> bool;
+}
+
 /// The global allocator used by ArceOS.
 ///
 /// It combines a [`ByteAllocator`] and a [`PageAllocator`] into a simple
@@ -43,21 +157,35 @@ cfg_if::cfg_if! {
 /// there is no memory, asks the page allocator for more memory and adds it to
 /// the byte allocator.
 ///
+/// Memory obtained this way is given back to the page allocator once it is
+/// completely free, either automatically when the free memory exceeds the
+/// threshold set by [`set_trim_threshold`], or explicitly by [`trim`].
+///
 /// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
-/// [`BitmapPageAllocator`] is used as the page allocator.
+/// [`BitmapPageAllocator`] is used as the page allocator. Each discontiguous
+/// memory region is managed by its own [`BitmapPageAllocator`].
 ///
+/// [`ByteAllocator`]: allocator::ByteAllocator
+/// [`PageAllocator`]: allocator::PageAllocator
 /// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
+/// [`BitmapPageAllocator`]: allocator::BitmapPageAllocator
+/// [`set_trim_threshold`]: GlobalAllocator::set_trim_threshold
+/// [`trim`]: GlobalAllocator::trim
+#[cfg(not(feature = "myalloc"))]
 pub struct GlobalAllocator {
-    balloc: SpinNoIrq<DefaultByteAllocator>,
-    palloc: SpinNoIrq<BitmapPageAllocator<PAGE_SIZE>>,
+    balloc: SpinNoIrq<ByteHeap>,
+    palloc: SpinNoIrq<PageHeap>,
+    trim_threshold: AtomicUsize,
 }
 
+#[cfg(not(feature = "myalloc"))]
 impl GlobalAllocator {
     /// Creates an empty [`GlobalAllocator`].
     pub const fn new() -> Self {
         Self {
-            balloc: SpinNoIrq::new(DefaultByteAllocator::new()),
-            palloc: SpinNoIrq::new(BitmapPageAllocator::new()),
+            balloc: SpinNoIrq::new(ByteHeap::new()),
+            palloc: SpinNoIrq::new(PageHeap::new()),
+            trim_threshold: AtomicUsize::new(DEFAULT_TRIM_THRESHOLD),
         }
     }
 
@@ -76,9 +204,10 @@ impl GlobalAllocator {
 
     /// Initializes the allocator with the given region.
     ///
-    /// It firstly adds the whole region to the page allocator, then allocates
-    /// a small region (32 KB) to initialize the byte allocator. Therefore,
-    /// the given region must be larger than 32 KB.
+    /// It firstly adds the whole region to the page allocator (see
+    /// [`global_init`] for how it is split), then allocates a small region
+    /// (32 KB) to initialize the byte allocator. Therefore, the given region
+    /// must be larger than 32 KB.
     pub fn init(&self, start_vaddr: usize, size: usize) {
         assert!(size > MIN_HEAP_SIZE);
         let init_heap_size = MIN_HEAP_SIZE;
@@ -91,9 +220,11 @@ impl GlobalAllocator {
 
     /// Add the given region to the allocator.
     ///
-    /// It will add the whole region to the byte allocator.
+    /// It will add the whole region to the page allocator, which manages each
+    /// discontiguous region separately. The byte allocator obtains memory
+    /// from it as needed.
     pub fn add_memory(&self, start_vaddr: usize, size: usize) -> AllocResult {
-        self.balloc.lock().add_memory(start_vaddr, size)
+        self.palloc.lock().add_memory(start_vaddr, size)
     }
 
     /// Allocate arbitrary number of bytes. Returns the left bound of the
@@ -102,9 +233,27 @@ impl GlobalAllocator {
     /// It firstly tries to allocate from the byte allocator. If there is no
     /// memory, it asks the page allocator for more memory and adds it to the
     /// byte allocator.
+    ///
+    /// With the `smp` feature, small allocations are served from the per-CPU
+    /// object cache, which is refilled from the byte allocator in batches.
+    ///
+    /// If both fail, the registered shrinkers are called to free some memory
+    /// before retrying.
     pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        oom::alloc_or_shrink(layout.size(), || {
+            #[cfg(feature = "smp")]
+            if let Some(class) = magazine::size_class(&layout) {
+                return magazine::alloc(class, |objs| {
+                    self.alloc_batch(magazine::class_layout(class), objs)
+                })
+                .ok_or(allocator::AllocError::NoMemory);
+            }
+            self.alloc_locked(&mut self.balloc.lock(), layout)
+        })
+    }
+
+    fn alloc_locked(&self, balloc: &mut ByteHeap, layout: Layout) -> AllocResult<NonNull<u8>> {
         // simple two-level allocator: if no heap memory, allocate from the page allocator.
-        let mut balloc = self.balloc.lock();
         loop {
             if let Ok(ptr) = balloc.alloc(layout) {
                 return Ok(ptr);
@@ -113,14 +262,20 @@ impl GlobalAllocator {
                 let expand_size = old_size
                     .max(layout.size())
                     .next_power_of_two()
-                    .max(PAGE_SIZE);
-                let heap_ptr = self.alloc_pages(expand_size / PAGE_SIZE, PAGE_SIZE)?;
+                    .max(MIN_HEAP_SIZE)
+                    + REGION_HEADER_SIZE;
+                // do not call shrinkers here, as they may free bytes.
+                let heap_ptr = self.palloc.lock().alloc_pages(
+                    MemoryZone::Normal,
+                    expand_size / PAGE_SIZE,
+                    PAGE_SIZE,
+                )?;
                 debug!(
                     "expand heap memory: [{:#x}, {:#x})",
                     heap_ptr,
                     heap_ptr + expand_size
                 );
-                balloc.add_memory(heap_ptr, expand_size)?;
+                balloc.expand(heap_ptr, expand_size)?;
             }
         }
     }
@@ -133,7 +288,79 @@ impl GlobalAllocator {
     ///
     /// [`alloc`]: GlobalAllocator::alloc
     pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
-        self.balloc.lock().dealloc(pos, layout)
+        #[cfg(feature = "smp")]
+        if let Some(class) = magazine::size_class(&layout) {
+            return magazine::dealloc(class, pos, |objs| {
+                self.dealloc_batch(magazine::class_layout(class), objs)
+            });
+        }
+        self.dealloc_locked(&mut self.balloc.lock(), pos, layout)
+    }
+
+    fn dealloc_locked(&self, balloc: &mut ByteHeap, pos: NonNull<u8>, layout: Layout) {
+        if balloc.dealloc(pos, layout)
+            && balloc.free_region_bytes() > self.trim_threshold.load(Ordering::Relaxed)
+        {
+            self.release_free_regions(balloc);
+        }
+    }
+
+    /// Fills `objs` with objects of the same `layout`, returns the number of
+    /// objects allocated.
+    #[cfg(feature = "smp")]
+    fn alloc_batch(&self, layout: Layout, objs: &mut [usize]) -> usize {
+        let mut balloc = self.balloc.lock();
+        for (i, obj) in objs.iter_mut().enumerate() {
+            match self.alloc_locked(&mut balloc, layout) {
+                Ok(ptr) => *obj = ptr.as_ptr() as usize,
+                Err(_) => return i,
+            }
+        }
+        objs.len()
+    }
+
+    /// Gives back objects of the same `layout` allocated by [`alloc_batch`].
+    ///
+    /// [`alloc_batch`]: GlobalAllocator::alloc_batch
+    #[cfg(feature = "smp")]
+    fn dealloc_batch(&self, layout: Layout, objs: &[usize]) {
+        let mut balloc = self.balloc.lock();
+        for &obj in objs {
+            let pos = unsafe { NonNull::new_unchecked(obj as *mut u8) };
+            self.dealloc_locked(&mut balloc, pos, layout);
+        }
+    }
+
+    /// Gives all completely free heap expansion regions back to the page
+    /// allocator. Returns the number of bytes released.
+    ///
+    /// With the `smp` feature, the object caches of all CPUs are flushed
+    /// first.
+    pub fn trim(&self) -> usize {
+        #[cfg(feature = "smp")]
+        magazine::drain(|class, objs| self.dealloc_batch(magazine::class_layout(class), objs));
+        self.release_free_regions(&mut self.balloc.lock())
+    }
+
+    /// Sets the threshold of automatic trimming.
+    ///
+    /// Once the completely free heap expansion regions are larger than
+    /// `bytes` in total, they are given back to the page allocator. Use
+    /// [`usize::MAX`] to trim only by explicitly calling [`trim`].
+    ///
+    /// [`trim`]: GlobalAllocator::trim
+    pub fn set_trim_threshold(&self, bytes: usize) {
+        self.trim_threshold.store(bytes, Ordering::Relaxed);
+    }
+
+    fn release_free_regions(&self, balloc: &mut ByteHeap) -> usize {
+        let mut released = 0;
+        balloc.take_free_regions(|start, size| {
+            debug!("shrink heap memory: [{:#x}, {:#x})", start, start + size);
+            self.dealloc_pages(start, size / PAGE_SIZE);
+            released += size;
+        });
+        released
     }
 
     /// Allocates contiguous pages.
@@ -142,8 +369,31 @@ impl GlobalAllocator {
     ///
     /// `align_pow2` must be a power of 2, and the returned region bound will be
     /// aligned to it.
+    ///
+    /// If there is no memory, the registered shrinkers are called to free
+    /// some memory before retrying.
+    ///
+    /// The pages are allocated from the [`MemoryZone::Normal`] zone, or from
+    /// the [`MemoryZone::Dma32`] zone if the former is exhausted.
     pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
-        self.palloc.lock().alloc_pages(num_pages, align_pow2)
+        self.alloc_pages_in(MemoryZone::Normal, num_pages, align_pow2)
+    }
+
+    /// Allocates contiguous pages from the given memory zone.
+    ///
+    /// It's similar to [`alloc_pages`], but the pages are guaranteed to be
+    /// in `zone` if it is [`MemoryZone::Dma32`].
+    ///
+    /// [`alloc_pages`]: GlobalAllocator::alloc_pages
+    pub fn alloc_pages_in(
+        &self,
+        zone: MemoryZone,
+        num_pages: usize,
+        align_pow2: usize,
+    ) -> AllocResult<usize> {
+        oom::alloc_or_shrink(num_pages * PAGE_SIZE, || {
+            self.palloc.lock().alloc_pages(zone, num_pages, align_pow2)
+        })
     }
 
     /// Gives back the allocated pages starts from `pos` to the page allocator.
@@ -176,19 +426,37 @@ impl GlobalAllocator {
     pub fn available_pages(&self) -> usize {
         self.palloc.lock().available_pages()
     }
+
+    /// Returns the number of available pages in the given memory zone.
+    pub fn available_pages_in(&self, zone: MemoryZone) -> usize {
+        self.palloc.lock().available_pages_in(zone)
+    }
 }
 
 unsafe impl GlobalAlloc for GlobalAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if let Ok(ptr) = GlobalAllocator::alloc(self, layout) {
-            ptr.as_ptr()
-        } else {
-            alloc::alloc::handle_alloc_error(layout)
+        #[cfg(feature = "debug-heap")]
+        let try_alloc = || debug::alloc(self, layout);
+        #[cfg(not(feature = "debug-heap"))]
+        let try_alloc = || GlobalAllocator::alloc(self, layout);
+        match oom::alloc_or_handle_oom(layout, try_alloc) {
+            Ok(ptr) => {
+                #[cfg(feature = "heap-profile")]
+                profile::record_alloc(ptr.as_ptr() as usize, layout);
+                ptr.as_ptr()
+            }
+            Err(_) => alloc::alloc::handle_alloc_error(layout),
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        GlobalAllocator::dealloc(self, NonNull::new(ptr).expect("dealloc null ptr"), layout)
+        #[cfg(feature = "heap-profile")]
+        profile::record_dealloc(ptr as usize);
+        let pos = NonNull::new(ptr).expect("dealloc null ptr");
+        #[cfg(feature = "debug-heap")]
+        debug::dealloc(self, pos, layout);
+        #[cfg(not(feature = "debug-heap"))]
+        GlobalAllocator::dealloc(self, pos, layout);
     }
 }
 
@@ -202,10 +470,12 @@ pub fn global_allocator() -> &'static GlobalAllocator {
 
 /// Initializes the global allocator with the given memory region.
 ///
-/// Note that the memory region bounds are just numbers, and the allocator
-/// does not actually access the region. Users should ensure that the region
-/// is valid and not being used by others, so that the allocated memory is also
-/// valid.
+/// With the default allocator, the region is split at the end of the
+/// [`MemoryZone::Dma32`] zone, and into pieces that a single page allocator
+/// can manage. Except for the first one, each piece keeps its page allocator
+/// in its leading pages, so the region must be mapped and writable. Users
+/// should also ensure that the region is not being used by others, so that
+/// the allocated memory is valid.
 ///
 /// This function should be called only once, and before any allocation.
 pub fn global_init(start_vaddr: usize, size: usize) {
@@ -222,7 +492,8 @@ pub fn global_init(start_vaddr: usize, size: usize) {
 /// Users should ensure that the region is valid and not being used by others,
 /// so that the allocated memory is also valid.
 ///
-/// It's similar to [`global_init`], but can be called multiple times.
+/// It's similar to [`global_init`], but can be called multiple times. Each
+/// piece of the region keeps its own page allocator in its leading pages.
 pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     debug!(
         "add a memory region to global allocator: [{:#x}, {:#x})",
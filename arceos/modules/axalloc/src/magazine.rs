@@ -0,0 +1,193 @@
+//! Per-CPU caches of small objects (enabled by the `smp` feature).
+//!
+//! Each CPU keeps a magazine (a small stack of free objects) for every size
+//! class. Allocations and deallocations are served from the magazine of the
+//! current CPU without taking the global heap lock, which is only acquired to
+//! refill an empty magazine or to flush a full one, both in batches.
+//!
+//! The cache of each CPU has its own lock, which is only contended when the
+//! caches are drained by another CPU.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+/// Number of size classes, which are `16, 32, ..., 2048` bytes.
+const NUM_CLASSES: usize = 8;
+const MIN_CLASS_SHIFT: usize = 4;
+/// Maximum alignment of cached objects.
+const CLASS_ALIGN: usize = 16;
+/// Number of objects a magazine can hold.
+const MAGAZINE_SIZE: usize = 32;
+/// Number of objects to move from or to the global heap at once.
+const BATCH_SIZE: usize = MAGAZINE_SIZE / 2;
+
+struct Magazine {
+    len: usize,
+    objs: [usize; MAGAZINE_SIZE],
+}
+
+struct CpuCache {
+    mags: [Magazine; NUM_CLASSES],
+}
+
+struct CacheCounters {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    refills: AtomicUsize,
+    flushes: AtomicUsize,
+}
+
+#[percpu::def_percpu]
+static CPU_CACHE: SpinNoIrq<CpuCache> = SpinNoIrq::new(CpuCache::new());
+
+#[percpu::def_percpu]
+static CACHE_COUNTERS: CacheCounters = CacheCounters::new();
+
+/// Statistics of the object cache of a CPU.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuCacheStats {
+    /// Number of allocations served by the cache.
+    pub hits: usize,
+    /// Number of allocations that found the cache empty.
+    pub misses: usize,
+    /// Number of batches taken from the global heap.
+    pub refills: usize,
+    /// Number of batches given back to the global heap.
+    pub flushes: usize,
+}
+
+impl CpuCacheStats {
+    /// Returns the percentage of allocations served by the cache.
+    pub fn hit_rate(&self) -> usize {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0
+        } else {
+            self.hits * 100 / total
+        }
+    }
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            len: 0,
+            objs: [0; MAGAZINE_SIZE],
+        }
+    }
+}
+
+impl CpuCache {
+    const fn new() -> Self {
+        Self {
+            mags: [const { Magazine::new() }; NUM_CLASSES],
+        }
+    }
+}
+
+impl CacheCounters {
+    const fn new() -> Self {
+        Self {
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            refills: AtomicUsize::new(0),
+            flushes: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Returns the size class of the layout, or `None` if it cannot be cached.
+pub(crate) fn size_class(layout: &Layout) -> Option<usize> {
+    if layout.align() > CLASS_ALIGN {
+        return None;
+    }
+    let shift = layout.size().max(1).next_power_of_two().trailing_zeros() as usize;
+    let class = shift.saturating_sub(MIN_CLASS_SHIFT);
+    (class < NUM_CLASSES).then_some(class)
+}
+
+/// Returns the layout used to allocate objects of the size class from the
+/// global heap.
+pub(crate) fn class_layout(class: usize) -> Layout {
+    Layout::from_size_align(1 << (class + MIN_CLASS_SHIFT), CLASS_ALIGN).unwrap()
+}
+
+/// Allocates an object of the size class from the cache of the current CPU.
+///
+/// If the cache is empty, `refill` is called to fill the given slots with
+/// objects from the global heap, and returns the number of objects filled.
+pub(crate) fn alloc(
+    class: usize,
+    refill: impl FnOnce(&mut [usize]) -> usize,
+) -> Option<NonNull<u8>> {
+    // Safety: the cache may belong to another CPU if the task has migrated
+    // meanwhile, which is fine as it is protected by its lock.
+    let mut cache = unsafe { CPU_CACHE.current_ref_raw() }.lock();
+    let mag = &mut cache.mags[class];
+    // Safety: the counters are only accessed atomically.
+    let counters = unsafe { CACHE_COUNTERS.current_ref_raw() };
+    if mag.len == 0 {
+        counters.misses.fetch_add(1, Ordering::Relaxed);
+        mag.len = refill(&mut mag.objs[..BATCH_SIZE]);
+        if mag.len == 0 {
+            return None;
+        }
+        counters.refills.fetch_add(1, Ordering::Relaxed);
+    } else {
+        counters.hits.fetch_add(1, Ordering::Relaxed);
+    }
+    mag.len -= 1;
+    NonNull::new(mag.objs[mag.len] as *mut u8)
+}
+
+/// Gives an object of the size class back to the cache of the current CPU.
+///
+/// If the cache is full, `flush` is called to give the given objects back to
+/// the global heap.
+pub(crate) fn dealloc(class: usize, pos: NonNull<u8>, flush: impl FnOnce(&[usize])) {
+    // Safety: see `alloc`.
+    let mut cache = unsafe { CPU_CACHE.current_ref_raw() }.lock();
+    let mag = &mut cache.mags[class];
+    if mag.len == MAGAZINE_SIZE {
+        flush(&mag.objs[..BATCH_SIZE]);
+        mag.objs.copy_within(BATCH_SIZE.., 0);
+        mag.len -= BATCH_SIZE;
+        let counters = unsafe { CACHE_COUNTERS.current_ref_raw() };
+        counters.flushes.fetch_add(1, Ordering::Relaxed);
+    }
+    mag.objs[mag.len] = pos.as_ptr() as usize;
+    mag.len += 1;
+}
+
+/// Gives all objects in the caches of all CPUs back to the global heap.
+///
+/// `flush` is called with each size class and its cached objects, with the
+/// lock of the cache held.
+pub(crate) fn drain(mut flush: impl FnMut(usize, &[usize])) {
+    for cpu_id in 0..axconfig::SMP {
+        // Safety: the cache is protected by its lock.
+        let mut cache = unsafe { CPU_CACHE.remote_ref_raw(cpu_id) }.lock();
+        for (class, mag) in cache.mags.iter_mut().enumerate() {
+            if mag.len > 0 {
+                flush(class, &mag.objs[..mag.len]);
+                mag.len = 0;
+            }
+        }
+    }
+}
+
+/// Returns the statistics of the object cache of the given CPU.
+pub fn cpu_cache_stats(cpu_id: usize) -> CpuCacheStats {
+    assert!(cpu_id < axconfig::SMP);
+    // Safety: the counters are only accessed atomically.
+    let counters = unsafe { CACHE_COUNTERS.remote_ref_raw(cpu_id) };
+    CpuCacheStats {
+        hits: counters.hits.load(Ordering::Relaxed),
+        misses: counters.misses.load(Ordering::Relaxed),
+        refills: counters.refills.load(Ordering::Relaxed),
+        flushes: counters.flushes.load(Ordering::Relaxed),
+    }
+}
@@ -0,0 +1,105 @@
+use allocator::AllocResult;
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::MemoryZone;
+
+/// The global allocator used by ArceOS.
+///
+/// With the `myalloc` feature, all requests are forwarded to the allocator
+/// defined by the user app through [`MyAllocatorIf`](crate::MyAllocatorIf).
+pub struct GlobalAllocator;
+
+impl GlobalAllocator {
+    /// Creates an empty [`GlobalAllocator`].
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// Returns the name of the allocator.
+    pub fn name(&self) -> &'static str {
+        crate_interface::call_interface!(crate::MyAllocatorIf::name())
+    }
+
+    /// Initializes the allocator with the given region.
+    pub fn init(&self, start_vaddr: usize, size: usize) {
+        crate_interface::call_interface!(crate::MyAllocatorIf::init(start_vaddr, size))
+    }
+
+    /// Add the given region to the allocator.
+    pub fn add_memory(&self, start_vaddr: usize, size: usize) -> AllocResult {
+        crate_interface::call_interface!(crate::MyAllocatorIf::add_memory(start_vaddr, size))
+    }
+
+    /// Allocate arbitrary number of bytes. Returns the left bound of the
+    /// allocated region.
+    ///
+    /// If there is no memory, the registered shrinkers are called to free
+    /// some memory before retrying.
+    pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        crate::oom::alloc_or_shrink(layout.size(), || {
+            crate_interface::call_interface!(crate::MyAllocatorIf::alloc(layout))
+        })
+    }
+
+    /// Gives back the allocated region.
+    pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
+        crate_interface::call_interface!(crate::MyAllocatorIf::dealloc(pos, layout))
+    }
+
+    /// Allocates contiguous pages.
+    ///
+    /// If there is no memory, the registered shrinkers are called to free
+    /// some memory before retrying.
+    pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        crate::oom::alloc_or_shrink(num_pages * crate::PAGE_SIZE, || {
+            crate_interface::call_interface!(crate::MyAllocatorIf::alloc_pages(
+                num_pages, align_pow2
+            ))
+        })
+    }
+
+    /// Allocates contiguous pages from the given memory zone.
+    ///
+    /// Custom allocators have no notion of zones, so it's the same as
+    /// [`alloc_pages`](GlobalAllocator::alloc_pages).
+    pub fn alloc_pages_in(
+        &self,
+        _zone: MemoryZone,
+        num_pages: usize,
+        align_pow2: usize,
+    ) -> AllocResult<usize> {
+        self.alloc_pages(num_pages, align_pow2)
+    }
+
+    /// Gives back the allocated pages starts from `pos`.
+    pub fn dealloc_pages(&self, pos: usize, num_pages: usize) {
+        crate_interface::call_interface!(crate::MyAllocatorIf::dealloc_pages(pos, num_pages))
+    }
+
+    /// Returns the number of allocated bytes.
+    pub fn used_bytes(&self) -> usize {
+        crate_interface::call_interface!(crate::MyAllocatorIf::used_bytes())
+    }
+
+    /// Returns the number of available bytes.
+    pub fn available_bytes(&self) -> usize {
+        crate_interface::call_interface!(crate::MyAllocatorIf::available_bytes())
+    }
+
+    /// Returns the number of allocated pages.
+    pub fn used_pages(&self) -> usize {
+        crate_interface::call_interface!(crate::MyAllocatorIf::used_pages())
+    }
+
+    /// Returns the number of available pages.
+    pub fn available_pages(&self) -> usize {
+        crate_interface::call_interface!(crate::MyAllocatorIf::available_pages())
+    }
+
+    /// Returns the number of available pages in the given memory zone, which
+    /// is the same as [`available_pages`](GlobalAllocator::available_pages).
+    pub fn available_pages_in(&self, _zone: MemoryZone) -> usize {
+        self.available_pages()
+    }
+}
@@ -0,0 +1,269 @@
+//! Memory pressure handling.
+//!
+//! Before an allocation fails, the registered shrinkers are asked to give
+//! back memory they can easily rebuild (e.g., caches), and the allocation is
+//! retried. If it still fails, the OOM handler decides what to do.
+
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use allocator::{AllocError, AllocResult};
+use axerrno::{AxError, AxResult};
+use kernel_guard::NoPreemptIrqSave;
+use kspin::SpinNoIrq;
+
+/// Maximum number of shrinkers that can be registered.
+const MAX_SHRINKERS: usize = 16;
+/// Maximum number of times to retry a failed allocation after shrinking.
+const MAX_SHRINK_RETRIES: usize = 3;
+/// Maximum number of times to retry a failed allocation at the request of
+/// the OOM handler.
+const MAX_OOM_RETRIES: usize = 8;
+/// The value of [`SHRINKING_CPU`] when no shrinker is running.
+const NO_CPU: usize = 0;
+
+/// A shrinker callback.
+///
+/// It is called with the number of bytes the allocator is short of, and
+/// returns the number of bytes it has freed. It may be called with
+/// arbitrary locks held by the allocating code, so it should only
+/// `try_lock` its own data structures, and give up if they are busy.
+pub type Shrinker = fn(wanted: usize) -> usize;
+
+/// An OOM handler, called with the layout of the allocation that failed
+/// after all shrinkers have been tried.
+///
+/// The handler may print a heap report, free some memory, or terminate the
+/// current task (in which case it never returns).
+pub type OomHandler = fn(layout: Layout) -> OomAction;
+
+/// What to do after the [`OomHandler`] returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomAction {
+    /// Retry the allocation, e.g., the handler has freed some memory. The
+    /// handler is asked again if it still fails, up to a few times.
+    Retry,
+    /// Let the allocation fail. For allocations through
+    /// [`GlobalAlloc`](core::alloc::GlobalAlloc), it results in a panic.
+    Fail,
+}
+
+static SHRINKERS: SpinNoIrq<[Option<Shrinker>; MAX_SHRINKERS]> =
+    SpinNoIrq::new([None; MAX_SHRINKERS]);
+static OOM_HANDLER: SpinNoIrq<Option<OomHandler>> = SpinNoIrq::new(None);
+/// The CPU running the shrinkers, or [`NO_CPU`].
+static SHRINKING_CPU: AtomicUsize = AtomicUsize::new(NO_CPU);
+/// Total number of bytes freed by the shrinkers, which tells the callers
+/// that waited for the shrinkers whether to retry.
+static SHRUNK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(all(feature = "smp", not(test)))]
+#[percpu::def_percpu]
+static CPU_MARK: u8 = 0;
+
+/// Returns an identifier of the current CPU other than [`NO_CPU`].
+///
+/// Preemption must be disabled.
+fn this_cpu_id() -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(test)] {
+            // Threads act as CPUs in tests.
+            std::thread_local!(static MARK: u8 = const { 0 });
+            MARK.with(|mark| mark as *const u8 as usize)
+        } else if #[cfg(feature = "smp")] {
+            // The address of a per-CPU variable is unique to the CPU.
+            unsafe { CPU_MARK.current_ptr() as usize }
+        } else {
+            1
+        }
+    }
+}
+
+/// Registers a shrinker to be called under memory pressure.
+///
+/// Returns [`AxError::NoMemory`] if too many shrinkers have been registered.
+pub fn register_shrinker(shrinker: Shrinker) -> AxResult {
+    let mut shrinkers = SHRINKERS.lock();
+    let slot = shrinkers
+        .iter_mut()
+        .find(|s| s.is_none())
+        .ok_or(AxError::NoMemory)?;
+    *slot = Some(shrinker);
+    Ok(())
+}
+
+/// Unregisters a shrinker registered by [`register_shrinker`].
+pub fn unregister_shrinker(shrinker: Shrinker) {
+    let mut shrinkers = SHRINKERS.lock();
+    if let Some(slot) = shrinkers
+        .iter_mut()
+        .find(|s| s.is_some_and(|s| s as usize == shrinker as usize))
+    {
+        *slot = None;
+    }
+}
+
+/// Installs the OOM handler, replacing the previous one.
+pub fn set_oom_handler(handler: OomHandler) {
+    *OOM_HANDLER.lock() = Some(handler);
+}
+
+/// Calls all shrinkers, returns the number of bytes freed.
+///
+/// If the shrinkers are running on another CPU, it waits for them to finish
+/// instead, and returns the number of bytes they have freed meanwhile.
+fn shrink(wanted: usize) -> usize {
+    // Shrinkers run with preemption and IRQs disabled, so that a shrinker
+    // that allocates can be recognized by the CPU.
+    let _guard = NoPreemptIrqSave::new();
+    let cpu = this_cpu_id();
+    loop {
+        let shrunk = SHRUNK_BYTES.load(Ordering::Acquire);
+        match SHRINKING_CPU.compare_exchange(NO_CPU, cpu, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) => break,
+            Err(owner) if owner == cpu => return 0,
+            Err(_) => {
+                while SHRINKING_CPU.load(Ordering::Acquire) != NO_CPU {
+                    core::hint::spin_loop();
+                }
+                let freed = SHRUNK_BYTES.load(Ordering::Acquire).wrapping_sub(shrunk);
+                if freed > 0 {
+                    return freed;
+                }
+                // They freed nothing (or finished before we noticed), try
+                // them again by ourselves.
+            }
+        }
+    }
+
+    // Copy the shrinkers out, as they will free memory.
+    let shrinkers = *SHRINKERS.lock();
+    let mut freed = 0;
+    for shrinker in shrinkers.iter().flatten() {
+        freed += shrinker(wanted.saturating_sub(freed));
+        if freed >= wanted {
+            break;
+        }
+    }
+    SHRUNK_BYTES.fetch_add(freed, Ordering::Release);
+    SHRINKING_CPU.store(NO_CPU, Ordering::Release);
+    if freed > 0 {
+        debug!("shrinkers freed {} bytes", freed);
+    }
+    freed
+}
+
+/// Calls `f` to allocate `size` bytes, and retries it after calling the
+/// shrinkers if there is no memory.
+pub(crate) fn alloc_or_shrink<T>(
+    size: usize,
+    mut f: impl FnMut() -> AllocResult<T>,
+) -> AllocResult<T> {
+    for _ in 0..MAX_SHRINK_RETRIES {
+        match f() {
+            Err(AllocError::NoMemory) => {
+                if shrink(size) == 0 {
+                    return Err(AllocError::NoMemory);
+                }
+            }
+            res => return res,
+        }
+    }
+    f()
+}
+
+/// Calls `f` to allocate `layout`, and retries it as long as the OOM handler
+/// asks to, at most [`MAX_OOM_RETRIES`] times.
+pub(crate) fn alloc_or_handle_oom<T>(
+    layout: Layout,
+    mut f: impl FnMut() -> AllocResult<T>,
+) -> AllocResult<T> {
+    for _ in 0..MAX_OOM_RETRIES {
+        match f() {
+            Err(AllocError::NoMemory) if handle_oom(layout) == OomAction::Retry => {}
+            res => return res,
+        }
+    }
+    f()
+}
+
+/// Handles a failed allocation of `layout`.
+fn handle_oom(layout: Layout) -> OomAction {
+    let allocator = crate::global_allocator();
+    error!(
+        "out of memory: {:?}, heap used {} bytes, available {} bytes, pages used {}, available {}",
+        layout,
+        allocator.used_bytes(),
+        allocator.available_bytes(),
+        allocator.used_pages(),
+        allocator.available_pages(),
+    );
+    let handler = *OOM_HANDLER.lock();
+    match handler {
+        Some(handler) => handler(layout),
+        None => OomAction::Fail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_shrink_retries() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        // Claims progress without freeing anything.
+        fn shrinker(wanted: usize) -> usize {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            // Called from a shrinker, it does not recurse.
+            assert_eq!(shrink(wanted), 0);
+            wanted
+        }
+
+        let _lock = SERIAL.lock().unwrap();
+        register_shrinker(shrinker).unwrap();
+        let res = alloc_or_shrink(16, || AllocResult::<()>::Err(AllocError::NoMemory));
+        unregister_shrinker(shrinker);
+        assert_eq!(res, Err(AllocError::NoMemory));
+        assert_eq!(CALLS.load(Ordering::Relaxed), MAX_SHRINK_RETRIES);
+    }
+
+    #[test]
+    fn test_concurrent_shrink() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static AVAILABLE: AtomicUsize = AtomicUsize::new(0);
+        fn shrinker(_wanted: usize) -> usize {
+            CALLS.fetch_add(1, Ordering::AcqRel);
+            thread::sleep(Duration::from_millis(100));
+            AVAILABLE.fetch_add(2, Ordering::AcqRel);
+            2
+        }
+        fn alloc() -> AllocResult {
+            AVAILABLE
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+                .map(|_| ())
+                .map_err(|_| AllocError::NoMemory)
+        }
+
+        let _lock = SERIAL.lock().unwrap();
+        register_shrinker(shrinker).unwrap();
+        let t1 = thread::spawn(|| alloc_or_shrink(1, alloc));
+        while CALLS.load(Ordering::Acquire) == 0 {
+            thread::yield_now();
+        }
+        // Waits for the running shrinker instead of failing.
+        let t2 = thread::spawn(|| alloc_or_shrink(1, alloc));
+        assert_eq!(t1.join().unwrap(), Ok(()));
+        assert_eq!(t2.join().unwrap(), Ok(()));
+        unregister_shrinker(shrinker);
+        assert_eq!(CALLS.load(Ordering::Acquire), 1);
+        assert_eq!(AVAILABLE.load(Ordering::Acquire), 0);
+    }
+}
@@ -0,0 +1,213 @@
+use core::mem::size_of;
+use core::ptr::NonNull;
+
+use allocator::{AllocError, AllocResult, BaseAllocator, BitmapPageAllocator, PageAllocator};
+
+use crate::{MemoryZone, PAGE_SIZE};
+
+type RegionAllocator = BitmapPageAllocator<PAGE_SIZE>;
+
+/// Maximum number of memory regions added by
+/// [`add_memory`](PageHeap::add_memory).
+const MAX_PAGE_REGIONS: usize = 16;
+/// Maximum size managed by a single bitmap, larger regions are split.
+const MAX_REGION_SIZE: usize = 1 << 30; // 1 G
+/// Virtual address where the [`MemoryZone::Dma32`] zone ends, in the linear
+/// mapping of the physical memory.
+///
+/// The zone is limited in bus addresses, which are translated from physical
+/// addresses by adding `PHYS_BUS_OFFSET` (see `axdma::phys_to_bus`).
+const DMA32_ZONE_END: usize = axconfig::PHYS_VIRT_OFFSET
+    + axconfig::DMA32_ZONE_END.saturating_sub(axconfig::PHYS_BUS_OFFSET);
+
+/// Size of the leading pages of an added region, which hold the page
+/// allocator of that region.
+const PAGE_REGION_HEADER_SIZE: usize = size_of::<RegionAllocator>().next_multiple_of(PAGE_SIZE);
+
+fn zone_of(addr: usize) -> MemoryZone {
+    if addr < DMA32_ZONE_END {
+        MemoryZone::Dma32
+    } else {
+        MemoryZone::Normal
+    }
+}
+
+/// Returns the end of the first piece of `[start, end)` which lies in a
+/// single zone and can be managed by a single bitmap.
+fn piece_end(start: usize, end: usize) -> usize {
+    let piece_end = end.min(start.saturating_add(MAX_REGION_SIZE));
+    if start < DMA32_ZONE_END {
+        piece_end.min(DMA32_ZONE_END)
+    } else {
+        piece_end
+    }
+}
+
+/// A discontiguous memory region added to the page heap.
+///
+/// It is managed by its own page allocator, which is stored at the beginning
+/// of the region.
+struct PageRegion {
+    start: usize,
+    end: usize,
+    zone: MemoryZone,
+    palloc: NonNull<RegionAllocator>,
+}
+
+// Safety: the page allocator is only accessed through `PageHeap`, which is
+// protected by a lock.
+unsafe impl Send for PageRegion {}
+
+impl PageRegion {
+    /// # Safety
+    ///
+    /// The region `[start, start + size)` must be valid, unused and
+    /// page-aligned, and larger than [`PAGE_REGION_HEADER_SIZE`].
+    unsafe fn new(start: usize, size: usize) -> Self {
+        let palloc = start as *mut RegionAllocator;
+        palloc.write(RegionAllocator::new());
+        (*palloc).init(
+            start + PAGE_REGION_HEADER_SIZE,
+            size - PAGE_REGION_HEADER_SIZE,
+        );
+        Self {
+            start,
+            end: start + size,
+            zone: zone_of(start),
+            palloc: NonNull::new_unchecked(palloc),
+        }
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+
+    fn palloc(&self) -> &RegionAllocator {
+        unsafe { self.palloc.as_ref() }
+    }
+
+    fn palloc_mut(&mut self) -> &mut RegionAllocator {
+        unsafe { self.palloc.as_mut() }
+    }
+}
+
+/// The page allocator of [`GlobalAllocator`](crate::GlobalAllocator).
+///
+/// It consists of a main page allocator, which holds the region passed to
+/// [`init`](PageHeap::init), and several discontiguous regions added by
+/// [`add_memory`](PageHeap::add_memory). Each of them lies in a single
+/// [`MemoryZone`].
+pub(crate) struct PageHeap {
+    main: RegionAllocator,
+    main_zone: MemoryZone,
+    regions: [Option<PageRegion>; MAX_PAGE_REGIONS],
+}
+
+impl PageHeap {
+    pub const fn new() -> Self {
+        Self {
+            main: RegionAllocator::new(),
+            main_zone: MemoryZone::Normal,
+            regions: [const { None }; MAX_PAGE_REGIONS],
+        }
+    }
+
+    pub fn init(&mut self, start: usize, size: usize) {
+        let end = start + size;
+        let main_end = piece_end(start, end);
+        self.main.init(start, main_end - start);
+        self.main_zone = zone_of(start);
+        if end > main_end {
+            if let Err(e) = self.add_memory(main_end, end - main_end) {
+                warn!("failed to add memory beyond {:#x}: {:?}", main_end, e);
+            }
+        }
+    }
+
+    /// Adds a discontiguous region to the page heap.
+    ///
+    /// The region is split at zone boundaries, or if it is too large for a
+    /// single bitmap. Pieces that are too small to hold their own page
+    /// allocator are ignored.
+    pub fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        let mut start = start.next_multiple_of(PAGE_SIZE);
+        let end = (start + size) & !(PAGE_SIZE - 1);
+        if end <= start + PAGE_REGION_HEADER_SIZE {
+            return Err(AllocError::InvalidParam);
+        }
+        while start < end {
+            let piece_end = piece_end(start, end);
+            if piece_end - start > PAGE_REGION_HEADER_SIZE {
+                let slot = self
+                    .regions
+                    .iter_mut()
+                    .find(|r| r.is_none())
+                    .ok_or(AllocError::NoMemory)?;
+                *slot = Some(unsafe { PageRegion::new(start, piece_end - start) });
+            }
+            start = piece_end;
+        }
+        Ok(())
+    }
+
+    fn allocators(&self) -> impl Iterator<Item = (MemoryZone, &RegionAllocator)> {
+        let regions = self.regions.iter().flatten();
+        core::iter::once((self.main_zone, &self.main))
+            .chain(regions.map(|r| (r.zone, r.palloc())))
+    }
+
+    fn allocators_mut(&mut self) -> impl Iterator<Item = (MemoryZone, &mut RegionAllocator)> {
+        let regions = self.regions.iter_mut().flatten();
+        core::iter::once((self.main_zone, &mut self.main))
+            .chain(regions.map(|r| (r.zone, r.palloc_mut())))
+    }
+
+    /// Allocates pages from the given zone.
+    ///
+    /// [`MemoryZone::Normal`] allocations fall back to the DMA32 zone only if
+    /// the normal zone is exhausted.
+    pub fn alloc_pages(
+        &mut self,
+        zone: MemoryZone,
+        num_pages: usize,
+        align_pow2: usize,
+    ) -> AllocResult<usize> {
+        let fallback = match zone {
+            MemoryZone::Normal => Some(MemoryZone::Dma32),
+            MemoryZone::Dma32 => None,
+        };
+        [Some(zone), fallback]
+            .into_iter()
+            .flatten()
+            .find_map(|zone| {
+                self.allocators_mut()
+                    .filter(|(z, _)| *z == zone)
+                    .find_map(|(_, palloc)| palloc.alloc_pages(num_pages, align_pow2).ok())
+            })
+            .ok_or(AllocError::NoMemory)
+    }
+
+    pub fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        match self.regions.iter_mut().flatten().find(|r| r.contains(pos)) {
+            Some(region) => region.palloc_mut().dealloc_pages(pos, num_pages),
+            None => self.main.dealloc_pages(pos, num_pages),
+        }
+    }
+
+    pub fn used_pages(&self) -> usize {
+        self.allocators().map(|(_, palloc)| palloc.used_pages()).sum()
+    }
+
+    pub fn available_pages(&self) -> usize {
+        self.allocators()
+            .map(|(_, palloc)| palloc.available_pages())
+            .sum()
+    }
+
+    pub fn available_pages_in(&self, zone: MemoryZone) -> usize {
+        self.allocators()
+            .filter(|(z, _)| *z == zone)
+            .map(|(_, palloc)| palloc.available_pages())
+            .sum()
+    }
+}
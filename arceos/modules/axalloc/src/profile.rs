@@ -0,0 +1,370 @@
+//! Per-call-site heap profiling (enabled by the `heap-profile` feature).
+//!
+//! When profiling is switched on at runtime, every byte allocation made
+//! through the global allocator is attributed to its call site (identified by
+//! a return address found by walking the frame pointers). For each call site,
+//! the allocation count, live bytes, peak bytes and a size histogram are
+//! recorded.
+//!
+//! The profiler never allocates memory itself. All records live in fixed-size
+//! tables, allocations that do not fit are counted as "untracked".
+//!
+//! Besides, every allocation and deallocation can be printed to the console
+//! (see [`set_heap_tracing`]), so that the workload can be replayed against
+//! different allocators by the `alloc_bench` tool on the host.
+//!
+//! Call sites can only be resolved if the kernel is built with
+//! `-C force-frame-pointers=yes`, which the build scripts add automatically
+//! when the `heap-profile` feature is selected. Without it, all allocations
+//! are attributed to the call site `0`.
+
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::caller::caller_addr;
+
+/// Maximum number of distinct call sites that can be recorded.
+const MAX_CALLERS: usize = 128;
+/// Maximum number of live allocations that can be tracked at the same time.
+const MAX_LIVE_ALLOCS: usize = 2048;
+
+/// Number of size classes in [`CallerStats::histogram`].
+///
+/// Class `i` counts allocations with size in `(16 << (i - 1), 16 << i]`, while
+/// class `0` counts allocations not larger than 16 bytes, and the last class
+/// also counts all larger allocations.
+pub const NUM_SIZE_CLASSES: usize = 16;
+
+static PROFILE_ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Whether there are recorded allocations whose deallocations still need to
+/// be tracked, even if profiling has been switched off.
+static HAS_RECORDS: AtomicBool = AtomicBool::new(false);
+static PROFILER: SpinNoIrq<Profiler> = SpinNoIrq::new(Profiler::new());
+
+/// Heap usage statistics of a single call site.
+#[derive(Debug, Clone, Copy)]
+pub struct CallerStats {
+    /// The return address identifying the call site.
+    pub caller: usize,
+    /// Number of allocations made at this call site.
+    pub allocs: usize,
+    /// Number of deallocations of memory allocated at this call site.
+    pub deallocs: usize,
+    /// Bytes allocated at this call site that are still in use.
+    pub live_bytes: usize,
+    /// The maximum value `live_bytes` has ever reached.
+    pub peak_bytes: usize,
+    /// Allocation counts by size class, see [`NUM_SIZE_CLASSES`].
+    pub histogram: [usize; NUM_SIZE_CLASSES],
+}
+
+/// Heap usage statistics summed over all call sites.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    /// Number of recorded allocations.
+    pub allocs: usize,
+    /// Number of recorded deallocations.
+    pub deallocs: usize,
+    /// Bytes in use by recorded allocations.
+    pub live_bytes: usize,
+    /// The maximum value `live_bytes` has ever reached.
+    pub peak_bytes: usize,
+    /// Number of allocations not recorded because the tables were full.
+    pub untracked: usize,
+}
+
+#[derive(Clone, Copy)]
+struct LiveAlloc {
+    ptr: usize,
+    size: usize,
+    caller_idx: usize,
+}
+
+struct Profiler {
+    callers: [CallerStats; MAX_CALLERS],
+    num_callers: usize,
+    /// Open addressing hash table (linear probing), `ptr == 0` means empty.
+    live: [LiveAlloc; MAX_LIVE_ALLOCS],
+    total: HeapStats,
+}
+
+impl CallerStats {
+    const fn empty() -> Self {
+        Self {
+            caller: 0,
+            allocs: 0,
+            deallocs: 0,
+            live_bytes: 0,
+            peak_bytes: 0,
+            histogram: [0; NUM_SIZE_CLASSES],
+        }
+    }
+}
+
+impl LiveAlloc {
+    const EMPTY: Self = Self {
+        ptr: 0,
+        size: 0,
+        caller_idx: 0,
+    };
+}
+
+impl Profiler {
+    const fn new() -> Self {
+        Self {
+            callers: [CallerStats::empty(); MAX_CALLERS],
+            num_callers: 0,
+            live: [LiveAlloc::EMPTY; MAX_LIVE_ALLOCS],
+            total: HeapStats {
+                allocs: 0,
+                deallocs: 0,
+                live_bytes: 0,
+                peak_bytes: 0,
+                untracked: 0,
+            },
+        }
+    }
+
+    fn reset(&mut self) {
+        self.callers[..self.num_callers].fill(CallerStats::empty());
+        self.num_callers = 0;
+        self.live.fill(LiveAlloc::EMPTY);
+        self.total = HeapStats::default();
+    }
+
+    fn caller_index(&mut self, caller: usize) -> Option<usize> {
+        let callers = &self.callers[..self.num_callers];
+        if let Some(idx) = callers.iter().position(|c| c.caller == caller) {
+            return Some(idx);
+        }
+        if self.num_callers == MAX_CALLERS {
+            return None;
+        }
+        let idx = self.num_callers;
+        self.callers[idx].caller = caller;
+        self.num_callers += 1;
+        Some(idx)
+    }
+
+    fn slot_of(ptr: usize) -> usize {
+        (ptr >> 3).wrapping_mul(0x9e37_79b9) % MAX_LIVE_ALLOCS
+    }
+
+    fn insert_live(&mut self, alloc: LiveAlloc) -> bool {
+        let mut slot = Self::slot_of(alloc.ptr);
+        for _ in 0..MAX_LIVE_ALLOCS {
+            if self.live[slot].ptr == 0 {
+                self.live[slot] = alloc;
+                return true;
+            }
+            slot = (slot + 1) % MAX_LIVE_ALLOCS;
+        }
+        false
+    }
+
+    fn remove_live(&mut self, ptr: usize) -> Option<LiveAlloc> {
+        let mut slot = Self::slot_of(ptr);
+        for _ in 0..MAX_LIVE_ALLOCS {
+            match self.live[slot].ptr {
+                0 => return None,
+                p if p == ptr => break,
+                _ => slot = (slot + 1) % MAX_LIVE_ALLOCS,
+            }
+        }
+        if self.live[slot].ptr != ptr {
+            return None;
+        }
+        let removed = self.live[slot];
+
+        // Backward shift deletion, keeps probe sequences intact without
+        // tombstones.
+        let mut hole = slot;
+        let mut next = slot;
+        loop {
+            next = (next + 1) % MAX_LIVE_ALLOCS;
+            let entry = self.live[next];
+            if entry.ptr == 0 {
+                break;
+            }
+            let home = Self::slot_of(entry.ptr);
+            // Keep the entry if its home slot lies cyclically in (hole, next].
+            let stays = if hole <= next {
+                hole < home && home <= next
+            } else {
+                hole < home || home <= next
+            };
+            if !stays {
+                self.live[hole] = entry;
+                hole = next;
+            }
+        }
+        self.live[hole] = LiveAlloc::EMPTY;
+        Some(removed)
+    }
+
+    fn record_alloc(&mut self, ptr: usize, size: usize, caller: usize) {
+        let Some(caller_idx) = self.caller_index(caller) else {
+            self.total.untracked += 1;
+            return;
+        };
+        if !self.insert_live(LiveAlloc {
+            ptr,
+            size,
+            caller_idx,
+        }) {
+            self.total.untracked += 1;
+            return;
+        }
+
+        let stats = &mut self.callers[caller_idx];
+        stats.allocs += 1;
+        stats.live_bytes += size;
+        stats.peak_bytes = stats.peak_bytes.max(stats.live_bytes);
+        stats.histogram[size_class(size)] += 1;
+
+        let total = &mut self.total;
+        total.allocs += 1;
+        total.live_bytes += size;
+        total.peak_bytes = total.peak_bytes.max(total.live_bytes);
+    }
+
+    fn record_dealloc(&mut self, ptr: usize) {
+        // Memory allocated while profiling is disabled is not tracked.
+        if let Some(alloc) = self.remove_live(ptr) {
+            let stats = &mut self.callers[alloc.caller_idx];
+            stats.deallocs += 1;
+            stats.live_bytes -= alloc.size;
+            self.total.deallocs += 1;
+            self.total.live_bytes -= alloc.size;
+        }
+    }
+}
+
+fn size_class(size: usize) -> usize {
+    let class = size.max(1).next_power_of_two().trailing_zeros() as usize;
+    class.saturating_sub(4).min(NUM_SIZE_CLASSES - 1)
+}
+
+/// Records an allocation made through the global allocator.
+pub(crate) fn record_alloc(ptr: usize, layout: Layout) {
+    if TRACE_ENABLED.load(Ordering::Relaxed) {
+        axlog::ax_println!(
+            "[heap-trace] a {:#x} {} {}",
+            ptr,
+            layout.size(),
+            layout.align()
+        );
+    }
+    if PROFILE_ENABLED.load(Ordering::Relaxed) {
+        let caller = caller_addr();
+        PROFILER.lock().record_alloc(ptr, layout.size(), caller);
+        HAS_RECORDS.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Records a deallocation made through the global allocator.
+pub(crate) fn record_dealloc(ptr: usize) {
+    if TRACE_ENABLED.load(Ordering::Relaxed) {
+        axlog::ax_println!("[heap-trace] f {:#x}", ptr);
+    }
+    if HAS_RECORDS.load(Ordering::Relaxed) {
+        PROFILER.lock().record_dealloc(ptr);
+    }
+}
+
+/// Switches heap profiling on or off at runtime.
+///
+/// It is off by default. Allocations made while profiling is off are never
+/// recorded, while deallocations of recorded memory are always tracked.
+pub fn set_heap_profiling(enabled: bool) {
+    PROFILE_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Switches heap tracing on or off at runtime.
+///
+/// It is off by default. When it is on, every allocation and deallocation
+/// made through the global allocator is printed to the console as a line
+/// starting with `[heap-trace]`. A console log containing these lines can be
+/// replayed by the `alloc_bench` tool.
+pub fn set_heap_tracing(enabled: bool) {
+    TRACE_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether heap profiling is currently switched on.
+pub fn heap_profiling_enabled() -> bool {
+    PROFILE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Clears all recorded statistics.
+pub fn reset_heap_profile() {
+    let mut profiler = PROFILER.lock();
+    profiler.reset();
+    HAS_RECORDS.store(false, Ordering::Relaxed);
+}
+
+/// Returns the statistics summed over all call sites.
+pub fn heap_stats() -> HeapStats {
+    PROFILER.lock().total
+}
+
+/// Calls `f` on the statistics of each recorded call site.
+///
+/// Each entry is copied out before `f` is called, so `f` is free to allocate.
+pub fn for_each_caller<F: FnMut(&CallerStats)>(mut f: F) {
+    let mut idx = 0;
+    loop {
+        let stats = {
+            let profiler = PROFILER.lock();
+            if idx >= profiler.num_callers {
+                break;
+            }
+            profiler.callers[idx]
+        };
+        f(&stats);
+        idx += 1;
+    }
+}
+
+/// Prints a report of the recorded statistics to the console.
+pub fn dump_heap_profile() {
+    let total = heap_stats();
+    axlog::ax_println!(
+        "heap profile: {} allocs, {} deallocs, live {} bytes, peak {} bytes, {} untracked",
+        total.allocs,
+        total.deallocs,
+        total.live_bytes,
+        total.peak_bytes,
+        total.untracked,
+    );
+    axlog::ax_println!(
+        "{:>18} {:>10} {:>10} {:>12} {:>12}",
+        "caller",
+        "allocs",
+        "deallocs",
+        "live",
+        "peak"
+    );
+    for_each_caller(|stats| {
+        axlog::ax_println!(
+            "{:>#18x} {:>10} {:>10} {:>12} {:>12}",
+            stats.caller,
+            stats.allocs,
+            stats.deallocs,
+            stats.live_bytes,
+            stats.peak_bytes,
+        );
+        for (class, &count) in stats.histogram.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            if class == NUM_SIZE_CLASSES - 1 {
+                axlog::ax_println!("{:>20} > {:<10} {}", "", 16usize << (class - 1), count);
+            } else {
+                axlog::ax_println!("{:>20} <= {:<9} {}", "", 16usize << class, count);
+            }
+        }
+    });
+}
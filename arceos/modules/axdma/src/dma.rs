@@ -1,7 +1,7 @@
 use core::{alloc::Layout, ptr::NonNull};
 
 use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator};
-use axalloc::{global_allocator, DefaultByteAllocator};
+use axalloc::{global_allocator, DefaultByteAllocator, MemoryZone};
 use axhal::{mem::virt_to_phys, paging::MappingFlags};
 use kspin::SpinNoIrq;
 use log::{debug, error};
@@ -50,11 +50,15 @@ impl DmaAllocator {
                     return Err(AllocError::NoMemory);
                 }
                 is_expanded = true;
-                let available_pages = global_allocator().available_pages();
+                let available_pages = global_allocator().available_pages_in(MemoryZone::Dma32);
                 // 4 pages or available pages.
                 let num_pages = 4.min(available_pages);
                 let expand_size = num_pages * PAGE_SIZE_4K;
-                let vaddr_raw = global_allocator().alloc_pages(num_pages, PAGE_SIZE_4K)?;
+                let vaddr_raw = global_allocator().alloc_pages_in(
+                    MemoryZone::Dma32,
+                    num_pages,
+                    PAGE_SIZE_4K,
+                )?;
                 let vaddr = va!(vaddr_raw);
                 self.update_flags(
                     vaddr,
@@ -71,8 +75,11 @@ impl DmaAllocator {
 
     fn alloc_coherent_pages(&mut self, layout: Layout) -> AllocResult<DMAInfo> {
         let num_pages = layout_pages(&layout);
-        let vaddr_raw =
-            global_allocator().alloc_pages(num_pages, PAGE_SIZE_4K.max(layout.align()))?;
+        let vaddr_raw = global_allocator().alloc_pages_in(
+            MemoryZone::Dma32,
+            num_pages,
+            PAGE_SIZE_4K.max(layout.align()),
+        )?;
         let vaddr = va!(vaddr_raw);
         self.update_flags(
             vaddr,
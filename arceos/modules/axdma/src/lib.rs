@@ -28,6 +28,8 @@ pub const fn phys_to_bus(paddr: PhysAddr) -> BusAddr {
 ///
 /// This function allocates a block of memory through the global allocator. The memory pages must be contiguous, undivided, and have consistent read and write access.
 ///
+/// The memory is allocated from the DMA32 zone, so that its bus address fits in 32 bits.
+///
 /// - `layout`: The memory layout, which describes the size and alignment requirements of the requested memory.
 ///
 /// Returns an [`DMAInfo`] structure containing details about the allocated memory, such as the starting address and size. If it's not possible to allocate memory meeting the criteria, returns [`None`].
@@ -162,6 +162,14 @@ impl File {
     pub fn metadata(&self) -> Result<Metadata> {
         self.inner.get_attr().map(Metadata)
     }
+
+    /// Reads some bytes into `buf` like [`Read::read`], but in chunks of a
+    /// few blocks, and yields to other futures between them, so that a large
+    /// read does not hold up the others on the same executor.
+    #[cfg(feature = "multitask")]
+    pub async fn read_async(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.inner.read_async(buf).await
+    }
 }
 
 impl Read for File {
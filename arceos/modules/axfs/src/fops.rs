@@ -20,6 +20,11 @@ pub type FileAttr = axfs_vfs::VfsNodeAttr;
 /// Alias of [`axfs_vfs::VfsNodePerm`].
 pub type FilePerm = axfs_vfs::VfsNodePerm;
 
+/// The bytes read at a time by [`File::read_async`], before it yields to
+/// other futures.
+#[cfg(feature = "multitask")]
+const ASYNC_READ_CHUNK_SIZE: usize = 4096;
+
 /// An opened file object, with open permissions and a cursor.
 pub struct File {
     node: WithCap<VfsNodeRef>,
@@ -186,6 +191,29 @@ impl File {
         Ok(read_len)
     }
 
+    /// Reads the file at the current position like [`read`](Self::read), but
+    /// in chunks of a few blocks, and yields to other futures between them, so
+    /// that a large read does not hold up the others on the same executor.
+    #[cfg(feature = "multitask")]
+    pub async fn read_async(&mut self, buf: &mut [u8]) -> AxResult<usize> {
+        let mut read_len = 0;
+        for (i, chunk) in buf.chunks_mut(ASYNC_READ_CHUNK_SIZE).enumerate() {
+            if i > 0 {
+                axtask::future::yield_now().await;
+            }
+            let len = match self.read(chunk) {
+                Ok(len) => len,
+                Err(e) if read_len == 0 => return Err(e),
+                Err(_) => break, // return what has been read
+            };
+            read_len += len;
+            if len < chunk.len() {
+                break; // end of file
+            }
+        }
+        Ok(read_len)
+    }
+
     /// Reads the file at the given position. Returns the number of bytes read.
     ///
     /// It does not update the file cursor.
@@ -0,0 +1,234 @@
+//! Task information in procfs, i.e., `/proc/<tid>/status`.
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::fmt::Write;
+
+use axfs_ramfs::{DirNode, RamFileSystem};
+use axfs_vfs::{VfsDirEntry, VfsError, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef};
+use axfs_vfs::{VfsNodeType, VfsOps, VfsResult};
+use axtask::TaskState;
+
+/// The procfs: the files of a RAM filesystem, plus a directory for each live
+/// task.
+pub struct ProcFileSystem {
+    inner: RamFileSystem,
+    root: Arc<ProcRootDir>,
+}
+
+impl ProcFileSystem {
+    /// Creates a procfs with the static files in `inner`.
+    pub fn new(inner: RamFileSystem) -> Self {
+        let root = Arc::new(ProcRootDir {
+            inner: inner.root_dir_node(),
+        });
+        Self { inner, root }
+    }
+}
+
+impl VfsOps for ProcFileSystem {
+    fn mount(&self, path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        self.inner.mount(path, mount_point)
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+}
+
+fn split_path(path: &str) -> (&str, Option<&str>) {
+    let path = path.trim_start_matches('/');
+    match path.split_once('/') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (path, None),
+    }
+}
+
+fn lookup_rest(node: VfsNodeRef, rest: Option<&str>) -> VfsResult<VfsNodeRef> {
+    match rest {
+        Some(rest) => node.lookup(rest),
+        None => Ok(node),
+    }
+}
+
+fn fill_dir_entries(
+    entries: &[(&str, VfsNodeType)],
+    start_idx: usize,
+    dirents: &mut [VfsDirEntry],
+) -> usize {
+    let entries = entries.iter().skip(start_idx);
+    let mut n = 0;
+    for (dirent, (name, ty)) in dirents.iter_mut().zip(entries) {
+        *dirent = VfsDirEntry::new(name, *ty);
+        n += 1;
+    }
+    n
+}
+
+/// The root directory of procfs.
+struct ProcRootDir {
+    inner: Arc<DirNode>,
+}
+
+impl VfsNodeOps for ProcRootDir {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.inner.get_attr()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.inner.parent()
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let (name, rest) = split_path(path);
+        if matches!(name, "" | ".") {
+            return lookup_rest(self, rest);
+        }
+        match name.parse::<u64>() {
+            Ok(tid) if axtask::find_task(tid).is_some() => {
+                lookup_rest(Arc::new(TaskDir { tid, parent: self }), rest)
+            }
+            _ => self.inner.clone().lookup(path),
+        }
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let static_names = self.inner.get_entries();
+        let tids: Vec<String> = axtask::all_tasks()
+            .map(|task| format!("{}", task.id().as_u64()))
+            .collect();
+
+        let mut entries = Vec::with_capacity(2 + static_names.len() + tids.len());
+        entries.push((".", VfsNodeType::Dir));
+        entries.push(("..", VfsNodeType::Dir));
+        for name in &static_names {
+            let ty = self.inner.clone().lookup(name)?.get_attr()?.file_type();
+            entries.push((name.as_str(), ty));
+        }
+        entries.extend(tids.iter().map(|tid| (tid.as_str(), VfsNodeType::Dir)));
+        Ok(fill_dir_entries(&entries, start_idx, dirents))
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        self.inner.create(path, ty)
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        self.inner.remove(path)
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        self.inner.rename(src_path, dst_path)
+    }
+}
+
+/// The directory `/proc/<tid>`.
+struct TaskDir {
+    tid: u64,
+    parent: Arc<ProcRootDir>,
+}
+
+impl VfsNodeOps for TaskDir {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let perm = VfsNodePerm::from_bits_truncate(0o555);
+        Ok(VfsNodeAttr::new(perm, VfsNodeType::Dir, 0, 0))
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        Some(self.parent.clone())
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let (name, rest) = split_path(path);
+        let node: VfsNodeRef = match name {
+            "" | "." => self,
+            ".." => self.parent.clone(),
+            "status" => Arc::new(TaskStatusFile { tid: self.tid }),
+            _ => return Err(VfsError::NotFound),
+        };
+        lookup_rest(node, rest)
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let entries = [
+            (".", VfsNodeType::Dir),
+            ("..", VfsNodeType::Dir),
+            ("status", VfsNodeType::File),
+        ];
+        Ok(fill_dir_entries(&entries, start_idx, dirents))
+    }
+}
+
+/// The file `/proc/<tid>/status`, whose content is generated on each read.
+struct TaskStatusFile {
+    tid: u64,
+}
+
+impl TaskStatusFile {
+    fn content(&self) -> VfsResult<String> {
+        let task = axtask::find_task(self.tid).ok_or(VfsError::NotFound)?;
+        let info = task.info();
+        let state = match info.state {
+            TaskState::Running => "R (running)",
+            TaskState::Ready => "R (ready)",
+            TaskState::Blocked => "S (blocked)",
+            TaskState::Exited => "Z (exited)",
+        };
+
+        let mut s = String::new();
+        // Writing to a `String` never fails.
+        let _ = write!(
+            s,
+            "Name:\t{}\nState:\t{}\nPid:\t{}\nCpu:\t{}\nPriority:\t{}\nRuntime:\t{} us\n",
+            info.name,
+            state,
+            self.tid,
+            info.cpu_id,
+            info.priority,
+            info.runtime.as_micros(),
+        );
+        let stats = &info.stats;
+        let _ = write!(
+            s,
+            "Utime:\t{} us\nStime:\t{} us\nWaitTime:\t{} us\n\
+             VoluntarySwitches:\t{}\nInvoluntarySwitches:\t{}\n",
+            stats.utime.as_micros(),
+            stats.stime.as_micros(),
+            stats.wait_time.as_micros(),
+            stats.voluntary_switches,
+            stats.involuntary_switches,
+        );
+        if let Some(exit_code) = info.exit_code {
+            let _ = writeln!(s, "ExitCode:\t{}", exit_code);
+        }
+        let _ = write!(
+            s,
+            "StackSize:\t{} kB\nStackHighWater:\t{} kB\n",
+            info.stack_size / 1024,
+            info.stack_high_water.div_ceil(1024),
+        );
+        Ok(s)
+    }
+}
+
+impl VfsNodeOps for TaskStatusFile {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let perm = VfsNodePerm::from_bits_truncate(0o444);
+        let size = self.content()?.len() as u64;
+        Ok(VfsNodeAttr::new(perm, VfsNodeType::File, size, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let content = self.content()?;
+        let start = content.len().min(offset as usize);
+        let src = &content.as_bytes()[start..];
+        let len = src.len().min(buf.len());
+        buf[..len].copy_from_slice(&src[..len]);
+        Ok(len)
+    }
+}
@@ -252,6 +252,45 @@ fn test_devfs_ramfs() -> Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "multitask")]
+fn test_read_async() -> Result<()> {
+    use axtask::future::Executor;
+    use std::sync::{Arc, Mutex};
+
+    const SIZE: usize = 3 * 4096 + 100; // several chunks of `read_async`
+    const NUM_READERS: usize = 4;
+    let fname = "/async.txt";
+    println!("read file {:?} asynchronously:", fname);
+
+    let data = (0..SIZE).map(|i| i as u8).collect::<Vec<_>>();
+    fs::write(fname, &data)?;
+
+    // The reads yield between chunks, and take turns on the executor.
+    let executor = Executor::new();
+    let results = Arc::new(Mutex::new(Vec::new()));
+    for _ in 0..NUM_READERS {
+        let results = results.clone();
+        executor.spawn(async move {
+            let mut file = File::open(fname).unwrap();
+            let mut buf = vec![0; SIZE + 1];
+            let len = file.read_async(&mut buf).await.unwrap();
+            buf.truncate(len);
+            assert_eq!(file.read_async(&mut [0; 16]).await.unwrap(), 0); // EOF
+            results.lock().unwrap().push(buf);
+        });
+    }
+    executor.run();
+    assert_eq!(executor.pending(), 0);
+
+    let results = results.lock().unwrap();
+    assert_eq!(results.len(), NUM_READERS);
+    assert!(results.iter().all(|buf| *buf == data));
+    fs::remove_file(fname)?;
+
+    println!("test_read_async() OK!");
+    Ok(())
+}
+
 pub fn test_all() {
     test_read_write_file().expect("test_read_write_file() failed");
     test_read_dir().expect("test_read_dir() failed");
@@ -259,4 +298,6 @@ pub fn test_all() {
     test_create_file_dir().expect("test_create_file_dir() failed");
     test_remove_file_dir().expect("test_remove_file_dir() failed");
     test_devfs_ramfs().expect("test_devfs_ramfs() failed");
+    #[cfg(feature = "multitask")]
+    test_read_async().expect("test_read_async() failed");
 }
@@ -38,6 +38,19 @@ pub fn wait_for_irqs() {
     riscv::asm::wfi()
 }
 
+/// Allows the current CPU to respond to interrupts, and waits for them at
+/// once.
+///
+/// It is called with interrupts disabled. Unlike [`enable_irqs`] followed by
+/// [`wait_for_irqs`], an interrupt that arrives in between still ends the
+/// wait, so that it is not handled before the wait and missed.
+#[inline]
+pub fn enable_irqs_and_wait() {
+    // A pending interrupt wakes the CPU up even if it is masked.
+    riscv::asm::wfi();
+    enable_irqs();
+}
+
 /// Halt the current CPU.
 #[inline]
 pub fn halt() {
@@ -13,7 +13,7 @@ use x86_64::instructions::interrupts;
 
 pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame};
 pub use self::gdt::GdtStruct;
-pub use self::idt::IdtStruct;
+pub use self::idt::{IdtStruct, DOUBLE_FAULT_IST_INDEX};
 pub use x86_64::structures::tss::TaskStateSegment;
 
 /// Allows the current CPU to respond to interrupts.
@@ -48,6 +48,22 @@ pub fn wait_for_irqs() {
     }
 }
 
+/// Allows the current CPU to respond to interrupts, and waits for them at
+/// once.
+///
+/// It is called with interrupts disabled. Unlike [`enable_irqs`] followed by
+/// [`wait_for_irqs`], an interrupt that arrives in between still ends the
+/// wait, so that it is not handled before the wait and missed.
+#[inline]
+pub fn enable_irqs_and_wait() {
+    if cfg!(target_os = "none") {
+        // `sti` takes effect after the next instruction.
+        unsafe { asm!("sti; hlt") }
+    } else {
+        core::hint::spin_loop()
+    }
+}
+
 /// Halt the current CPU.
 #[inline]
 pub fn halt() {
@@ -14,6 +14,9 @@ fn handle_page_fault(tf: &TrapFrame) {
         .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
     let vaddr = va!(unsafe { cr2() });
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
+        if !tf.is_user() {
+            report_unhandled_fault!(vaddr);
+        }
         panic!(
             "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
             if tf.is_user() { "user" } else { "kernel" },
@@ -26,10 +29,20 @@ fn handle_page_fault(tf: &TrapFrame) {
     }
 }
 
+/// Handles double faults on a separate stack, see [`super::DOUBLE_FAULT_IST_INDEX`].
+fn handle_double_fault(tf: &TrapFrame) -> ! {
+    // Kernel stack overflows end up here, as the page fault cannot be handled
+    // on the overflowed stack. The faulting address tells which stack it is.
+    let vaddr = va!(unsafe { cr2() });
+    report_unhandled_fault!(vaddr);
+    panic!("#DF @ {:#x}, fault_vaddr={:#x}:\n{:#x?}", tf.rip, vaddr, tf);
+}
+
 #[no_mangle]
 fn x86_trap_handler(tf: &TrapFrame) {
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
+        DOUBLE_FAULT_VECTOR => handle_double_fault(tf),
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
         GENERAL_PROTECTION_FAULT_VECTOR => {
             panic!(
@@ -1,7 +1,17 @@
 //! Description tables (per-CPU GDT, per-CPU ISS, IDT)
 
-use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment};
+use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment, DOUBLE_FAULT_IST_INDEX};
 use lazyinit::LazyInit;
+use x86_64::VirtAddr;
+
+/// Size of the stack to handle double faults on each CPU.
+const DOUBLE_FAULT_STACK_SIZE: usize = 0x4000;
+
+#[repr(align(16))]
+struct DoubleFaultStack([u8; DOUBLE_FAULT_STACK_SIZE]);
+
+static mut DOUBLE_FAULT_STACKS: [DoubleFaultStack; axconfig::SMP] =
+    [const { DoubleFaultStack([0; DOUBLE_FAULT_STACK_SIZE]) }; axconfig::SMP];
 
 static IDT: LazyInit<IdtStruct> = LazyInit::new();
 
@@ -16,7 +26,11 @@ fn init_percpu() {
         IDT.load();
         let tss = TSS.current_ref_mut_raw();
         let gdt = GDT.current_ref_mut_raw();
-        tss.init_once(TaskStateSegment::new());
+        let stack = core::ptr::addr_of!(DOUBLE_FAULT_STACKS[crate::cpu::this_cpu_id()]);
+        let mut new_tss = TaskStateSegment::new();
+        new_tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::new((stack as usize + DOUBLE_FAULT_STACK_SIZE) as u64);
+        tss.init_once(new_tss);
         gdt.init_once(GdtStruct::new(tss));
         gdt.load();
         gdt.load_tss();
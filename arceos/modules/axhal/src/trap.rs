@@ -17,6 +17,12 @@ pub static IRQ: [fn(usize) -> bool];
 #[def_trap_handler]
 pub static PAGE_FAULT: [fn(VirtAddr, MappingFlags, bool) -> bool];
 
+/// A slice of functions called with the faulting address of a kernel fault
+/// that is not handled, right before the panic. They can panic with a more
+/// helpful message, e.g., on an overflow into a guard page.
+#[def_trap_handler]
+pub static UNHANDLED_FAULT: [fn(VirtAddr)];
+
 /// A slice of syscall handler functions.
 #[cfg(feature = "uspace")]
 #[def_trap_handler]
@@ -38,6 +44,15 @@ macro_rules! handle_trap {
     }}
 }
 
+#[allow(unused_macros)]
+macro_rules! report_unhandled_fault {
+    ($vaddr:expr) => {
+        for func in $crate::trap::UNHANDLED_FAULT.iter() {
+            func($vaddr);
+        }
+    };
+}
+
 /// Call the external syscall handler.
 #[cfg(feature = "uspace")]
 pub(crate) fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
@@ -11,12 +11,13 @@ mod backend;
 
 pub use self::aspace::AddrSpace;
 
+use axalloc::global_allocator;
 use axerrno::{AxError, AxResult};
-use axhal::mem::phys_to_virt;
-use axhal::paging::PagingError;
+use axhal::mem::{phys_to_virt, virt_to_phys, PAGE_SIZE_4K};
+use axhal::paging::{MappingFlags, PagingError};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
-use memory_addr::{va, PhysAddr, VirtAddr};
+use memory_addr::{align_up_4k, va, PhysAddr, VirtAddr};
 use memory_set::MappingError;
 
 const USER_ASPACE_BASE: usize = 0x0000;
@@ -86,6 +87,68 @@ pub fn init_memory_management() {
     axhal::paging::set_kernel_page_table_root(kernel_page_table_root());
 }
 
+/// Unmaps the page at `vaddr` from the linear mapping to make it a guard
+/// page, or maps it back.
+fn set_guard_page(aspace: &mut AddrSpace, vaddr: VirtAddr, guard: bool) -> AxResult {
+    if guard {
+        aspace.unmap(vaddr, PAGE_SIZE_4K)
+    } else {
+        let flags = MappingFlags::READ | MappingFlags::WRITE;
+        aspace.map_linear(vaddr, virt_to_phys(vaddr), PAGE_SIZE_4K, flags)
+    }
+}
+
+struct GuardPageIfImpl;
+
+#[crate_interface::impl_interface]
+impl axalloc::GuardPageIf for GuardPageIfImpl {
+    fn set_guard_page(vaddr: usize, guard: bool) -> bool {
+        if !KERNEL_ASPACE.is_inited() {
+            return false;
+        }
+        // The allocator may be called with the kernel address space locked.
+        let Some(mut aspace) = KERNEL_ASPACE.try_lock() else {
+            return false;
+        };
+        set_guard_page(&mut aspace, VirtAddr::from(vaddr), guard).is_ok()
+    }
+}
+
+/// Allocates a kernel stack of `size` bytes from the global allocator, with
+/// a guard page below it, which is unmapped from the linear mapping.
+///
+/// The stack itself stays in the linear mapping and is physically contiguous,
+/// so that buffers on it can be passed to devices for DMA. Returns the lowest
+/// address of the stack. The size is rounded up to 4K.
+///
+/// With multiple CPUs, other CPUs may still have the guard page in their TLBs
+/// and miss overflows into it, as there is no TLB shootdown yet.
+pub fn alloc_kernel_stack(size: usize) -> AxResult<VirtAddr> {
+    if !KERNEL_ASPACE.is_inited() {
+        return Err(AxError::BadState);
+    }
+    let num_pages = align_up_4k(size) / PAGE_SIZE_4K + 1;
+    let guard = global_allocator()
+        .alloc_pages(num_pages, PAGE_SIZE_4K)
+        .map_err(|_| AxError::NoMemory)?;
+    let guard = va!(guard);
+    if let Err(e) = set_guard_page(&mut KERNEL_ASPACE.lock(), guard, true) {
+        global_allocator().dealloc_pages(guard.as_usize(), num_pages);
+        return Err(e);
+    }
+    Ok(guard + PAGE_SIZE_4K)
+}
+
+/// Frees a kernel stack allocated by [`alloc_kernel_stack`], after mapping
+/// its guard page back.
+pub fn dealloc_kernel_stack(bottom: VirtAddr, size: usize) {
+    let num_pages = align_up_4k(size) / PAGE_SIZE_4K + 1;
+    let guard = bottom - PAGE_SIZE_4K;
+    set_guard_page(&mut KERNEL_ASPACE.lock(), guard, false)
+        .expect("failed to map the guard page of kernel stack back");
+    global_allocator().dealloc_pages(guard.as_usize(), num_pages);
+}
+
 /// Initializes kernel paging for secondary CPUs.
 pub fn init_memory_management_secondary() {
     unsafe { axhal::arch::write_page_table_root(kernel_page_table_root()) };
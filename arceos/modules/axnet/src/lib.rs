@@ -14,6 +14,10 @@
 //!
 //! - `smoltcp`: Use [smoltcp] as the underlying network stack. This is enabled
 //!   by default.
+//! - `multitask`: Add async versions of the blocking [`TcpSocket`] methods,
+//!   which run on the async runtime of `axtask`.
+//! - `irq`: Let the async methods sleep between polls of the NIC, instead of
+//!   yielding to other futures, while no other socket polls it.
 //!
 //! [smoltcp]: https://github.com/smoltcp-rs/smoltcp
 
@@ -8,6 +8,8 @@ mod udp;
 use alloc::vec;
 use core::cell::RefCell;
 use core::ops::DerefMut;
+#[cfg(feature = "multitask")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use axdriver::prelude::*;
 use axdriver_net::{DevError, NetBufPtr};
@@ -38,6 +40,11 @@ macro_rules! env_or_default {
 const IP: &str = env_or_default!("AX_IP");
 const GATEWAY: &str = env_or_default!("AX_GW");
 const DNS_SEVER: &str = "8.8.8.8";
+
+/// How long an async method waits at most for other sockets to poll the
+/// interfaces, before it polls them itself.
+#[cfg(all(feature = "multitask", feature = "irq"))]
+const ASYNC_POLL_INTERVAL: core::time::Duration = core::time::Duration::from_millis(10);
 const IP_PREFIX: u8 = 24;
 
 const STANDARD_MTU: usize = 1500;
@@ -54,6 +61,13 @@ static LISTEN_TABLE: LazyInit<ListenTable> = LazyInit::new();
 static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 static ETH0: LazyInit<InterfaceWrapper> = LazyInit::new();
 
+/// The number of times the interfaces have been polled, see [`wait_for_poll`].
+#[cfg(feature = "multitask")]
+static POLL_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// The futures waiting for the interfaces to be polled.
+#[cfg(feature = "multitask")]
+static POLL_WQ: axtask::WaitQueue = axtask::WaitQueue::new();
+
 struct SocketSetWrapper<'a>(Mutex<SocketSet<'a>>);
 
 struct DeviceWrapper {
@@ -121,6 +135,11 @@ impl<'a> SocketSetWrapper<'a> {
 
     pub fn poll_interfaces(&self) {
         ETH0.poll(&self.0);
+        #[cfg(feature = "multitask")]
+        {
+            POLL_COUNT.fetch_add(1, Ordering::Release);
+            POLL_WQ.notify_all(false);
+        }
     }
 
     pub fn remove(&self, handle: SocketHandle) {
@@ -300,6 +319,39 @@ pub fn poll_interfaces() {
     SOCKET_SET.poll_interfaces();
 }
 
+/// Returns the number of times the interfaces have been polled.
+#[cfg(feature = "multitask")]
+fn poll_count() -> usize {
+    POLL_COUNT.load(Ordering::Acquire)
+}
+
+/// Waits until the interfaces are polled by others since [`poll_count`]
+/// returned `count`, which wakes up the futures waiting for them. The NIC is
+/// polled rather than interrupt-driven, so it gives up after a while with
+/// `irq`, or yields to other futures without it.
+///
+/// Returns `false` if it gives up, then the caller should poll the interfaces
+/// itself.
+#[cfg(feature = "multitask")]
+async fn wait_for_poll(count: usize) -> bool {
+    use core::future::Future;
+    use core::task::Poll;
+
+    let mut polled = core::pin::pin!(POLL_WQ.wait_until_async(|| poll_count() != count));
+    #[cfg(feature = "irq")]
+    let mut give_up = core::pin::pin!(axtask::future::sleep(ASYNC_POLL_INTERVAL));
+    #[cfg(not(feature = "irq"))]
+    let mut give_up = core::pin::pin!(axtask::future::yield_now());
+    core::future::poll_fn(|cx| {
+        if polled.as_mut().poll(cx).is_ready() {
+            Poll::Ready(true)
+        } else {
+            give_up.as_mut().poll(cx).map(|()| false)
+        }
+    })
+    .await
+}
+
 /// Benchmark raw socket transmit bandwidth.
 pub fn bench_transmit() {
     ETH0.dev.lock().bench_transmit_bandwidth();
@@ -6,7 +6,14 @@
 //! # Cargo Features
 //!
 //! - `alloc`: Enable global memory allocator.
-//! - `paging`: Enable page table manipulation support.
+//! - `alt_alloc`: Use the bump allocator as the global memory allocator during
+//!   boot, and a buddy allocator after that.
+//! - `debug-heap`: Enable the debug mode of the global memory allocator. With
+//!   `paging`, guard pages are placed after large allocations once the kernel
+//!   address space is set up, and overflows into them are reported on page
+//!   faults.
+//! - `paging`: Enable page table manipulation support. With `multitask`, kernel
+//!   stack overflows are reported on x86_64, which has stack guard pages.
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
@@ -25,6 +32,7 @@ extern crate axlog;
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
 
+
 #[cfg(feature = "smp")]
 mod mp;
 
@@ -141,12 +149,15 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         );
     }
 
-    #[cfg(any(feature = "alloc", feature = "alt_alloc"))]
+    #[cfg(feature = "alloc")]
     init_allocator();
 
     #[cfg(feature = "paging")]
     axmm::init_memory_management();
 
+    #[cfg(all(feature = "debug-heap", feature = "paging"))]
+    axalloc::set_guard_pages(true);
+
     info!("Initialize platform devices...");
     axhal::platform_init();
 
@@ -183,6 +194,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         init_tls();
     }
 
+    #[cfg(feature = "alt_alloc")]
+    alt_axalloc::release_free_region().expect("release early allocator region failed");
+
     info!("Primary CPU {} init OK.", cpu_id);
     INITED_CPUS.fetch_add(1, Ordering::Relaxed);
 
@@ -230,33 +244,22 @@ fn init_allocator() {
     }
 }
 
-#[cfg(feature = "alt_alloc")]
-fn init_allocator() {
-    use axhal::mem::{memory_regions, phys_to_virt, MemRegionFlags};
-
-    info!("Initialize global memory allocator...");
-    info!("  use {} allocator.", alt_axalloc::global_allocator().name());
-
-    let mut max_region_size = 0;
-    let mut max_region_paddr = 0.into();
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.size > max_region_size {
-            max_region_size = r.size;
-            max_region_paddr = r.paddr;
-        }
-    }
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.paddr == max_region_paddr {
-            alt_axalloc::global_init(phys_to_virt(r.paddr).as_usize(), r.size);
-            break;
-        }
-    }
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.paddr != max_region_paddr {
-            alt_axalloc::global_add_memory(phys_to_virt(r.paddr).as_usize(), r.size)
-                .expect("add heap memory region failed");
-        }
+/// Reports overflows into guard pages of kernel stacks or heap allocations,
+/// on kernel faults that are not handled.
+#[cfg(all(feature = "paging", any(feature = "multitask", feature = "debug-heap")))]
+#[axhal::trap::register_trap_handler(axhal::trap::UNHANDLED_FAULT)]
+fn report_guard_page_fault(vaddr: axhal::mem::VirtAddr) {
+    #[cfg(feature = "multitask")]
+    if axtask::check_stack_guard(vaddr.as_usize()) {
+        // Do not allocate, the heap may be locked by the faulting code.
+        panic!(
+            "kernel stack overflow in {:?}: fault_vaddr={:#x}",
+            axtask::current().name(),
+            vaddr
+        );
     }
+    #[cfg(feature = "debug-heap")]
+    axalloc::report_guard_page_fault(vaddr.as_usize());
 }
 
 #[cfg(feature = "irq")]
@@ -264,12 +267,15 @@ fn init_interrupt() {
     use axhal::time::TIMER_IRQ_NUM;
 
     // Setup timer interrupt handler
+    #[cfg(not(feature = "multitask"))]
     const PERIODIC_INTERVAL_NANOS: u64 =
         axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
 
+    #[cfg(not(feature = "multitask"))]
     #[percpu::def_percpu]
     static NEXT_DEADLINE: u64 = 0;
 
+    #[cfg(not(feature = "multitask"))]
     fn update_timer() {
         let now_ns = axhal::time::monotonic_time_nanos();
         // Safety: we have disabled preemption in IRQ handler.
@@ -282,11 +288,20 @@ fn init_interrupt() {
     }
 
     axhal::irq::register_handler(TIMER_IRQ_NUM, || {
-        update_timer();
+        // With `multitask`, the task manager programs the timer for both the
+        // periodic ticks and its timer events, and stops the ticks on idle
+        // CPUs with `tickless`.
         #[cfg(feature = "multitask")]
         axtask::on_timer_tick();
+        #[cfg(not(feature = "multitask"))]
+        update_timer();
     });
 
+    // Other CPUs send IPIs to wake up this CPU when it is idle, the interrupt
+    // itself is all it takes.
+    #[cfg(feature = "smp")]
+    axhal::irq::register_handler(axhal::irq::IPI_IRQ_NUM, || {});
+
     // Enable IRQs before starting app
     axhal::arch::enable_irqs();
 }
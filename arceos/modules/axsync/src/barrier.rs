@@ -0,0 +1,108 @@
+//! A barrier to synchronize a group of tasks.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+use kspin::SpinNoIrq;
+
+/// A barrier, similar to
+/// [`std::sync::Barrier`](https://doc.rust-lang.org/std/sync/struct.Barrier.html).
+///
+/// The tasks calling [`wait`](Barrier::wait) block in the wait queue, until
+/// the given number of tasks have called it. Then all of them are woken up,
+/// and the barrier can be used again.
+pub struct Barrier {
+    num_tasks: usize,
+    /// The number of tasks that are waiting in the current generation.
+    count: SpinNoIrq<usize>,
+    generation: AtomicUsize,
+    wq: WaitQueue,
+}
+
+/// The result of [`Barrier::wait`].
+#[derive(Debug, Clone, Copy)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` for the leader, i.e., the last task that reaches the
+    /// barrier, and `false` for the others.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Creates a new barrier that blocks `num_tasks` tasks at a time.
+    pub const fn new(num_tasks: usize) -> Self {
+        Self {
+            num_tasks,
+            count: SpinNoIrq::new(0),
+            generation: AtomicUsize::new(0),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    /// Blocks the current task until all tasks have reached the barrier.
+    pub fn wait(&self) -> BarrierWaitResult {
+        let mut count = self.count.lock();
+        let generation = self.generation.load(Ordering::Acquire);
+        *count += 1;
+        if *count < self.num_tasks {
+            drop(count);
+            self.wq.wait_until(|| self.generation.load(Ordering::Acquire) != generation);
+            BarrierWaitResult(false)
+        } else {
+            *count = 0;
+            self.generation.fetch_add(1, Ordering::Release);
+            drop(count);
+            self.wq.notify_all(true);
+            BarrierWaitResult(true)
+        }
+    }
+}
+
+impl core::fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Barrier").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{may_interrupt, INIT, SERIAL};
+    use crate::Barrier;
+    use axtask as thread;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn all_tasks_pass_together() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 5;
+        const NUM_ROUNDS: usize = 10;
+        static BARRIER: Barrier = Barrier::new(NUM_TASKS);
+        static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+        static LEADERS: AtomicUsize = AtomicUsize::new(0);
+
+        let tasks = (0..NUM_TASKS)
+            .map(|_| {
+                thread::spawn(|| {
+                    for round in 0..NUM_ROUNDS {
+                        ARRIVED.fetch_add(1, Ordering::AcqRel);
+                        may_interrupt();
+                        if BARRIER.wait().is_leader() {
+                            LEADERS.fetch_add(1, Ordering::AcqRel);
+                        }
+                        // No one passes before all arrive.
+                        assert!(ARRIVED.load(Ordering::Acquire) >= (round + 1) * NUM_TASKS);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in tasks {
+            t.join();
+        }
+        assert_eq!(LEADERS.load(Ordering::Acquire), NUM_ROUNDS);
+    }
+}
@@ -0,0 +1,146 @@
+//! A condition variable working with [`Mutex`](crate::Mutex).
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axtask::WaitQueue;
+
+use crate::MutexGuard;
+
+/// A type indicating whether a timed wait on a condition variable returned
+/// due to a time out or not.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait was known to have timed out.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// A condition variable, similar to
+/// [`std::sync::Condvar`](https://doc.rust-lang.org/std/sync/struct.Condvar.html).
+///
+/// The waiting tasks release the [`Mutex`](crate::Mutex) and block in the
+/// wait queue, until they are notified, then lock the mutex again. Like the
+/// standard one, spurious wakeups are possible, so the condition should be
+/// checked in a loop (or with [`wait_while`](Condvar::wait_while)).
+pub struct Condvar {
+    wq: WaitQueue,
+    /// Bumped on each notification, so that a notification sent between
+    /// releasing the mutex and blocking is not missed.
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    pub const fn new() -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    /// Releases the mutex of `guard` and blocks the current task until this
+    /// condition variable is notified, then locks the mutex again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+        self.wq.wait_until(|| self.seq.load(Ordering::Acquire) != seq);
+        mutex.lock()
+    }
+
+    /// Blocks the current task as [`wait`](Condvar::wait) does, until
+    /// `condition` returns `false`.
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Blocks the current task as [`wait`](Condvar::wait) does, but for the
+    /// given duration at most.
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: core::time::Duration,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+        let timed_out = self
+            .wq
+            .wait_timeout_until(dur, || self.seq.load(Ordering::Acquire) != seq);
+        (mutex.lock(), WaitTimeoutResult(timed_out))
+    }
+
+    /// Wakes up one task blocked on this condition variable.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+
+    /// Wakes up all tasks blocked on this condition variable.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_all(true);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Condvar").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{INIT, SERIAL};
+    use crate::{Condvar, Mutex};
+    use axtask as thread;
+
+    #[test]
+    fn notify_and_wait_while() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 5;
+        static STARTED: Mutex<usize> = Mutex::new(0);
+        static CV: Condvar = Condvar::new();
+
+        let tasks = (0..NUM_TASKS)
+            .map(|_| {
+                thread::spawn(|| {
+                    *STARTED.lock() += 1;
+                    CV.notify_all();
+                    let guard = CV.wait_while(STARTED.lock(), |n| *n != 0);
+                    assert_eq!(*guard, 0);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut started = CV.wait_while(STARTED.lock(), |n| *n < NUM_TASKS);
+        *started = 0;
+        drop(started);
+        CV.notify_all();
+        for t in tasks {
+            t.join();
+        }
+    }
+}
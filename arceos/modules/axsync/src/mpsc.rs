@@ -0,0 +1,597 @@
+//! Multi-producer, single-consumer FIFO queue communication primitives.
+//!
+//! Similar to [`std::sync::mpsc`], a channel is created by [`channel`]
+//! (unbounded) or [`sync_channel`] (bounded), and is disconnected once all
+//! the senders, or the receiver, are dropped.
+//!
+//! [`std::sync::mpsc`]: https://doc.rust-lang.org/std/sync/mpsc/index.html
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::fmt;
+
+use axtask::WaitQueue;
+use kspin::SpinNoIrq;
+
+/// An error returned from [`Sender::send`] or [`SyncSender::send`], if the
+/// receiver has been dropped. It contains the message that was not sent.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct SendError<T>(pub T);
+
+/// An error returned from [`Receiver::recv`], if all the senders have been
+/// dropped and no message is left.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct RecvError;
+
+/// An error returned from [`Receiver::try_recv`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TryRecvError {
+    /// No message is available now, but the channel is still connected.
+    Empty,
+    /// All the senders have been dropped and no message is left.
+    Disconnected,
+}
+
+/// An error returned from [`Receiver::recv_timeout`].
+#[cfg(feature = "irq")]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RecvTimeoutError {
+    /// No message arrived before the timeout.
+    Timeout,
+    /// All the senders have been dropped and no message is left.
+    Disconnected,
+}
+
+/// An error returned from [`SyncSender::try_send`]. It contains the message
+/// that was not sent.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum TrySendError<T> {
+    /// The channel is full (or no receiver is waiting for a rendezvous
+    /// channel).
+    Full(T),
+    /// The receiver has been dropped.
+    Disconnected(T),
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+    /// The number of messages received so far.
+    received: u64,
+    /// The number of tasks blocked in [`Receiver::recv`].
+    waiting_receivers: usize,
+}
+
+struct Channel<T> {
+    state: SpinNoIrq<State<T>>,
+    /// The capacity of a bounded channel, `0` for a rendezvous channel.
+    bound: Option<usize>,
+    recv_wq: WaitQueue,
+    send_wq: WaitQueue,
+}
+
+impl<T> Channel<T> {
+    fn new(bound: Option<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            state: SpinNoIrq::new(State {
+                queue: VecDeque::new(),
+                senders: 1,
+                receiver_alive: true,
+                received: 0,
+                waiting_receivers: 0,
+            }),
+            bound,
+            recv_wq: WaitQueue::new(),
+            send_wq: WaitQueue::new(),
+        })
+    }
+
+    /// A rendezvous channel still holds one message, until it is received.
+    fn capacity(&self) -> usize {
+        self.bound.map_or(usize::MAX, |bound| bound.max(1))
+    }
+
+    fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let ticket = loop {
+            let mut state = self.state.lock();
+            if !state.receiver_alive {
+                return Err(SendError(msg));
+            }
+            if state.queue.len() < self.capacity() {
+                state.queue.push_back(msg);
+                break state.received + state.queue.len() as u64;
+            }
+            drop(state);
+            self.send_wq.wait_until(|| {
+                let state = self.state.lock();
+                !state.receiver_alive || state.queue.len() < self.capacity()
+            });
+        };
+        self.recv_wq.notify_one(true);
+
+        if self.bound == Some(0) {
+            // Wait for the receiver to take the message.
+            self.send_wq.wait_until(|| {
+                let state = self.state.lock();
+                !state.receiver_alive || state.received >= ticket
+            });
+            let mut state = self.state.lock();
+            if state.received < ticket {
+                // It is the only message in the queue.
+                return Err(SendError(state.queue.pop_back().unwrap()));
+            }
+        }
+        Ok(())
+    }
+
+    fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.state.lock();
+        if !state.receiver_alive {
+            return Err(TrySendError::Disconnected(msg));
+        }
+        let full = if self.bound == Some(0) {
+            state.waiting_receivers == 0 || !state.queue.is_empty()
+        } else {
+            state.queue.len() >= self.capacity()
+        };
+        if full {
+            return Err(TrySendError::Full(msg));
+        }
+        state.queue.push_back(msg);
+        drop(state);
+        self.recv_wq.notify_one(true);
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock();
+        match state.queue.pop_front() {
+            Some(msg) => {
+                state.received += 1;
+                drop(state);
+                if self.bound.is_some() {
+                    // Both the senders waiting for room and the one waiting for
+                    // the rendezvous.
+                    self.send_wq.notify_all(true);
+                }
+                Ok(msg)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Whether a message or the disconnection can be received.
+    fn can_recv(&self) -> bool {
+        let state = self.state.lock();
+        !state.queue.is_empty() || state.senders == 0
+    }
+
+    fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            match self.try_recv() {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+            self.state.lock().waiting_receivers += 1;
+            self.recv_wq.wait_until(|| self.can_recv());
+            self.state.lock().waiting_receivers -= 1;
+        }
+    }
+
+    #[cfg(feature = "irq")]
+    fn recv_timeout(&self, dur: core::time::Duration) -> Result<T, RecvTimeoutError> {
+        match self.try_recv() {
+            Err(TryRecvError::Empty) => {}
+            res => return res.map_err(|_| RecvTimeoutError::Disconnected),
+        }
+        self.state.lock().waiting_receivers += 1;
+        self.recv_wq.wait_timeout_until(dur, || self.can_recv());
+        self.state.lock().waiting_receivers -= 1;
+        self.try_recv().map_err(|err| match err {
+            TryRecvError::Empty => RecvTimeoutError::Timeout,
+            TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+        })
+    }
+}
+
+/// The sending half of an unbounded channel created by [`channel`].
+///
+/// Messages are sent without blocking. It can be cloned to send from many
+/// tasks.
+pub struct Sender<T> {
+    chan: Arc<Channel<T>>,
+}
+
+/// The sending half of a bounded channel created by [`sync_channel`].
+///
+/// Sending blocks while the channel is full. It can be cloned to send from
+/// many tasks.
+pub struct SyncSender<T> {
+    chan: Arc<Channel<T>>,
+}
+
+/// The receiving half of a channel created by [`channel`] or
+/// [`sync_channel`].
+pub struct Receiver<T> {
+    chan: Arc<Channel<T>>,
+}
+
+/// Creates an unbounded channel, returns the sender and the receiver.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let chan = Channel::new(None);
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Creates a bounded channel that holds `bound` messages at most, returns
+/// the sender and the receiver.
+///
+/// If `bound` is `0`, it becomes a rendezvous channel: each send blocks
+/// until the message is received.
+pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    let chan = Channel::new(Some(bound));
+    (SyncSender { chan: chan.clone() }, Receiver { chan })
+}
+
+impl<T> Sender<T> {
+    /// Sends a message on the channel.
+    ///
+    /// Returns the message in [`SendError`] if the receiver has been
+    /// dropped. A successful send does not mean that the message will be
+    /// received.
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.chan.send(msg)
+    }
+}
+
+impl<T> SyncSender<T> {
+    /// Sends a message on the channel, blocking the current task while the
+    /// channel is full.
+    ///
+    /// Returns the message in [`SendError`] if the receiver has been
+    /// dropped.
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.chan.send(msg)
+    }
+
+    /// Tries to send a message on the channel without blocking.
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(msg)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives a message, blocking the current task until one arrives.
+    ///
+    /// Returns [`RecvError`] if all the senders have been dropped and no
+    /// message is left.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.chan.recv()
+    }
+
+    /// Receives a message without blocking.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+
+    /// Receives a message, blocking the current task for the given
+    /// duration at most.
+    #[cfg(feature = "irq")]
+    pub fn recv_timeout(&self, timeout: core::time::Duration) -> Result<T, RecvTimeoutError> {
+        self.chan.recv_timeout(timeout)
+    }
+
+    /// Returns an iterator that blocks waiting for messages, until the
+    /// channel is disconnected.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    /// Returns an iterator over the messages available now, without
+    /// blocking.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+macro_rules! impl_sender {
+    ($sender:ident) => {
+        impl<T> Clone for $sender<T> {
+            fn clone(&self) -> Self {
+                self.chan.state.lock().senders += 1;
+                Self {
+                    chan: self.chan.clone(),
+                }
+            }
+        }
+
+        impl<T> Drop for $sender<T> {
+            fn drop(&mut self) {
+                let mut state = self.chan.state.lock();
+                state.senders -= 1;
+                let disconnected = state.senders == 0;
+                drop(state);
+                if disconnected {
+                    self.chan.recv_wq.notify_all(true);
+                }
+            }
+        }
+
+        impl<T> fmt::Debug for $sender<T> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($sender)).finish_non_exhaustive()
+            }
+        }
+    };
+}
+
+impl_sender!(Sender);
+impl_sender!(SyncSender);
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.chan.state.lock().receiver_alive = false;
+        self.chan.send_wq.notify_all(true);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// An iterator over messages on a [`Receiver`], created by
+/// [`Receiver::iter`].
+#[derive(Debug)]
+pub struct Iter<'a, T: 'a> {
+    rx: &'a Receiver<T>,
+}
+
+/// An iterator over the messages available now on a [`Receiver`], created
+/// by [`Receiver::try_iter`].
+#[derive(Debug)]
+pub struct TryIter<'a, T: 'a> {
+    rx: &'a Receiver<T>,
+}
+
+/// An owning iterator over messages on a [`Receiver`].
+#[derive(Debug)]
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sending on a closed channel")
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(..) => f.write_str("Full(..)"),
+            TrySendError::Disconnected(..) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(..) => f.write_str("sending on a full channel"),
+            TrySendError::Disconnected(..) => f.write_str("sending on a closed channel"),
+        }
+    }
+}
+
+impl<T> From<SendError<T>> for TrySendError<T> {
+    fn from(err: SendError<T>) -> Self {
+        TrySendError::Disconnected(err.0)
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("receiving on a closed channel")
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.write_str("receiving on an empty channel"),
+            TryRecvError::Disconnected => f.write_str("receiving on a closed channel"),
+        }
+    }
+}
+
+impl From<RecvError> for TryRecvError {
+    fn from(_: RecvError) -> Self {
+        TryRecvError::Disconnected
+    }
+}
+
+#[cfg(feature = "irq")]
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => f.write_str("timed out waiting on channel"),
+            RecvTimeoutError::Disconnected => {
+                f.write_str("channel is empty and sending half is closed")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "irq")]
+impl From<RecvError> for RecvTimeoutError {
+    fn from(_: RecvError) -> Self {
+        RecvTimeoutError::Disconnected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channel, sync_channel, RecvError, SendError, TryRecvError, TrySendError};
+    use crate::tests::{may_interrupt, INIT, SERIAL};
+    use axtask as thread;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[test]
+    fn unbounded_fifo() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_MSGS: usize = 1000;
+        let (tx, rx) = channel();
+        let sender = thread::spawn(move || {
+            for i in 0..NUM_MSGS {
+                tx.send(i).unwrap();
+                may_interrupt();
+            }
+        });
+        for i in 0..NUM_MSGS {
+            assert_eq!(rx.recv(), Ok(i));
+            may_interrupt();
+        }
+        sender.join();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn bounded_blocks_when_full() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const BOUND: usize = 2;
+        static SENT: AtomicUsize = AtomicUsize::new(0);
+        let (tx, rx) = sync_channel(BOUND);
+        let sender = thread::spawn(move || {
+            for i in 0..BOUND + 1 {
+                tx.send(i).unwrap();
+                SENT.fetch_add(1, Ordering::AcqRel);
+            }
+            assert_eq!(tx.try_send(0), Err(TrySendError::Full(0)));
+        });
+        for _ in 0..10 {
+            thread::yield_now();
+        }
+        // The last send waits for room.
+        assert_eq!(SENT.load(Ordering::Acquire), BOUND);
+
+        assert_eq!(rx.recv(), Ok(0));
+        for _ in 0..10 {
+            thread::yield_now();
+        }
+        assert_eq!(SENT.load(Ordering::Acquire), BOUND + 1);
+        sender.join();
+        assert_eq!(rx.iter().collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn rendezvous() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static SENT: AtomicBool = AtomicBool::new(false);
+        let (tx, rx) = sync_channel(0);
+        // No receiver is waiting.
+        assert_eq!(tx.try_send(0), Err(TrySendError::Full(0)));
+
+        let sender = thread::spawn(move || {
+            tx.send(1).unwrap();
+            SENT.store(true, Ordering::Release);
+        });
+        for _ in 0..10 {
+            thread::yield_now();
+        }
+        // The send returns only after the message is received.
+        assert!(!SENT.load(Ordering::Acquire));
+        assert_eq!(rx.recv(), Ok(1));
+        sender.join();
+        assert!(SENT.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn disconnected() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 5;
+        let (tx, rx) = channel();
+        let senders = (0..NUM_TASKS)
+            .map(|i| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    may_interrupt();
+                    tx.send(i).unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        // Blocks until all the senders are dropped.
+        let mut msgs = rx.iter().collect::<Vec<_>>();
+        msgs.sort();
+        assert_eq!(msgs, (0..NUM_TASKS).collect::<Vec<_>>());
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+        for t in senders {
+            t.join();
+        }
+
+        let (tx, rx) = sync_channel(1);
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+}
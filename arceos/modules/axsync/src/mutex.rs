@@ -11,8 +11,14 @@ use axtask::{current, WaitQueue};
 /// [`std::sync::Mutex`](https://doc.rust-lang.org/std/sync/struct.Mutex.html).
 ///
 /// When the mutex is locked, the current task will block and be put into the
-/// wait queue. When the mutex is unlocked, all tasks waiting on the queue
-/// will be woken up.
+/// wait queue. When the mutex is unlocked, the waiting task with the highest
+/// real-time priority (or the first one) will be woken up.
+///
+/// It supports priority inheritance: a real-time task waiting for the mutex
+/// lends its priority to the owner until the owner releases it, and to the
+/// owners of the mutexes that the owner waits for in turn. So the owners
+/// cannot be held up by tasks of lower priorities than the waiter. It is only
+/// dealt with when the mutex is contended.
 pub struct Mutex<T: ?Sized> {
     wq: WaitQueue,
     owner_id: AtomicU64,
@@ -70,6 +76,7 @@ impl<T: ?Sized> Mutex<T> {
     /// and the lock will be dropped when the guard falls out of scope.
     pub fn lock(&self) -> MutexGuard<T> {
         let current_id = current().id().as_u64();
+        let mut waited = false;
         loop {
             // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
             // when called in a loop.
@@ -87,11 +94,17 @@ impl<T: ?Sized> Mutex<T> {
                         "{} tried to acquire mutex it already owns.",
                         current().id_name()
                     );
+                    axtask::pi_wait_begin(&self.owner_id);
                     // Wait until the lock looks unlocked before retrying
                     self.wq.wait_until(|| !self.is_locked());
+                    axtask::pi_wait_end();
+                    waited = true;
                 }
             }
         }
+        if waited {
+            axtask::pi_acquired();
+        }
         MutexGuard {
             lock: self,
             data: unsafe { &mut *self.data.get() },
@@ -126,7 +139,8 @@ impl<T: ?Sized> Mutex<T> {
     /// thread. However, this can be useful in some instances for exposing
     /// the lock to FFI that doesn’t know how to deal with RAII.
     pub unsafe fn force_unlock(&self) {
-        let owner_id = self.owner_id.swap(0, Ordering::Release);
+        // Pairs with `axtask::pi_release`, see `axtask::pi_wait_begin`.
+        let owner_id = self.owner_id.swap(0, Ordering::SeqCst);
         assert_eq!(
             owner_id,
             current().id().as_u64(),
@@ -134,6 +148,7 @@ impl<T: ?Sized> Mutex<T> {
             current().id_name()
         );
         self.wq.notify_one(true);
+        axtask::pi_release();
     }
 
     /// Returns a mutable reference to the underlying data.
@@ -167,6 +182,13 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     }
 }
 
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Returns the mutex that the guard locks.
+    pub(crate) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
 impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
     type Target = T;
     #[inline(always)]
@@ -199,21 +221,14 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
 
 #[cfg(test)]
 mod tests {
+    use crate::tests::{may_interrupt, INIT, SERIAL};
     use crate::Mutex;
     use axtask as thread;
-    use std::sync::Once;
-
-    static INIT: Once = Once::new();
-
-    fn may_interrupt() {
-        // simulate interrupts
-        if rand::random::<u32>() % 3 == 0 {
-            thread::yield_now();
-        }
-    }
+    use std::sync::Mutex as StdMutex;
 
     #[test]
     fn lots_and_lots() {
+        let _lock = SERIAL.lock();
         INIT.call_once(thread::init_scheduler);
 
         const NUM_TASKS: u32 = 10;
@@ -249,4 +264,85 @@ mod tests {
         assert_eq!(*M.lock(), NUM_ITERS * NUM_TASKS * 3);
         println!("Mutex test OK");
     }
+
+    #[test]
+    fn priority_inheritance() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static M: Mutex<()> = Mutex::new(());
+        static ORDER: StdMutex<Vec<&str>> = StdMutex::new(Vec::new());
+
+        let low = thread::spawn(|| {
+            let _guard = M.lock();
+            thread::yield_now();
+            ORDER.lock().unwrap().push("low");
+        });
+        while !M.is_locked() {
+            thread::yield_now();
+        }
+
+        let high = thread::spawn(|| {
+            let _guard = M.lock();
+            ORDER.lock().unwrap().push("high");
+        });
+        let medium = thread::spawn(|| ORDER.lock().unwrap().push("medium"));
+        assert!(thread::set_scheduler(&high, thread::SchedPolicy::Fifo, 50));
+        assert!(thread::set_scheduler(&medium, thread::SchedPolicy::Fifo, 10));
+
+        // `low` runs with the priority of `high` once `high` waits for it.
+        high.join();
+        medium.join();
+        low.join();
+        assert_eq!(*ORDER.lock().unwrap(), ["low", "high", "medium"]);
+    }
+
+    #[test]
+    fn priority_inheritance_chain() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        static M1: Mutex<()> = Mutex::new(());
+        static M2: Mutex<()> = Mutex::new(());
+        static GO: AtomicBool = AtomicBool::new(false);
+        static ORDER: StdMutex<Vec<&str>> = StdMutex::new(Vec::new());
+
+        let low = thread::spawn(|| {
+            let _guard = M1.lock();
+            while !GO.load(Ordering::Acquire) {
+                thread::yield_now();
+            }
+            ORDER.lock().unwrap().push("low");
+        });
+        while !M1.is_locked() {
+            thread::yield_now();
+        }
+        // `mid` holds `M2` and waits for `M1`.
+        let mid = thread::spawn(|| {
+            let _guard2 = M2.lock();
+            let _guard1 = M1.lock();
+            ORDER.lock().unwrap().push("mid");
+        });
+        while !M2.is_locked() {
+            thread::yield_now();
+        }
+
+        let high = thread::spawn(|| {
+            let _guard = M2.lock();
+            ORDER.lock().unwrap().push("high");
+        });
+        let medium = thread::spawn(|| ORDER.lock().unwrap().push("medium"));
+        assert!(thread::set_scheduler(&high, thread::SchedPolicy::Fifo, 50));
+        assert!(thread::set_scheduler(&medium, thread::SchedPolicy::Fifo, 10));
+        GO.store(true, Ordering::Release);
+
+        // The priority of `high` is passed on to `low` through `mid`.
+        high.join();
+        medium.join();
+        mid.join();
+        low.join();
+        assert_eq!(*ORDER.lock().unwrap(), ["low", "mid", "high", "medium"]);
+    }
 }
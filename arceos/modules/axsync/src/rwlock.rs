@@ -0,0 +1,272 @@
+//! A writer-preferring sleeping reader-writer lock.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// The lock is held by a writer, the other bits count the readers.
+const WRITER: usize = 1;
+const READER: usize = 2;
+
+/// A reader-writer lock, similar to
+/// [`std::sync::RwLock`](https://doc.rust-lang.org/std/sync/struct.RwLock.html).
+///
+/// Tasks that cannot get the lock block in wait queues. It prefers writers:
+/// no new reader gets the lock while some writer is waiting, so writers are
+/// not starved by a stream of readers.
+pub struct RwLock<T: ?Sized> {
+    /// Accessed with `SeqCst` along with `writers_waiting`, so that an
+    /// unlocking task either sees a new waiter, or the waiter sees the lock
+    /// released.
+    state: AtomicUsize,
+    /// The number of writers waiting for the lock.
+    writers_waiting: AtomicUsize,
+    read_wq: WaitQueue,
+    write_wq: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides immutable data access.
+///
+/// When the guard falls out of scope it will release the shared access.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the exclusive access.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new [`RwLock`] wrapping the supplied data.
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            read_wq: WaitQueue::new(),
+            write_wq: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this [`RwLock`] and unwraps the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn can_read(&self) -> bool {
+        self.state.load(Ordering::SeqCst) & WRITER == 0
+            && self.writers_waiting.load(Ordering::SeqCst) == 0
+    }
+
+    /// Locks this [`RwLock`] with shared read access, blocking the current
+    /// task until it can be acquired.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            self.read_wq.wait_until(|| self.can_read());
+        }
+    }
+
+    /// Try to lock this [`RwLock`] with shared read access, returning a guard
+    /// if successful.
+    ///
+    /// It fails if the lock is held by a writer, or some writer is waiting.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let mut state = self.state.load(Ordering::SeqCst);
+        while state & WRITER == 0 && self.writers_waiting.load(Ordering::SeqCst) == 0 {
+            match self.state.compare_exchange_weak(
+                state,
+                state + READER,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Some(RwLockReadGuard { lock: self }),
+                Err(s) => state = s,
+            }
+        }
+        None
+    }
+
+    /// Locks this [`RwLock`] with exclusive write access, blocking the
+    /// current task until it can be acquired.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        self.writers_waiting.fetch_add(1, Ordering::SeqCst);
+        let guard = loop {
+            if let Some(guard) = self.try_write() {
+                break guard;
+            }
+            self.write_wq.wait_until(|| self.state.load(Ordering::SeqCst) == 0);
+        };
+        self.writers_waiting.fetch_sub(1, Ordering::SeqCst);
+        guard
+    }
+
+    /// Try to lock this [`RwLock`] with exclusive write access, returning a
+    /// guard if successful.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`RwLock`] mutably, no actual locking needs
+    /// to take place.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn read_unlock(&self) {
+        let state = self.state.fetch_sub(READER, Ordering::SeqCst) - READER;
+        if state == 0 && self.writers_waiting.load(Ordering::SeqCst) > 0 {
+            self.write_wq.notify_one(true);
+        }
+    }
+
+    fn write_unlock(&self) {
+        self.state.store(0, Ordering::SeqCst);
+        if self.writers_waiting.load(Ordering::SeqCst) > 0 {
+            self.write_wq.notify_one(true);
+        } else {
+            self.read_wq.notify_all(true);
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLock<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "RwLock {{ data: ")
+                .and_then(|()| (*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "RwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        // We know statically that there are only readers
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        // We know statically that only we are referencing data
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        // We know statically that only we are referencing data
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{may_interrupt, INIT, SERIAL};
+    use crate::RwLock;
+    use axtask as thread;
+
+    #[test]
+    fn readers_and_writers() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: u32 = 5;
+        const NUM_ITERS: u32 = 1000;
+        static LOCK: RwLock<(u32, u32)> = RwLock::new((0, 0));
+
+        let mut tasks = Vec::new();
+        for _ in 0..NUM_TASKS {
+            tasks.push(thread::spawn(|| {
+                for _ in 0..NUM_ITERS {
+                    let mut pair = LOCK.write();
+                    pair.0 += 1;
+                    may_interrupt();
+                    pair.1 += 1;
+                }
+            }));
+            tasks.push(thread::spawn(|| {
+                for _ in 0..NUM_ITERS {
+                    let pair = LOCK.read();
+                    may_interrupt();
+                    assert_eq!(pair.0, pair.1); // never sees a half-done write
+                }
+            }));
+        }
+        for t in tasks {
+            t.join();
+        }
+        assert_eq!(*LOCK.read(), (NUM_TASKS * NUM_ITERS, NUM_TASKS * NUM_ITERS));
+
+        // No new reader while a writer is waiting.
+        let reader = LOCK.read();
+        let writer = thread::spawn(|| *LOCK.write() = (0, 0));
+        while LOCK.try_read().is_some() {
+            thread::yield_now();
+        }
+        drop(reader);
+        writer.join();
+        assert_eq!(*LOCK.read(), (0, 0));
+    }
+}
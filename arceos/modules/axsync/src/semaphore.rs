@@ -0,0 +1,118 @@
+//! A counting semaphore.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// A counting semaphore.
+///
+/// It holds a number of permits. [`acquire`](Semaphore::acquire) takes one
+/// of them, and blocks the current task in the wait queue while there is
+/// none. [`release`](Semaphore::release) puts one back and wakes up a
+/// waiting task.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    wq: WaitQueue,
+}
+
+/// A guard that holds a permit of a [`Semaphore`].
+///
+/// When the guard falls out of scope it will release the permit.
+pub struct SemaphoreGuard<'a> {
+    sem: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the given number of permits.
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: AtomicUsize::new(permits),
+            wq: WaitQueue::new(),
+        }
+    }
+
+    /// Returns the number of permits available now.
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Acquire)
+    }
+
+    /// Takes a permit, blocking the current task until one is available.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.wq.wait_until(|| self.available_permits() > 0);
+        }
+    }
+
+    /// Try to take a permit, returns `false` if none is available.
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Puts a permit back, and wakes up a task waiting for it.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+
+    /// Takes a permit as [`acquire`](Semaphore::acquire) does, returns a
+    /// guard that releases it on drop.
+    pub fn access(&self) -> SemaphoreGuard<'_> {
+        self.acquire();
+        SemaphoreGuard { sem: self }
+    }
+}
+
+impl core::fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        self.sem.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{may_interrupt, INIT, SERIAL};
+    use crate::Semaphore;
+    use axtask as thread;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn limits_concurrency() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 10;
+        const NUM_PERMITS: usize = 3;
+        static SEM: Semaphore = Semaphore::new(NUM_PERMITS);
+        static INSIDE: AtomicUsize = AtomicUsize::new(0);
+
+        let tasks = (0..NUM_TASKS)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..100 {
+                        let _guard = SEM.access();
+                        let n = INSIDE.fetch_add(1, Ordering::AcqRel) + 1;
+                        assert!(n <= NUM_PERMITS);
+                        may_interrupt();
+                        INSIDE.fetch_sub(1, Ordering::AcqRel);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in tasks {
+            t.join();
+        }
+        assert_eq!(SEM.available_permits(), NUM_PERMITS);
+        assert!(SEM.try_acquire());
+        SEM.release();
+    }
+}
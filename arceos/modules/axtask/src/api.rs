@@ -2,32 +2,46 @@
 
 use alloc::{string::String, sync::Arc};
 
-pub(crate) use crate::run_queue::{AxRunQueue, RUN_QUEUE};
+pub(crate) use crate::run_queue::current_run_queue;
 
 #[doc(cfg(feature = "multitask"))]
-pub use crate::task::{CurrentTask, TaskId, TaskInner};
+pub use crate::cpumask::CpuMask;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::futex::{futex_lock_pi, futex_unlock_pi, FUTEX_BITSET_MATCH_ANY};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::futex::{futex_requeue, futex_wait, futex_wake, FutexError};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::notify::{cancel, is_cancelled, Interrupted};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::notify::{pending_notifications, send_notification};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::notify::{set_notification_mask, take_notifications};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::registry::{all_tasks, find_task, TaskInfo};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::rt::{pi_acquired, pi_release, pi_wait_begin, pi_wait_end};
+pub use crate::rt::{SchedPolicy, MAX_RT_PRIO};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::sched::{register_scheduler, scheduler_name, scheduler_names, select_scheduler};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::sched::{Scheduler, SchedulerCtor};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::stats::{total_sched_stats, KernelModeGuard, SchedStats};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::task::{CurrentTask, TaskId, TaskInner, TaskState};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task_ext::{TaskExtMut, TaskExtRef};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::wait_queue::WaitQueue;
 
+#[cfg(feature = "irq")]
+#[doc(cfg(feature = "irq"))]
+pub use crate::timers::{set_periodic_timer, set_timer, TimerHandle};
+
 /// The reference type of a task.
 pub type AxTaskRef = Arc<AxTask>;
 
-cfg_if::cfg_if! {
-    if #[cfg(feature = "sched_rr")] {
-        const MAX_TIME_SLICE: usize = 5;
-        pub(crate) type AxTask = scheduler::RRTask<TaskInner, MAX_TIME_SLICE>;
-        pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
-    } else if #[cfg(feature = "sched_cfs")] {
-        pub(crate) type AxTask = scheduler::CFSTask<TaskInner>;
-        pub(crate) type Scheduler = scheduler::CFScheduler<TaskInner>;
-    } else {
-        // If no scheduler features are set, use FIFO as the default.
-        pub(crate) type AxTask = scheduler::FifoTask<TaskInner>;
-        pub(crate) type Scheduler = scheduler::FifoScheduler<TaskInner>;
-    }
-}
+pub(crate) type AxTask = TaskInner;
 
 #[cfg(feature = "preempt")]
 struct KernelGuardIfImpl;
@@ -67,32 +81,40 @@ pub fn current() -> CurrentTask {
 pub fn init_scheduler() {
     info!("Initialize scheduling...");
 
+    crate::sched::init();
     crate::run_queue::init();
     #[cfg(feature = "irq")]
     crate::timers::init();
 
-    info!("  use {} scheduler.", Scheduler::scheduler_name());
+    info!("  use {} scheduler with the real-time class.", scheduler_name());
 }
 
 /// Initializes the task scheduler for secondary CPUs.
 pub fn init_scheduler_secondary() {
     crate::run_queue::init_secondary();
+    #[cfg(feature = "irq")]
+    crate::timers::init();
 }
 
-/// Handles periodic timer ticks for the task manager.
+/// Handles the timer interrupt for the task manager.
 ///
-/// For example, advance scheduler states, checks timed events, etc.
+/// It runs the expired timer events of the current CPU, advances scheduler
+/// states at periodic ticks, and programs the next timer interrupt.
 #[cfg(feature = "irq")]
 #[doc(cfg(feature = "irq"))]
 pub fn on_timer_tick() {
-    crate::timers::check_events();
-    RUN_QUEUE.lock().scheduler_timer_tick();
+    if crate::timers::check_events() {
+        current_run_queue().scheduler_timer_tick();
+    }
 }
 
 /// Adds the given task to the run queue, returns the task reference.
+///
+/// With multiple CPUs, the task is put into the run queue of the least
+/// loaded CPU.
 pub fn spawn_task(task: TaskInner) -> AxTaskRef {
     let task_ref = task.into_arc();
-    RUN_QUEUE.lock().add_task(task_ref.clone());
+    crate::run_queue::add_task(task_ref.clone());
     task_ref
 }
 
@@ -129,13 +151,67 @@ where
 ///
 /// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
 pub fn set_priority(prio: isize) -> bool {
-    RUN_QUEUE.lock().set_current_priority(prio)
+    current_run_queue().set_current_priority(prio)
+}
+
+/// Sets the CPU affinity of the given task, i.e., the CPUs it is allowed to
+/// run on.
+///
+/// If the task is the current task and the current CPU is not in `cpumask`,
+/// it is migrated at once. Other tasks are moved to an allowed CPU the next
+/// time they are scheduled or woken up.
+///
+/// Returns `false` and leaves the affinity unchanged if `cpumask` contains no
+/// online CPU.
+pub fn set_affinity(task: &AxTaskRef, cpumask: CpuMask) -> bool {
+    crate::run_queue::set_affinity(task, cpumask)
+}
+
+/// Sets the CPU affinity of the current task.
+///
+/// See [`set_affinity`] for details.
+pub fn set_current_affinity(cpumask: CpuMask) -> bool {
+    set_affinity(current().as_task_ref(), cpumask)
+}
+
+/// Returns the CPU affinity of the given task.
+pub fn get_affinity(task: &AxTaskRef) -> CpuMask {
+    task.cpumask()
+}
+
+/// Sets the scheduling policy and the real-time priority of the given task.
+///
+/// Real-time tasks ([`SchedPolicy::Fifo`] and [`SchedPolicy::RoundRobin`])
+/// have a priority from 1 to [`MAX_RT_PRIO`], and always run before the
+/// normal tasks. The priority of [`SchedPolicy::Normal`] must be 0, use
+/// [`set_priority`] to set the priority of the underlying scheduler instead.
+///
+/// Returns `false` and leaves the task unchanged if the priority is out of
+/// range.
+pub fn set_scheduler(task: &AxTaskRef, policy: SchedPolicy, priority: u8) -> bool {
+    if !crate::rt::check_params(policy, priority) {
+        return false;
+    }
+    debug!(
+        "task set scheduler: {}, policy={:?}, priority={}",
+        task.id_name(),
+        policy,
+        priority
+    );
+    crate::run_queue::update_rt_params(task, |rt| rt.set_params(policy, priority));
+    true
+}
+
+/// Returns the scheduling policy and the real-time priority of the given
+/// task, not including the priority it inherits.
+pub fn get_scheduler(task: &AxTaskRef) -> (SchedPolicy, u8) {
+    (task.rt().policy(), task.rt().priority())
 }
 
 /// Current task gives up the CPU time voluntarily, and switches to another
 /// ready task.
 pub fn yield_now() {
-    RUN_QUEUE.lock().yield_current();
+    current_run_queue().yield_current();
 }
 
 /// Current task is going to sleep for the given duration.
@@ -150,24 +226,76 @@ pub fn sleep(dur: core::time::Duration) {
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
 pub fn sleep_until(deadline: axhal::time::TimeValue) {
     #[cfg(feature = "irq")]
-    RUN_QUEUE.lock().sleep_until(deadline);
+    current_run_queue().sleep_until(deadline);
     #[cfg(not(feature = "irq"))]
     axhal::time::busy_wait_until(deadline);
 }
 
+/// Calls `f` with the task-local variable of the current task identified by
+/// `key`, which is initialized by `init` on the first access.
+///
+/// `key` must be unique for each variable, e.g., the address of a `static`.
+/// The variables are kept with the task extended data, and dropped when the
+/// task exits, it returns [`None`] if they are accessed after that (e.g., in
+/// their destructors). They are dropped with the task if it does not exit.
+///
+/// # Panics
+///
+/// Panics if the variable of `key` is accessed with another type.
+pub fn with_current_local<T: 'static, R>(
+    key: usize,
+    init: impl FnOnce() -> T,
+    f: impl FnOnce(&T) -> R,
+) -> Option<R> {
+    let ptr = current().locals().get_or_init(key, init)?;
+    // SAFETY: the variables are only dropped by the task itself on exit, or
+    // with the task, which cannot happen while it is running.
+    Some(f(unsafe { &*ptr }))
+}
+
 /// Exits the current task.
+///
+/// The task-local variables are dropped first, in the context of the task.
 pub fn exit(exit_code: i32) -> ! {
-    RUN_QUEUE.lock().exit_current(exit_code)
+    current().locals().destroy();
+    current_run_queue().exit_current(exit_code)
+}
+
+/// Returns whether `vaddr` is in the guard page below the kernel stack of
+/// the current task, i.e., the task has overflowed its stack.
+///
+/// It is meant to be called on kernel faults that are not handled, which
+/// `axruntime` does for every app, and the caller should panic then, as the
+/// task cannot go on. Tasks only overflow their own stacks, so the other tasks
+/// are not checked. Always returns `false` on architectures other than x86_64,
+/// which have no stack guard pages.
+#[cfg(feature = "paging")]
+pub fn check_stack_guard(vaddr: usize) -> bool {
+    current_may_uninit().is_some_and(|curr| curr.stack_guard_contains(vaddr))
 }
 
 /// The idle task routine.
 ///
-/// It runs an infinite loop that keeps calling [`yield_now()`].
+/// It runs an infinite loop that keeps calling [`yield_now()`]. With the
+/// `tickless` feature, the periodic tick is stopped while it waits for IRQs.
 pub fn run_idle() -> ! {
     loop {
         yield_now();
         debug!("idle task: waiting for IRQs...");
         #[cfg(feature = "irq")]
-        axhal::arch::wait_for_irqs();
+        {
+            // IRQs are disabled from checking for ready tasks until the CPU
+            // waits, and enabled at once as it waits, so that no wakeup is
+            // missed in between.
+            let rq = current_run_queue();
+            #[cfg(feature = "tickless")]
+            let idle = rq.stop_tick_if_idle();
+            #[cfg(not(feature = "tickless"))]
+            let idle = true;
+            if idle {
+                axhal::arch::enable_irqs_and_wait();
+            }
+            drop(rq);
+        }
     }
 }
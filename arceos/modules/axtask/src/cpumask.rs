@@ -0,0 +1,91 @@
+//! CPU affinity masks.
+
+use core::fmt;
+
+const BITS_PER_WORD: usize = usize::BITS as usize;
+const NUM_WORDS: usize = axconfig::SMP.div_ceil(BITS_PER_WORD);
+
+/// A set of CPUs, e.g., the CPUs that a task is allowed to run on.
+///
+/// Only the CPUs whose IDs are less than [`axconfig::SMP`] can be in the set.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CpuMask([usize; NUM_WORDS]);
+
+impl CpuMask {
+    /// Creates an empty set.
+    pub const fn new() -> Self {
+        Self([0; NUM_WORDS])
+    }
+
+    /// Creates a set of all CPUs.
+    pub const fn full() -> Self {
+        let mut mask = Self::new();
+        let mut cpu_id = 0;
+        while cpu_id < axconfig::SMP {
+            mask.0[cpu_id / BITS_PER_WORD] |= 1 << (cpu_id % BITS_PER_WORD);
+            cpu_id += 1;
+        }
+        mask
+    }
+
+    /// Creates a set that only contains the given CPU.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu_id` is not less than [`axconfig::SMP`].
+    pub const fn one_shot(cpu_id: usize) -> Self {
+        assert!(cpu_id < axconfig::SMP);
+        let mut mask = Self::new();
+        mask.0[cpu_id / BITS_PER_WORD] = 1 << (cpu_id % BITS_PER_WORD);
+        mask
+    }
+
+    /// Returns whether the given CPU is in the set.
+    pub const fn get(&self, cpu_id: usize) -> bool {
+        cpu_id < axconfig::SMP
+            && self.0[cpu_id / BITS_PER_WORD] & (1 << (cpu_id % BITS_PER_WORD)) != 0
+    }
+
+    /// Adds the given CPU to the set if `value` is true, or removes it
+    /// otherwise.
+    ///
+    /// CPU IDs not less than [`axconfig::SMP`] are ignored.
+    pub fn set(&mut self, cpu_id: usize, value: bool) {
+        if cpu_id >= axconfig::SMP {
+            return;
+        }
+        let bit = 1 << (cpu_id % BITS_PER_WORD);
+        if value {
+            self.0[cpu_id / BITS_PER_WORD] |= bit;
+        } else {
+            self.0[cpu_id / BITS_PER_WORD] &= !bit;
+        }
+    }
+
+    /// Returns the number of CPUs in the set.
+    pub fn len(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Returns whether the set contains no CPU.
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+
+    /// Iterates over the IDs of the CPUs in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..axconfig::SMP).filter(|&cpu_id| self.get(cpu_id))
+    }
+}
+
+impl Default for CpuMask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CpuMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
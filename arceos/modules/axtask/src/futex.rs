@@ -0,0 +1,304 @@
+//! Fast user-space locking (futex) primitives.
+//!
+//! A futex is a 32-bit word in memory. Tasks wait on it with [`futex_wait`],
+//! which blocks only if the word still holds the expected value, and are
+//! woken up with [`futex_wake`] or moved to another futex with
+//! [`futex_requeue`]. Locks and condition variables in user space (or in C
+//! libraries) are built on them, and only enter the kernel when contended.
+//!
+//! Mutexes with priority inheritance are built on [`futex_lock_pi`] and
+//! [`futex_unlock_pi`] instead, whose word holds the ID of the owner.
+//!
+//! The waiters are keyed by the address of the futex word, hashed into a
+//! fixed number of buckets. So futexes are private to an address space:
+//! kernels with several user address spaces should not share them across.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use kspin::{SpinNoIrq, SpinNoIrqGuard};
+
+use crate::WaitQueue;
+
+/// The bitset that matches any waiter.
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// The number of buckets that the futex addresses are hashed into.
+const NUM_BUCKETS: usize = 64;
+
+/// The errors of futex operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexError {
+    /// The futex word does not hold the expected value.
+    WouldBlock,
+    /// The wait has timed out.
+    TimedOut,
+    /// The bitset is zero.
+    InvalidInput,
+}
+
+/// A task waiting on a futex.
+struct Waiter {
+    /// The address of the futex, changed by [`futex_requeue`].
+    key: AtomicUsize,
+    bitset: u32,
+    woken: AtomicBool,
+    wq: WaitQueue,
+}
+
+type Bucket = SpinNoIrq<VecDeque<Arc<Waiter>>>;
+type BucketGuard = SpinNoIrqGuard<'static, VecDeque<Arc<Waiter>>>;
+
+static BUCKETS: [Bucket; NUM_BUCKETS] = [const { SpinNoIrq::new(VecDeque::new()) }; NUM_BUCKETS];
+
+fn bucket_index(key: usize) -> usize {
+    // Futex words are 4-byte aligned, and nearby ones often share a cache
+    // line, so mix in the higher bits.
+    let key = key >> 2;
+    (key ^ (key >> 6) ^ (key >> 12)) % NUM_BUCKETS
+}
+
+fn key_of(futex: &AtomicU32) -> usize {
+    futex as *const AtomicU32 as usize
+}
+
+/// Locks the buckets of the two keys in order, the second guard is [`None`]
+/// if they are in the same bucket.
+fn lock_two(key1: usize, key2: usize) -> (BucketGuard, Option<BucketGuard>) {
+    let (idx1, idx2) = (bucket_index(key1), bucket_index(key2));
+    if idx1 == idx2 {
+        (BUCKETS[idx1].lock(), None)
+    } else if idx1 < idx2 {
+        let guard1 = BUCKETS[idx1].lock();
+        (guard1, Some(BUCKETS[idx2].lock()))
+    } else {
+        let guard2 = BUCKETS[idx2].lock();
+        (BUCKETS[idx1].lock(), Some(guard2))
+    }
+}
+
+/// Marks the waiter as woken up, and wakes up its task. It must have been
+/// removed from its bucket.
+fn wake_waiter(waiter: &Waiter) {
+    waiter.woken.store(true, Ordering::Release);
+    waiter.wq.notify_one(false);
+}
+
+/// Removes the current waiter from its bucket after a timeout, returns
+/// `false` if it has been woken up meanwhile.
+fn remove_waiter(waiter: &Arc<Waiter>) -> bool {
+    loop {
+        let key = waiter.key.load(Ordering::Acquire);
+        let mut bucket = BUCKETS[bucket_index(key)].lock();
+        // It may have been requeued before we locked the bucket.
+        if waiter.key.load(Ordering::Acquire) != key {
+            continue;
+        }
+        return match bucket.iter().position(|w| Arc::ptr_eq(w, waiter)) {
+            Some(idx) => {
+                bucket.remove(idx);
+                true
+            }
+            None => false,
+        };
+    }
+}
+
+/// Blocks the current task on the futex at `key` if `should_block` returns
+/// `true`, which is checked under the lock of the bucket.
+fn wait_on(
+    key: usize,
+    should_block: impl FnOnce() -> bool,
+    timeout: Option<core::time::Duration>,
+    bitset: u32,
+) -> Result<(), FutexError> {
+    if bitset == 0 {
+        return Err(FutexError::InvalidInput);
+    }
+    let waiter = Arc::new(Waiter {
+        key: AtomicUsize::new(key),
+        bitset,
+        woken: AtomicBool::new(false),
+        wq: WaitQueue::new(),
+    });
+    {
+        let mut bucket = BUCKETS[bucket_index(key)].lock();
+        if !should_block() {
+            return Err(FutexError::WouldBlock);
+        }
+        bucket.push_back(waiter.clone());
+    }
+
+    let woken = || waiter.woken.load(Ordering::Acquire);
+    #[cfg(feature = "irq")]
+    if let Some(dur) = timeout {
+        if waiter.wq.wait_timeout_until(dur, woken) && remove_waiter(&waiter) {
+            return Err(FutexError::TimedOut);
+        }
+        return Ok(());
+    }
+    #[cfg(not(feature = "irq"))]
+    if timeout.is_some() {
+        warn!("futex_wait: the `timeout` argument is ignored without the `irq` feature");
+    }
+    waiter.wq.wait_until(woken);
+    Ok(())
+}
+
+/// Wakes up at most `count` tasks waiting on the futex at `key`, whose
+/// bitsets intersect `bitset`.
+fn wake_on(key: usize, count: usize, bitset: u32) -> Result<usize, FutexError> {
+    if bitset == 0 {
+        return Err(FutexError::InvalidInput);
+    }
+    let mut woken = Vec::new();
+    {
+        let mut bucket = BUCKETS[bucket_index(key)].lock();
+        bucket.retain(|w| {
+            let matched = woken.len() < count
+                && w.key.load(Ordering::Acquire) == key
+                && w.bitset & bitset != 0;
+            if matched {
+                woken.push(w.clone());
+            }
+            !matched
+        });
+    }
+    for waiter in &woken {
+        wake_waiter(waiter);
+    }
+    Ok(woken.len())
+}
+
+/// Blocks the current task on `futex` if it holds `val`, until it is woken
+/// up by [`futex_wake`] with a bitset that intersects `bitset`, or the
+/// given duration has elapsed.
+///
+/// The value is checked and the task is queued atomically with respect to
+/// the wake-ups, so the wake-ups after changing the value are not missed.
+///
+/// The timeout is ignored if the `irq` feature is not enabled.
+pub fn futex_wait(
+    futex: &AtomicU32,
+    val: u32,
+    timeout: Option<core::time::Duration>,
+    bitset: u32,
+) -> Result<(), FutexError> {
+    let should_block = || futex.load(Ordering::SeqCst) == val;
+    wait_on(key_of(futex), should_block, timeout, bitset)
+}
+
+/// Wakes up at most `count` tasks waiting on `futex`, whose bitsets
+/// intersect `bitset`. Returns the number of tasks woken up.
+pub fn futex_wake(futex: &AtomicU32, count: usize, bitset: u32) -> Result<usize, FutexError> {
+    wake_on(key_of(futex), count, bitset)
+}
+
+/// Locks the mutex whose word `owner_id` holds the ID of the owner task (or
+/// `0` if it is unlocked), with priority inheritance.
+///
+/// The current task sets the word to its ID if the mutex is unlocked.
+/// Otherwise it lends its real-time priority to the owner (see
+/// [`pi_wait_begin`](crate::pi_wait_begin)), and waits on the word until it is
+/// released by [`futex_unlock_pi`].
+///
+/// # Panics
+///
+/// Panics if the current task already owns the mutex.
+pub fn futex_lock_pi(owner_id: &AtomicU64) {
+    let curr = crate::current();
+    let current_id = curr.id().as_u64();
+    let key = owner_id as *const AtomicU64 as usize;
+    let mut waited = false;
+    loop {
+        match owner_id.compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(id) => {
+                assert_ne!(
+                    id,
+                    current_id,
+                    "{} tried to acquire mutex it already owns.",
+                    curr.id_name()
+                );
+                crate::pi_wait_begin(owner_id);
+                let should_block = || owner_id.load(Ordering::SeqCst) != 0;
+                // Fails at once if it has been released.
+                let _ = wait_on(key, should_block, None, FUTEX_BITSET_MATCH_ANY);
+                crate::pi_wait_end();
+                waited = true;
+            }
+        }
+    }
+    if waited {
+        crate::pi_acquired();
+    }
+}
+
+/// Unlocks the mutex locked by [`futex_lock_pi`], wakes up a task waiting for
+/// it, and drops the priority the current task has inherited from the
+/// waiters.
+///
+/// # Panics
+///
+/// Panics if the current task does not own the mutex.
+pub fn futex_unlock_pi(owner_id: &AtomicU64) {
+    // Pairs with `pi_release`, see `pi_wait_begin`.
+    let id = owner_id.swap(0, Ordering::SeqCst);
+    let curr = crate::current();
+    assert_eq!(
+        id,
+        curr.id().as_u64(),
+        "{} tried to release mutex it doesn't own",
+        curr.id_name()
+    );
+    let _ = wake_on(owner_id as *const AtomicU64 as usize, 1, FUTEX_BITSET_MATCH_ANY);
+    crate::pi_release();
+}
+
+/// Wakes up at most `wake_count` tasks waiting on `futex`, and moves at most
+/// `requeue_count` of the remaining ones to wait on `futex2`. Returns the
+/// number of tasks woken up and moved.
+///
+/// If `expected` is given, it fails with [`FutexError::WouldBlock`] unless
+/// `futex` still holds that value (i.e., `FUTEX_CMP_REQUEUE`).
+pub fn futex_requeue(
+    futex: &AtomicU32,
+    wake_count: usize,
+    futex2: &AtomicU32,
+    requeue_count: usize,
+    expected: Option<u32>,
+) -> Result<usize, FutexError> {
+    let (key1, key2) = (key_of(futex), key_of(futex2));
+    let (mut bucket1, mut bucket2) = lock_two(key1, key2);
+    if expected.is_some_and(|val| futex.load(Ordering::SeqCst) != val) {
+        return Err(FutexError::WouldBlock);
+    }
+
+    let mut woken = Vec::new();
+    let mut moved = Vec::new();
+    bucket1.retain(|w| {
+        if w.key.load(Ordering::Acquire) != key1 {
+            true
+        } else if woken.len() < wake_count {
+            woken.push(w.clone());
+            false
+        } else if moved.len() < requeue_count {
+            w.key.store(key2, Ordering::Release);
+            moved.push(w.clone());
+            // Stays in the same bucket if the keys share it.
+            bucket2.is_none()
+        } else {
+            true
+        }
+    });
+    if let Some(bucket2) = bucket2.as_mut() {
+        bucket2.extend(moved.iter().cloned());
+    }
+    drop(bucket2);
+    drop(bucket1);
+
+    for waiter in &woken {
+        wake_waiter(waiter);
+    }
+    Ok(woken.len() + moved.len())
+}
@@ -0,0 +1,347 @@
+//! A lightweight async runtime on top of tasks.
+//!
+//! Futures are run by [`block_on`] on the current task, or spawned onto an
+//! [`Executor`], which runs many of them on the tasks that call
+//! [`Executor::run`]. Wakers are backed by wait queues: a task with no future
+//! to poll blocks in a wait queue until a future is woken up.
+//!
+//! Futures can wait for wait queues with [`WaitQueue::wait_until_async`], for
+//! timers with [`sleep`] and [`sleep_until`], or give way to other futures
+//! with [`yield_now`].
+
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, task::Wake};
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use kspin::SpinNoIrq;
+
+use crate::WaitQueue;
+
+/// The future returned by [`WaitQueue::wait_until_async`].
+pub struct WaitUntil<'a, F> {
+    wq: &'a WaitQueue,
+    condition: F,
+    /// Identifies the waker registered in the wait queue.
+    token: Option<u64>,
+}
+
+impl<'a, F: Fn() -> bool> WaitUntil<'a, F> {
+    pub(crate) fn new(wq: &'a WaitQueue, condition: F) -> Self {
+        Self {
+            wq,
+            condition,
+            token: None,
+        }
+    }
+}
+
+// The condition is never pinned.
+impl<F> Unpin for WaitUntil<'_, F> {}
+
+impl<F: Fn() -> bool> Future for WaitUntil<'_, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if this
+            .wq
+            .register_waker(&mut this.token, cx.waker(), &this.condition)
+        {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+impl<F> Drop for WaitUntil<'_, F> {
+    fn drop(&mut self) {
+        if let Some(token) = self.token {
+            self.wq.unregister_waker(token);
+        }
+    }
+}
+
+/// The future returned by [`yield_now`].
+pub struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Returns a future that lets the other ready futures run first.
+pub fn yield_now() -> YieldNow {
+    YieldNow(false)
+}
+
+/// The future returned by [`sleep`] and [`sleep_until`].
+#[cfg(feature = "irq")]
+pub struct Sleep {
+    deadline: axhal::time::TimeValue,
+    timer: Option<crate::TimerHandle>,
+}
+
+#[cfg(feature = "irq")]
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if axhal::time::wall_time() >= self.deadline {
+            return Poll::Ready(());
+        }
+        if !self.timer.as_ref().is_some_and(|t| t.is_active()) {
+            let waker = cx.waker().clone();
+            self.timer = Some(crate::set_timer(self.deadline, move |_| waker.wake()));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "irq")]
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timer) = &self.timer {
+            timer.cancel();
+        }
+    }
+}
+
+/// Returns a future that completes after the given duration.
+#[cfg(feature = "irq")]
+pub fn sleep(dur: core::time::Duration) -> Sleep {
+    sleep_until(axhal::time::wall_time() + dur)
+}
+
+/// Returns a future that completes at the given deadline.
+#[cfg(feature = "irq")]
+pub fn sleep_until(deadline: axhal::time::TimeValue) -> Sleep {
+    Sleep {
+        deadline,
+        timer: None,
+    }
+}
+
+/// Wakes up the task that runs [`block_on`].
+struct BlockOnWaker {
+    woken: AtomicBool,
+    wq: WaitQueue,
+}
+
+impl Wake for BlockOnWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+}
+
+/// Runs a future to completion on the current task, which blocks while the
+/// future is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let inner = Arc::new(BlockOnWaker {
+        woken: AtomicBool::new(false),
+        wq: WaitQueue::new(),
+    });
+    let waker = Waker::from(inner.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        inner
+            .wq
+            .wait_until(|| inner.woken.swap(false, Ordering::AcqRel));
+    }
+}
+
+/// The job is not in the ready queue and not being polled.
+const JOB_IDLE: u8 = 0;
+/// The job is in the ready queue.
+const JOB_QUEUED: u8 = 1;
+/// The job is being polled.
+const JOB_RUNNING: u8 = 2;
+/// The job is being polled, and it has been woken up meanwhile.
+const JOB_NOTIFIED: u8 = 3;
+/// The future has completed.
+const JOB_DONE: u8 = 4;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A future spawned onto an executor.
+struct Job {
+    state: AtomicU8,
+    /// Only accessed by the task that changes the state to `JOB_RUNNING`.
+    future: UnsafeCell<Option<BoxFuture>>,
+    executor: Arc<ExecutorInner>,
+}
+
+unsafe impl Sync for Job {}
+
+impl Job {
+    /// Polls the future once. The job must have been taken from the ready
+    /// queue.
+    fn run(self: Arc<Self>) {
+        self.state.store(JOB_RUNNING, Ordering::Release);
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        // SAFETY: only one task can take the job from the ready queue.
+        let slot = unsafe { &mut *self.future.get() };
+        let Some(future) = slot.as_mut() else {
+            return;
+        };
+        if future.as_mut().poll(&mut cx).is_ready() {
+            *slot = None;
+            self.state.store(JOB_DONE, Ordering::Release);
+            if self.executor.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+                // Let all runners return.
+                self.executor.wq.notify_all(true);
+            }
+        } else if self
+            .state
+            .compare_exchange(JOB_RUNNING, JOB_IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            // Woken up while polling, run it again later.
+            self.state.store(JOB_QUEUED, Ordering::Release);
+            self.executor.push(self.clone());
+        }
+    }
+}
+
+impl Wake for Job {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let new = match state {
+                JOB_IDLE => JOB_QUEUED,
+                JOB_RUNNING => JOB_NOTIFIED,
+                _ => return, // queued, notified or done
+            };
+            match self
+                .state
+                .compare_exchange(state, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(s) => state = s,
+            }
+        }
+        if state == JOB_IDLE {
+            self.executor.push(self.clone());
+        }
+    }
+}
+
+struct ExecutorInner {
+    ready: SpinNoIrq<VecDeque<Arc<Job>>>,
+    /// The number of futures that have not completed.
+    pending: AtomicUsize,
+    /// The tasks in [`Executor::run`] with no job to run.
+    wq: WaitQueue,
+}
+
+impl ExecutorInner {
+    fn push(&self, job: Arc<Job>) {
+        self.ready.lock().push_back(job);
+        self.wq.notify_one(false);
+    }
+}
+
+/// An executor that runs many futures on a few tasks.
+///
+/// Futures are spawned with [`spawn`](Executor::spawn), and run by the
+/// tasks that call [`run`](Executor::run). A future is polled by one task at
+/// a time. The handle can be cloned to spawn futures from other futures.
+#[derive(Clone)]
+pub struct Executor {
+    inner: Arc<ExecutorInner>,
+}
+
+impl Executor {
+    /// Creates an executor with no future.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(ExecutorInner {
+                ready: SpinNoIrq::new(VecDeque::new()),
+                pending: AtomicUsize::new(0),
+                wq: WaitQueue::new(),
+            }),
+        }
+    }
+
+    /// Spawns a future onto the executor, it is polled the next time a task
+    /// runs the executor.
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.inner.pending.fetch_add(1, Ordering::AcqRel);
+        let job = Arc::new(Job {
+            state: AtomicU8::new(JOB_QUEUED),
+            future: UnsafeCell::new(Some(Box::pin(future))),
+            executor: self.inner.clone(),
+        });
+        self.inner.push(job);
+    }
+
+    /// Returns the number of futures that have not completed.
+    pub fn pending(&self) -> usize {
+        self.inner.pending.load(Ordering::Acquire)
+    }
+
+    /// Runs the futures on the current task, until all of them complete.
+    ///
+    /// The task blocks while there is no future to poll, and yields after
+    /// polling each round of ready futures, so that futures that keep waking
+    /// themselves up do not starve other tasks. Several tasks can run the same
+    /// executor.
+    pub fn run(&self) {
+        let inner = &self.inner;
+        loop {
+            let round = inner.ready.lock().len();
+            if round > 0 {
+                for _ in 0..round {
+                    let job = inner.ready.lock().pop_front();
+                    match job {
+                        Some(job) => job.run(),
+                        None => break, // taken by other tasks
+                    }
+                }
+                crate::yield_now();
+                continue;
+            }
+            if self.pending() == 0 {
+                return;
+            }
+            inner
+                .wq
+                .wait_until(|| self.pending() == 0 || !inner.ready.lock().is_empty());
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -2,7 +2,12 @@
 //!
 //! This module provides primitives for task management, including task
 //! creation, scheduling, sleeping, termination, etc. The scheduler algorithm
-//! is configurable by cargo features.
+//! for normal tasks can be selected at runtime (see [`select_scheduler`]),
+//! and real-time tasks (see [`set_scheduler`]) always run before them. Tasks
+//! can be cancelled (see [`cancel`]) or sent notifications, which interrupt
+//! their interruptible waits. Futures can be run on tasks by the async
+//! runtime in [`future`]. User-space locks can be built on futexes (see
+//! [`futex_wait`]).
 //!
 //! # Cargo Features
 //!
@@ -10,20 +15,32 @@
 //!   management and scheduling is used, as well as more task-related APIs.
 //!   Otherwise, only a few APIs with naive implementation is available.
 //! - `irq`: Interrupts are enabled. If this feature is enabled, timer-based
-//!    APIs can be used, such as [`sleep`], [`sleep_until`], and
-//!    [`WaitQueue::wait_timeout`].
+//!    APIs can be used, such as [`sleep`], [`sleep_until`],
+//!    [`WaitQueue::wait_timeout`], and [`set_timer`].
 //! - `preempt`: Enable preemptive scheduling.
-//! - `sched_fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
-//!   `multitask` feature if it is enabled. This feature is enabled by default,
-//!   and it can be overriden by other scheduler features.
-//! - `sched_rr`: Use the [Round-robin preemptive scheduler][2]. It also enables
-//!   the `multitask` and `preempt` features if it is enabled.
-//! - `sched_cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
-//!   the `multitask` and `preempt` features if it is enabled.
+//! - `tickless`: Stop the periodic timer tick while a CPU is idle, and only
+//!   wake it up for the next timer event, or with an IPI when other CPUs give
+//!   it tasks to run. It also enables the `irq` feature.
+//! - `smp`: Enable SMP support. Each CPU has its own run queue, and idle CPUs
+//!   steal tasks from busy ones.
+//! - `paging`: Allocate kernel stacks with an unmapped guard page below each on
+//!   x86_64, so that stack overflows are caught (see [`check_stack_guard`])
+//!   instead of silently corrupting other memory. Other architectures have no
+//!   separate stack to handle the fault on, so their kernel stacks are still
+//!   allocated from the heap without guard pages.
+//! - `stack-usage`: Fill kernel stacks with a pattern when tasks are spawned,
+//!   to report the maximum stack usage of each task (see [`TaskInfo`]).
+//! - `sched_fifo`: Use the FIFO cooperative scheduler (`fifo`) by default. It
+//!   also enables the `multitask` feature if it is enabled. This feature is
+//!   enabled by default, and it can be overriden by other scheduler features.
+//! - `sched_rr`: Use the Round-robin preemptive scheduler (`rr`) by default.
+//!   It also enables the `multitask` and `preempt` features if it is enabled.
+//! - `sched_cfs`: Use the Completely Fair Scheduler (`cfs`) by default. It also
+//!   enables the `multitask` and `preempt` features if it is enabled.
 //!
-//! [1]: scheduler::FifoScheduler
-//! [2]: scheduler::RRScheduler
-//! [3]: scheduler::CFScheduler
+//! The default scheduler is overridden by the `scheduler` config item. The
+//! preemptive schedulers only switch tasks at yields and blocks if the
+//! `preempt` feature is disabled.
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
@@ -42,7 +59,14 @@ cfg_if::cfg_if! {
         extern crate log;
         extern crate alloc;
 
+        mod cpumask;
+        mod futex;
+        mod notify;
+        mod registry;
+        mod rt;
         mod run_queue;
+        mod sched;
+        mod stats;
         mod task;
         mod task_ext;
         mod api;
@@ -51,6 +75,9 @@ cfg_if::cfg_if! {
         #[cfg(feature = "irq")]
         mod timers;
 
+        #[doc(cfg(feature = "multitask"))]
+        pub mod future;
+
         #[doc(cfg(feature = "multitask"))]
         pub use self::api::*;
         pub use self::api::{sleep, sleep_until, yield_now};
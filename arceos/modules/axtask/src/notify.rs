@@ -0,0 +1,133 @@
+//! Task cancellation and asynchronous notifications.
+//!
+//! A task can be asked to stop with [`cancel`], or be sent notifications,
+//! which are bits in a 64-bit mask, with [`send_notification`]. Neither of
+//! them stops the task by itself: the task checks them with [`is_cancelled`]
+//! and [`take_notifications`] when it sees fit, and its interruptible waits
+//! (e.g. [`WaitQueue::wait_interruptible`]) return [`Interrupted`] early, so
+//! that it does not miss them while blocked. Kernels can build POSIX signals
+//! on the notifications.
+//!
+//! [`WaitQueue::wait_interruptible`]: crate::WaitQueue::wait_interruptible
+
+use core::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::run_queue::unblock_task;
+use crate::task::TaskState;
+use crate::{current, AxTaskRef};
+
+/// The error returned by interruptible waits, when the current task is
+/// cancelled or has unmasked notifications pending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted;
+
+/// The cancellation and notification states of a task.
+pub(crate) struct NotifyState {
+    cancelled: AtomicBool,
+    pending: AtomicU64,
+    masked: AtomicU64,
+    /// Whether the task is in an interruptible wait. It is locked while the
+    /// task is woken up, so that a wait that has finished is not interrupted.
+    interruptible: SpinNoIrq<bool>,
+}
+
+impl NotifyState {
+    pub const fn new() -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+            pending: AtomicU64::new(0),
+            masked: AtomicU64::new(0),
+            interruptible: SpinNoIrq::new(false),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether the task is cancelled, or has unmasked notifications
+    /// pending.
+    pub fn is_interrupted(&self) -> bool {
+        self.is_cancelled()
+            || self.pending.load(Ordering::SeqCst) & !self.masked.load(Ordering::SeqCst) != 0
+    }
+
+    pub fn set_interruptible(&self, interruptible: bool) {
+        *self.interruptible.lock() = interruptible;
+    }
+}
+
+/// Wakes up the task if it is in an interruptible wait, after it is
+/// cancelled or notified.
+fn interrupt(task: &AxTaskRef) {
+    // Pairs with the fence in `abort_block_if_interrupted`: either the task
+    // sees the new states, or we see that it has blocked.
+    fence(Ordering::SeqCst);
+    let interruptible = task.notify_state().interruptible.lock();
+    if *interruptible {
+        unblock_task(task.clone(), true);
+    }
+}
+
+/// Lets the current task keep running, if it is interrupted right after it
+/// blocks in an interruptible wait.
+///
+/// The interruption may come before the task is marked as blocked, when
+/// [`interrupt`] cannot wake it up. The task cannot be woken up as usual then,
+/// since it is still running on this CPU, so its blocking is aborted instead.
+/// Does nothing if it has been woken up in the meantime.
+pub(crate) fn abort_block_if_interrupted(task: &AxTaskRef) {
+    fence(Ordering::SeqCst);
+    if task.notify_state().is_interrupted() {
+        task.transition_state(TaskState::Blocked, TaskState::Running);
+    }
+}
+
+/// Asks the given task to stop.
+///
+/// The task is flagged as cancelled (see [`is_cancelled`]) and woken up if
+/// it is in an interruptible wait. It is up to the task to exit.
+pub fn cancel(task: &AxTaskRef) {
+    debug!("task cancel: {}", task.id_name());
+    task.notify_state().cancelled.store(true, Ordering::SeqCst);
+    interrupt(task);
+}
+
+/// Returns whether the current task has been cancelled.
+pub fn is_cancelled() -> bool {
+    current().notify_state().is_cancelled()
+}
+
+/// Sends notifications `bits` to the given task.
+///
+/// The task is woken up if it is in an interruptible wait, unless all the
+/// bits are masked by [`set_notification_mask`].
+pub fn send_notification(task: &AxTaskRef, bits: u64) {
+    let state = task.notify_state();
+    state.pending.fetch_or(bits, Ordering::SeqCst);
+    if state.is_interrupted() {
+        interrupt(task);
+    }
+}
+
+/// Returns the pending notifications of the current task, including the
+/// masked ones.
+pub fn pending_notifications() -> u64 {
+    current().notify_state().pending.load(Ordering::SeqCst)
+}
+
+/// Takes the pending notifications of the current task in `mask`, returns
+/// the bits that were pending.
+pub fn take_notifications(mask: u64) -> u64 {
+    current().notify_state().pending.fetch_and(!mask, Ordering::SeqCst) & mask
+}
+
+/// Sets the notifications that do not interrupt the waits of the current
+/// task, returns the old mask.
+///
+/// Masked notifications are still kept pending.
+pub fn set_notification_mask(mask: u64) -> u64 {
+    current().notify_state().masked.swap(mask, Ordering::SeqCst)
+}
@@ -0,0 +1,90 @@
+//! The registry of all live tasks, for introspection.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use kspin::SpinNoIrq;
+
+use crate::stats::SchedStats;
+use crate::task::{TaskId, TaskState};
+use crate::{AxTask, AxTaskRef};
+
+/// All tasks that have not been dropped, indexed by the task ID.
+static TASKS: SpinNoIrq<BTreeMap<u64, Weak<AxTask>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// A snapshot of the status of a task, returned by [`TaskInner::info`].
+///
+/// [`TaskInner::info`]: crate::TaskInner::info
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    /// The task ID.
+    pub id: TaskId,
+    /// The task name.
+    pub name: String,
+    /// The task state.
+    pub state: TaskState,
+    /// The CPU that the task is running on, or it last ran on.
+    pub cpu_id: usize,
+    /// The priority set by [`set_priority`](crate::set_priority), 0 by default.
+    pub priority: isize,
+    /// The CPU time that the task has run for.
+    pub runtime: Duration,
+    /// The CPU time and scheduler statistics.
+    pub stats: SchedStats,
+    /// The exit code, if the task has exited.
+    pub exit_code: Option<i32>,
+    /// The size of the kernel stack in bytes, or 0 if the task runs on a
+    /// stack it does not own (e.g., the boot stack).
+    pub stack_size: usize,
+    /// The maximum number of bytes of the kernel stack used so far, or 0 if
+    /// the `stack-usage` feature is disabled.
+    pub stack_high_water: usize,
+}
+
+pub(crate) fn register(task: &AxTaskRef) {
+    TASKS
+        .lock()
+        .insert(task.id().as_u64(), Arc::downgrade(task));
+}
+
+/// Removes a task that is being dropped. Its statistics are counted as
+/// dropped at the same time, so that [`total_sched_stats`] neither misses nor
+/// counts it twice.
+///
+/// [`total_sched_stats`]: crate::total_sched_stats
+pub(crate) fn unregister(task: &AxTask) {
+    let mut tasks = TASKS.lock();
+    tasks.remove(&task.id().as_u64());
+    if !task.is_idle() {
+        crate::stats::add_dropped(&task.sched_stats());
+    }
+}
+
+/// Runs `f` with the registry locked, on the tasks that have not been
+/// unregistered, including the ones being dropped.
+pub(crate) fn with_all_tasks<R>(f: impl FnOnce(&mut dyn Iterator<Item = &AxTask>) -> R) -> R {
+    let tasks = TASKS.lock();
+    // Safety: a task is only dropped after it unregisters itself, which
+    // waits for the lock, so the tasks in the registry are still alive even
+    // if their reference counts have dropped to zero.
+    let mut iter = tasks.values().map(|t| unsafe { &*t.as_ptr() });
+    f(&mut iter)
+}
+
+/// Returns an iterator over all live tasks, in the order of task IDs.
+///
+/// It iterates over a snapshot, so the tasks spawned after this call are not
+/// included. The references returned keep the tasks from being dropped, so
+/// do not keep them for long.
+pub fn all_tasks() -> impl Iterator<Item = AxTaskRef> {
+    let tasks: Vec<_> = TASKS.lock().values().filter_map(Weak::upgrade).collect();
+    tasks.into_iter()
+}
+
+/// Finds the live task with the given ID.
+pub fn find_task(id: u64) -> Option<AxTaskRef> {
+    TASKS.lock().get(&id).and_then(Weak::upgrade)
+}
@@ -0,0 +1,379 @@
+//! The real-time scheduling class and priority inheritance.
+//!
+//! Real-time tasks have a fixed priority from 1 to [`MAX_RT_PRIO`], and always
+//! run before the normal tasks, which are scheduled by the selected
+//! [`Scheduler`](crate::Scheduler). A real-time task runs until it blocks, yields, or a task
+//! with a higher priority becomes ready. Round-robin tasks are also switched
+//! out when their time slices run out, if there are other tasks of the same
+//! priority.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::{AxTaskRef, Scheduler};
+
+/// The highest real-time priority. The lowest one is 1.
+pub const MAX_RT_PRIO: u8 = 99;
+
+/// Timer ticks in a time slice of [`SchedPolicy::RoundRobin`] tasks.
+const RT_TIME_SLICE: usize = 10;
+
+/// The maximum number of owners that a priority is passed on to along a
+/// chain of mutexes, which also stops at deadlock cycles.
+const MAX_PI_CHAIN: usize = 16;
+
+/// Serializes the changes of inherited priorities. It is only taken when
+/// mutexes with priority inheritance are contended.
+static PI_LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+/// The value of `queued` when the task is not in any scheduler.
+const NOT_QUEUED: u8 = 0;
+/// The value of `queued` when the task is in the normal scheduler.
+const QUEUED_NORMAL: u8 = u8::MAX;
+
+/// The scheduling policies.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// Scheduled by the selected [`Scheduler`], when there is no ready
+    /// real-time task.
+    Normal = 0,
+    /// Real-time, first in first out among the tasks of the same priority.
+    Fifo = 1,
+    /// Real-time, round-robin among the tasks of the same priority.
+    RoundRobin = 2,
+}
+
+impl From<u8> for SchedPolicy {
+    #[inline]
+    fn from(policy: u8) -> Self {
+        match policy {
+            0 => Self::Normal,
+            1 => Self::Fifo,
+            2 => Self::RoundRobin,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// The real-time scheduling parameters of a task.
+pub(crate) struct RtState {
+    policy: AtomicU8,
+    /// The priority set by `set_scheduler()`, 0 for normal tasks.
+    priority: AtomicU8,
+    /// The priority inherited from the tasks waiting for the mutexes it
+    /// holds, 0 if there is none.
+    inherited: AtomicU8,
+    /// The address of the owner ID of the mutex with priority inheritance
+    /// that the task waits for, 0 if there is none. Only changed with
+    /// `PI_LOCK` held.
+    blocked_on: AtomicUsize,
+    time_slice: AtomicUsize,
+    /// Where the task is queued, i.e., `NOT_QUEUED`, `QUEUED_NORMAL` or the
+    /// real-time priority. Only accessed with the run queue locked.
+    queued: AtomicU8,
+}
+
+impl RtState {
+    pub const fn new() -> Self {
+        Self {
+            policy: AtomicU8::new(SchedPolicy::Normal as u8),
+            priority: AtomicU8::new(0),
+            inherited: AtomicU8::new(0),
+            blocked_on: AtomicUsize::new(0),
+            time_slice: AtomicUsize::new(RT_TIME_SLICE),
+            queued: AtomicU8::new(NOT_QUEUED),
+        }
+    }
+
+    pub fn policy(&self) -> SchedPolicy {
+        self.policy.load(Ordering::Acquire).into()
+    }
+
+    /// Returns the priority set by `set_scheduler()`, 0 for normal tasks.
+    pub fn priority(&self) -> u8 {
+        self.priority.load(Ordering::Acquire)
+    }
+
+    pub fn set_params(&self, policy: SchedPolicy, priority: u8) {
+        self.policy.store(policy as u8, Ordering::Release);
+        self.priority.store(priority, Ordering::Release);
+        self.time_slice.store(RT_TIME_SLICE, Ordering::Relaxed);
+    }
+
+    /// Returns the real-time priority that the task is scheduled with,
+    /// including the inherited one, or 0 if it is in the normal class.
+    pub fn effective_priority(&self) -> u8 {
+        self.priority().max(self.inherited.load(Ordering::Acquire))
+    }
+
+    /// Whether the task is round-robin. Normal tasks that inherit a priority
+    /// are first in first out.
+    fn is_round_robin(&self) -> bool {
+        self.policy() == SchedPolicy::RoundRobin
+    }
+}
+
+/// The scheduler of a run queue, with the real-time tasks in a queue per
+/// priority, and the normal tasks in the selected [`Scheduler`].
+pub(crate) struct ClassScheduler {
+    /// The queue of priority `p` is at index `p - 1`.
+    rt_queues: [VecDeque<AxTaskRef>; MAX_RT_PRIO as usize],
+    /// Bit `p - 1` is set if the queue of priority `p` is not empty.
+    rt_bitmap: u128,
+    normal: Box<dyn Scheduler>,
+}
+
+impl ClassScheduler {
+    pub fn new(normal: Box<dyn Scheduler>) -> Self {
+        Self {
+            rt_queues: [const { VecDeque::new() }; MAX_RT_PRIO as usize],
+            rt_bitmap: 0,
+            normal,
+        }
+    }
+
+    /// Replaces the scheduler of normal tasks, and moves the ready tasks to
+    /// the new one.
+    pub fn replace_normal(&mut self, normal: Box<dyn Scheduler>) {
+        let mut old = core::mem::replace(&mut self.normal, normal);
+        while let Some(task) = old.pick_next_task() {
+            self.normal.add_task(task);
+        }
+    }
+
+    /// Returns the highest priority of the queued real-time tasks, or 0 if
+    /// there is none.
+    pub fn highest_rt_priority(&self) -> u8 {
+        (u128::BITS - self.rt_bitmap.leading_zeros()) as u8
+    }
+
+    fn push_rt(&mut self, task: AxTaskRef, prio: u8, front: bool) {
+        task.rt().queued.store(prio, Ordering::Relaxed);
+        let queue = &mut self.rt_queues[prio as usize - 1];
+        if front {
+            queue.push_front(task);
+        } else {
+            queue.push_back(task);
+        }
+        self.rt_bitmap |= 1 << (prio - 1);
+    }
+
+    fn pop_rt(&mut self, prio: u8, idx: usize) -> Option<AxTaskRef> {
+        let queue = &mut self.rt_queues[prio as usize - 1];
+        let task = queue.remove(idx);
+        if queue.is_empty() {
+            self.rt_bitmap &= !(1 << (prio - 1));
+        }
+        task
+    }
+
+    pub fn add_task(&mut self, task: AxTaskRef) {
+        match task.rt().effective_priority() {
+            0 => {
+                task.rt().queued.store(QUEUED_NORMAL, Ordering::Relaxed);
+                self.normal.add_task(task);
+            }
+            prio => self.push_rt(task, prio, false),
+        }
+    }
+
+    /// Removes a task from the scheduler, returns `None` if it is not queued.
+    pub fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+        let task = match task.rt().queued.load(Ordering::Relaxed) {
+            NOT_QUEUED => return None,
+            QUEUED_NORMAL => self.normal.remove_task(task),
+            prio => {
+                let queue = &self.rt_queues[prio as usize - 1];
+                let idx = queue.iter().position(|t| Arc::ptr_eq(t, task))?;
+                self.pop_rt(prio, idx)
+            }
+        }?;
+        task.rt().queued.store(NOT_QUEUED, Ordering::Relaxed);
+        Some(task)
+    }
+
+    pub fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        let task = match self.highest_rt_priority() {
+            0 => self.normal.pick_next_task(),
+            prio => self.pop_rt(prio, 0),
+        }?;
+        task.rt().queued.store(NOT_QUEUED, Ordering::Relaxed);
+        Some(task)
+    }
+
+    /// Takes the first task in the order they are picked that satisfies
+    /// `pred`, the other tasks stay in place.
+    #[cfg(any(feature = "smp", test))]
+    pub fn take_task_if(&mut self, mut pred: impl FnMut(&AxTaskRef) -> bool) -> Option<AxTaskRef> {
+        let rt_task = (1..=self.highest_rt_priority())
+            .rev()
+            .find_map(|prio| self.rt_queues[prio as usize - 1].iter().find(|t| pred(t)));
+        let task = match rt_task {
+            Some(task) => task.clone(),
+            None => self.normal.find_task(&mut pred)?,
+        };
+        self.remove_task(&task)
+    }
+
+    /// Puts the previous task back. A preempted real-time task goes to the
+    /// front of its queue, unless its time slice has run out.
+    pub fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool) {
+        let rt = prev.rt();
+        match rt.effective_priority() {
+            0 => {
+                rt.queued.store(QUEUED_NORMAL, Ordering::Relaxed);
+                self.normal.put_prev_task(prev, preempt);
+            }
+            prio => {
+                let expired = rt.time_slice.load(Ordering::Relaxed) == 0;
+                let front = preempt && !expired;
+                if !front {
+                    rt.time_slice.store(RT_TIME_SLICE, Ordering::Relaxed);
+                }
+                self.push_rt(prev, prio, front);
+            }
+        }
+    }
+
+    /// Advances the states of the current task at a timer tick, returns
+    /// whether it should be preempted.
+    pub fn task_tick(&mut self, current: &AxTaskRef) -> bool {
+        let rt = current.rt();
+        match rt.effective_priority() {
+            0 => self.normal.task_tick(current) || self.rt_bitmap != 0,
+            prio => {
+                if rt.is_round_robin() {
+                    let slice = rt.time_slice.load(Ordering::Relaxed).saturating_sub(1);
+                    if slice == 0 && self.rt_queues[prio as usize - 1].is_empty() {
+                        // No other task to take turns with, start a new slice.
+                        rt.time_slice.store(RT_TIME_SLICE, Ordering::Relaxed);
+                    } else {
+                        rt.time_slice.store(slice, Ordering::Relaxed);
+                        if slice == 0 {
+                            return true;
+                        }
+                    }
+                }
+                self.highest_rt_priority() > prio
+            }
+        }
+    }
+
+    pub fn set_priority(&mut self, task: &AxTaskRef, prio: isize) -> bool {
+        self.normal.set_priority(task, prio)
+    }
+}
+
+/// Returns whether `policy` and `priority` are valid scheduling parameters.
+pub(crate) fn check_params(policy: SchedPolicy, priority: u8) -> bool {
+    match policy {
+        SchedPolicy::Normal => priority == 0,
+        SchedPolicy::Fifo | SchedPolicy::RoundRobin => (1..=MAX_RT_PRIO).contains(&priority),
+    }
+}
+
+/// Marks that the current task is going to wait for a mutex with priority
+/// inheritance, whose owner ID is `owner_id`, until [`pi_wait_end`].
+///
+/// The real-time priority of the current task is lent to the owner, and is
+/// passed on along the chain of owners if the owner waits for another such
+/// mutex, and so on.
+pub fn pi_wait_begin(owner_id: &AtomicU64) {
+    let _lock = PI_LOCK.lock();
+    let curr = crate::current();
+    let rt = curr.rt();
+    rt.blocked_on.store(owner_id as *const _ as usize, Ordering::Release);
+    let prio = rt.effective_priority();
+    if prio == 0 {
+        return;
+    }
+    let mut owner_id = owner_id;
+    for _ in 0..MAX_PI_CHAIN {
+        let id = owner_id.load(Ordering::SeqCst);
+        let Some(owner) = crate::find_task(id) else {
+            break;
+        };
+        crate::run_queue::update_rt_params(&owner, |rt| {
+            if rt.inherited.load(Ordering::Acquire) < prio {
+                debug!("task inherits priority {}: {}", prio, owner.id_name());
+                rt.inherited.store(prio, Ordering::SeqCst);
+            }
+        });
+        // The owner may have released the mutex meanwhile, without seeing the
+        // inherited priority, see `pi_release`.
+        if owner_id.load(Ordering::SeqCst) != id {
+            update_inherited(&owner);
+            break;
+        }
+        let next = owner.rt().blocked_on.load(Ordering::Acquire);
+        if next == 0 {
+            break;
+        }
+        // Safety: the owner keeps the mutex it waits for alive until it calls
+        // `pi_wait_end`, which needs `PI_LOCK`.
+        owner_id = unsafe { &*(next as *const AtomicU64) };
+    }
+}
+
+/// Marks that the current task no longer waits for a mutex with priority
+/// inheritance, whether it has acquired it or not.
+pub fn pi_wait_end() {
+    let _lock = PI_LOCK.lock();
+    crate::current().rt().blocked_on.store(0, Ordering::Release);
+}
+
+/// Marks that the current task has acquired a mutex with priority
+/// inheritance after waiting for it, so that it inherits the priorities of the
+/// other tasks still waiting for the mutex.
+pub fn pi_acquired() {
+    let _lock = PI_LOCK.lock();
+    update_inherited(crate::current().as_task_ref());
+}
+
+/// Marks that the current task has released a mutex with priority
+/// inheritance.
+///
+/// The priority it has inherited from the tasks waiting for the mutex is
+/// dropped. It returns at once if the task has not inherited any priority, so
+/// uncontended mutexes do not pay for priority inheritance.
+pub fn pi_release() {
+    let curr = crate::current();
+    // Pairs with the check of the owner in `pi_wait_begin`, so that either
+    // this or the lender sees the other.
+    if curr.rt().inherited.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+    let _lock = PI_LOCK.lock();
+    update_inherited(curr.as_task_ref());
+}
+
+/// Sets the inherited priority of `task` to the highest priority of the
+/// tasks waiting for the mutexes it holds. `PI_LOCK` must be held.
+///
+/// It goes through all tasks, which is fine as it is only called when the
+/// mutexes are contended.
+fn update_inherited(task: &AxTaskRef) {
+    let id = task.id().as_u64();
+    let prio = crate::registry::with_all_tasks(|tasks| {
+        tasks
+            .filter(|t| {
+                let blocked_on = t.rt().blocked_on.load(Ordering::Acquire);
+                // Safety: see `pi_wait_begin`.
+                blocked_on != 0
+                    && unsafe { &*(blocked_on as *const AtomicU64) }.load(Ordering::SeqCst) == id
+            })
+            .map(|t| t.rt().effective_priority())
+            .max()
+            .unwrap_or(0)
+    });
+    if task.rt().inherited.load(Ordering::Acquire) != prio {
+        crate::run_queue::update_rt_params(task, |rt| {
+            rt.inherited.store(prio, Ordering::SeqCst);
+        });
+    }
+}
@@ -1,72 +1,373 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
-use kspin::SpinNoIrq;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(all(feature = "smp", feature = "preempt"))]
+use core::sync::atomic::{AtomicBool, AtomicU8};
+
+use kernel_guard::NoPreemptIrqSave;
+use kspin::{SpinNoIrq, SpinRaw};
 use lazyinit::LazyInit;
-use scheduler::BaseScheduler;
 
+#[cfg(feature = "smp")]
+use alloc::sync::Weak;
+
+use crate::rt::{ClassScheduler, RtState};
+use crate::sched::SchedulerCtor;
 use crate::task::{CurrentTask, TaskState};
-use crate::{AxTaskRef, Scheduler, TaskInner, WaitQueue};
+use crate::{AxTaskRef, CpuMask, TaskInner, WaitQueue};
 
-// TODO: per-CPU
-pub(crate) static RUN_QUEUE: LazyInit<SpinNoIrq<AxRunQueue>> = LazyInit::new();
+/// Run queues of all CPUs, indexed by the CPU ID.
+static RUN_QUEUES: [LazyInit<AxRunQueue>; axconfig::SMP] =
+    [const { LazyInit::new() }; axconfig::SMP];
 
-// TODO: per-CPU
-static EXITED_TASKS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::new());
+#[percpu::def_percpu]
+static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
 
-static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
+/// The task switched out last on this CPU, whose `on_cpu` flag is cleared by
+/// the next task once the switch has completed.
+#[cfg(feature = "smp")]
+#[percpu::def_percpu]
+static PREV_TASK: Weak<crate::AxTask> = Weak::new();
 
+/// A task that is switched out of this CPU but is no longer allowed to run
+/// here, which is moved to another CPU by the next task once the switch has
+/// completed.
+#[cfg(feature = "smp")]
 #[percpu::def_percpu]
-static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
+static MIGRATING_TASK: Option<AxTaskRef> = None;
 
+/// Number of timer ticks between two periodic load balancing.
+#[cfg(all(feature = "smp", feature = "irq"))]
+const LOAD_BALANCE_INTERVAL: usize = 10;
+
+/// The run queue of a CPU.
+///
+/// The scheduler can be accessed by other CPUs to wake up tasks or to steal
+/// tasks. It is only locked for a short time with IRQs disabled, and never
+/// across context switches, so at most one run queue is locked at a time.
 pub(crate) struct AxRunQueue {
-    scheduler: Scheduler,
+    cpu_id: usize,
+    scheduler: SpinRaw<ClassScheduler>,
+    /// Number of tasks in the scheduler, read without locking for load
+    /// balancing.
+    nr_ready: AtomicUsize,
+    exited_tasks: SpinNoIrq<VecDeque<AxTaskRef>>,
+    wait_for_exit: WaitQueue,
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    ticks: AtomicUsize,
+    /// The real-time priority of the task running on this CPU, which may be
+    /// out of date.
+    #[cfg(all(feature = "smp", feature = "preempt"))]
+    curr_rt_prio: AtomicU8,
+    /// Set by other CPUs that have put a task of a higher real-time priority
+    /// than the running one into this run queue, so that the running task is
+    /// preempted as soon as preemption is enabled on this CPU.
+    #[cfg(all(feature = "smp", feature = "preempt"))]
+    need_resched: AtomicBool,
+}
+
+/// A reference to the run queue of the current CPU.
+///
+/// IRQs and preemption are disabled while it is alive, so that the current
+/// task cannot be migrated until it reschedules.
+pub(crate) struct CurrentRunQueueRef {
+    inner: &'static AxRunQueue,
+    _guard: NoPreemptIrqSave,
+}
+
+impl Deref for CurrentRunQueueRef {
+    type Target = AxRunQueue;
+    fn deref(&self) -> &Self::Target {
+        self.inner
+    }
+}
+
+fn run_queue(cpu_id: usize) -> &'static AxRunQueue {
+    &RUN_QUEUES[cpu_id]
+}
+
+/// Returns the run queue of the current CPU.
+pub(crate) fn current_run_queue() -> CurrentRunQueueRef {
+    // Disable preemption before reading the CPU ID, otherwise the current
+    // task may be migrated to another CPU in between.
+    let guard = NoPreemptIrqSave::new();
+    CurrentRunQueueRef {
+        inner: run_queue(axhal::cpu::this_cpu_id()),
+        _guard: guard,
+    }
+}
+
+/// Iterates over the initialized run queues of other CPUs, starting from the
+/// next CPU of `cpu_id`.
+#[cfg(feature = "smp")]
+fn other_run_queues(cpu_id: usize) -> impl Iterator<Item = &'static AxRunQueue> {
+    (1..axconfig::SMP).filter_map(move |i| RUN_QUEUES[(cpu_id + i) % axconfig::SMP].get())
+}
+
+/// Selects the least loaded run queue among the online CPUs in `cpumask`,
+/// the current CPU is preferred if there are several of them. Preemption must
+/// be disabled.
+///
+/// Falls back to the current CPU if none of the CPUs in `cpumask` is online.
+#[cfg_attr(not(feature = "smp"), allow(unused_variables))]
+fn select_run_queue(cpumask: &CpuMask) -> &'static AxRunQueue {
+    let this_rq = run_queue(axhal::cpu::this_cpu_id());
+    #[cfg(feature = "smp")]
+    {
+        core::iter::once(this_rq)
+            .chain(other_run_queues(this_rq.cpu_id))
+            .filter(|rq| cpumask.get(rq.cpu_id))
+            .min_by_key(|rq| rq.nr_ready())
+            .unwrap_or(this_rq)
+    }
+    #[cfg(not(feature = "smp"))]
+    this_rq
+}
+
+/// Puts a newly spawned task into the least loaded run queue among the CPUs
+/// it is allowed to run on.
+pub(crate) fn add_task(task: AxTaskRef) {
+    let _guard = NoPreemptIrqSave::new();
+    task.accounting().on_ready(axhal::time::monotonic_time_nanos());
+    select_run_queue(&task.cpumask()).add_task(task);
+}
+
+/// Changes the CPU affinity of a task, see [`crate::set_affinity`].
+pub(crate) fn set_affinity(task: &AxTaskRef, cpumask: CpuMask) -> bool {
+    if !cpumask.iter().any(|cpu_id| RUN_QUEUES[cpu_id].is_inited()) {
+        return false;
+    }
+    debug!("task set affinity: {}, cpumask={:?}", task.id_name(), cpumask);
+    task.update_cpumask(cpumask);
+
+    // Tasks in run queues are moved when they are picked, blocked tasks when
+    // they are woken up, and running tasks when they reschedule. So only the
+    // current task has to reschedule now.
+    let rq = current_run_queue();
+    if crate::current().ptr_eq(task) && !cpumask.get(rq.cpu_id) {
+        rq.yield_current();
+    }
+    true
+}
+
+/// Wakes up a blocked task, and puts it into the run queue of the CPU it
+/// last ran on, or another CPU if it is no longer allowed to run there.
+///
+/// If `resched` is true or the task has a higher real-time priority than the
+/// current task, and it is woken up on the current CPU, the current task will
+/// be preempted when the preemption is enabled. If it is woken up on another
+/// CPU with a higher real-time priority than the task running there, that CPU
+/// is asked to reschedule, and does so once it enables preemption, e.g., at
+/// the end of its next interrupt.
+pub(crate) fn unblock_task(task: AxTaskRef, resched: bool) {
+    // A task may be woken up by several events at the same time (e.g. timer
+    // and `notify()`), only one of them can make it ready.
+    if !task.transition_state(TaskState::Blocked, TaskState::Ready) {
+        return;
+    }
+    debug!("task unblock: {}", task.id_name());
+    let _guard = NoPreemptIrqSave::new();
+    // The task may have blocked on another CPU which has not switched out of
+    // it yet. Wait until its context is saved before it can be picked by any
+    // CPU, the other CPU does not wait for anything to finish the switch.
+    #[cfg(feature = "smp")]
+    while task.on_cpu() {
+        core::hint::spin_loop();
+    }
+    task.accounting().on_ready(axhal::time::monotonic_time_nanos());
+    let cpumask = task.cpumask();
+    let rq = if cpumask.get(task.cpu_id()) {
+        run_queue(task.cpu_id())
+    } else {
+        select_run_queue(&cpumask)
+    };
+    let rt_prio = task.rt().effective_priority();
+    rq.enqueue(task);
+    if rq.cpu_id == axhal::cpu::this_cpu_id() {
+        if resched || rt_prio > crate::current().rt().effective_priority() {
+            #[cfg(feature = "preempt")]
+            crate::current().set_preempt_pending(true);
+        }
+    } else {
+        #[cfg(all(feature = "smp", feature = "preempt"))]
+        if rt_prio > rq.curr_rt_prio.load(Ordering::Acquire) {
+            rq.need_resched.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// Returns whether another CPU has asked the current CPU to reschedule, and
+/// clears the request. See [`unblock_task`].
+#[cfg(all(feature = "smp", feature = "preempt"))]
+pub(crate) fn take_remote_resched() -> bool {
+    let Some(rq) = RUN_QUEUES[axhal::cpu::this_cpu_id()].get() else {
+        return false;
+    };
+    rq.need_resched.load(Ordering::Acquire) && rq.need_resched.swap(false, Ordering::AcqRel)
+}
+
+/// Updates the real-time scheduling parameters of a task with `update`, and
+/// moves it to the right place if it is in a run queue.
+///
+/// The current task will be preempted when the preemption is enabled, if it
+/// no longer has the highest priority on the current CPU. Other CPUs are
+/// asked to reschedule in the same way, see [`unblock_task`].
+pub(crate) fn update_rt_params(task: &AxTaskRef, update: impl FnOnce(&RtState)) {
+    let _guard = NoPreemptIrqSave::new();
+    loop {
+        let rq = run_queue(task.cpu_id());
+        let mut scheduler = rq.scheduler.lock();
+        // The task may be moved to another run queue before it is locked.
+        if task.cpu_id() != rq.cpu_id {
+            continue;
+        }
+        let queued = scheduler.remove_task(task);
+        update(task.rt());
+        if let Some(task) = queued {
+            scheduler.add_task(task);
+        }
+        let highest = scheduler.highest_rt_priority();
+        if rq.cpu_id == axhal::cpu::this_cpu_id() {
+            if highest > crate::current().rt().effective_priority() {
+                #[cfg(feature = "preempt")]
+                crate::current().set_preempt_pending(true);
+            }
+        } else {
+            // See `unblock_task`.
+            #[cfg(all(feature = "smp", feature = "preempt"))]
+            if highest > rq.curr_rt_prio.load(Ordering::Acquire) {
+                rq.need_resched.store(true, Ordering::Release);
+            }
+        }
+        return;
+    }
+}
+
+/// Replaces the schedulers of normal tasks of all run queues with the ones
+/// created by `ctor`, see [`crate::select_scheduler`].
+pub(crate) fn replace_schedulers(ctor: SchedulerCtor) {
+    for rq in RUN_QUEUES.iter().filter_map(|rq| rq.get()) {
+        let normal = ctor();
+        let _guard = NoPreemptIrqSave::new();
+        rq.scheduler.lock().replace_normal(normal);
+    }
 }
 
 impl AxRunQueue {
-    pub fn new() -> SpinNoIrq<Self> {
-        let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
-        let mut scheduler = Scheduler::new();
-        scheduler.add_task(gc_task);
-        SpinNoIrq::new(Self { scheduler })
+    fn new(cpu_id: usize) -> Self {
+        let mut gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE);
+        gc_task.pin_to_cpu(cpu_id);
+        let mut scheduler = ClassScheduler::new(crate::sched::new_scheduler());
+        scheduler.add_task(gc_task.into_arc());
+        Self {
+            cpu_id,
+            scheduler: SpinRaw::new(scheduler),
+            nr_ready: AtomicUsize::new(1),
+            exited_tasks: SpinNoIrq::new(VecDeque::new()),
+            wait_for_exit: WaitQueue::new(),
+            #[cfg(all(feature = "smp", feature = "irq"))]
+            ticks: AtomicUsize::new(0),
+            #[cfg(all(feature = "smp", feature = "preempt"))]
+            curr_rt_prio: AtomicU8::new(0),
+            #[cfg(all(feature = "smp", feature = "preempt"))]
+            need_resched: AtomicBool::new(false),
+        }
+    }
+
+    #[cfg(any(feature = "smp", feature = "tickless"))]
+    fn nr_ready(&self) -> usize {
+        self.nr_ready.load(Ordering::Relaxed)
     }
 
-    pub fn add_task(&mut self, task: AxTaskRef) {
-        debug!("task spawn: {}", task.id_name());
+    /// Puts a ready task into the scheduler. IRQs must be disabled.
+    fn enqueue(&self, task: AxTaskRef) {
+        task.set_cpu_id(self.cpu_id);
+        let mut scheduler = self.scheduler.lock();
+        scheduler.add_task(task);
+        self.nr_ready.fetch_add(1, Ordering::Relaxed);
+        drop(scheduler);
+        // The idle task may be about to wait for IRQs, make sure it wakes up
+        // on time. Another CPU is woken up with an IPI if its tick is stopped,
+        // and restarts the tick once it switches to the task.
+        #[cfg(feature = "tickless")]
+        if self.cpu_id == axhal::cpu::this_cpu_id() {
+            crate::timers::restart_tick();
+        } else {
+            // Pairs with `stop_tick_if_idle`.
+            core::sync::atomic::fence(Ordering::SeqCst);
+            if crate::timers::tick_stopped(self.cpu_id) {
+                axhal::irq::send_ipi(self.cpu_id);
+            }
+        }
+    }
+
+    fn add_task(&self, task: AxTaskRef) {
+        debug!("task spawn: {} on CPU {}", task.id_name(), self.cpu_id);
         assert!(task.is_ready());
-        self.scheduler.add_task(task);
+        self.enqueue(task);
+    }
+
+    /// Stops the periodic tick of this CPU before the idle task waits for
+    /// IRQs, if there is no ready task.
+    ///
+    /// Returns whether the tick is stopped, after which IRQs must be kept
+    /// disabled until the CPU waits, so that the IPI sent once a task is put
+    /// in is not handled before that.
+    #[cfg(feature = "tickless")]
+    pub fn stop_tick_if_idle(&self) -> bool {
+        if self.nr_ready() != 0 {
+            return false;
+        }
+        crate::timers::stop_tick();
+        // Another CPU may have put a task in before it sees the tick stopped,
+        // and then it does not send an IPI, see `enqueue`.
+        core::sync::atomic::fence(Ordering::SeqCst);
+        if self.nr_ready() != 0 {
+            crate::timers::restart_tick();
+            return false;
+        }
+        true
     }
 
     #[cfg(feature = "irq")]
-    pub fn scheduler_timer_tick(&mut self) {
+    pub fn scheduler_timer_tick(&self) {
         let curr = crate::current();
-        if !curr.is_idle() && self.scheduler.task_tick(curr.as_task_ref()) {
+        if !curr.is_idle() && self.scheduler.lock().task_tick(curr.as_task_ref()) {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
+        #[cfg(feature = "smp")]
+        if self.ticks.fetch_add(1, Ordering::Relaxed) % LOAD_BALANCE_INTERVAL == 0 {
+            self.load_balance();
+        }
     }
 
-    pub fn yield_current(&mut self) {
+    pub fn yield_current(&self) {
         let curr = crate::current();
         trace!("task yield: {}", curr.id_name());
         assert!(curr.is_running());
         self.resched(false);
     }
 
-    pub fn set_current_priority(&mut self, prio: isize) -> bool {
-        self.scheduler
-            .set_priority(crate::current().as_task_ref(), prio)
+    pub fn set_current_priority(&self, prio: isize) -> bool {
+        let curr = crate::current();
+        let ok = self.scheduler.lock().set_priority(curr.as_task_ref(), prio);
+        if ok {
+            curr.set_priority(prio);
+        }
+        ok
     }
 
     #[cfg(feature = "preempt")]
-    pub fn preempt_resched(&mut self) {
+    pub fn preempt_resched(&self) {
         let curr = crate::current();
         assert!(curr.is_running());
 
-        // When we get the mutable reference of the run queue, we must
-        // have held the `SpinNoIrq` lock with both IRQs and preemption
-        // disabled. So we need to set `current_disable_count` to 1 in
-        // `can_preempt()` to obtain the preemption permission before
-        //  locking the run queue.
+        // When we get the reference of the current run queue, we must have
+        // both IRQs and preemption disabled. So we need to set
+        // `current_disable_count` to 1 in `can_preempt()` to obtain the
+        // preemption permission.
         let can_preempt = curr.can_preempt(1);
 
         debug!(
@@ -81,25 +382,24 @@ impl AxRunQueue {
         }
     }
 
-    pub fn exit_current(&mut self, exit_code: i32) -> ! {
+    pub fn exit_current(&self, exit_code: i32) -> ! {
         let curr = crate::current();
         debug!("task exit: {}, exit_code={}", curr.id_name(), exit_code);
         assert!(curr.is_running());
         assert!(!curr.is_idle());
         if curr.is_init() {
-            EXITED_TASKS.lock().clear();
+            self.exited_tasks.lock().clear();
             axhal::misc::terminate();
         } else {
-            curr.set_state(TaskState::Exited);
-            curr.notify_exit(exit_code, self);
-            EXITED_TASKS.lock().push_back(curr.clone());
-            WAIT_FOR_EXIT.notify_one_locked(false, self);
+            curr.notify_exit(exit_code);
+            self.exited_tasks.lock().push_back(curr.clone());
+            self.wait_for_exit.notify_one(false);
             self.resched(false);
         }
         unreachable!("task exited!");
     }
 
-    pub fn block_current<F>(&mut self, wait_queue_push: F)
+    pub fn block_current<F>(&self, wait_queue_push: F)
     where
         F: FnOnce(AxTaskRef),
     {
@@ -114,23 +414,16 @@ impl AxRunQueue {
 
         curr.set_state(TaskState::Blocked);
         wait_queue_push(curr.clone());
-        self.resched(false);
-    }
-
-    pub fn unblock_task(&mut self, task: AxTaskRef, resched: bool) {
-        debug!("task unblock: {}", task.id_name());
-        if task.is_blocked() {
-            task.set_state(TaskState::Ready);
-            self.scheduler.add_task(task); // TODO: priority
-            if resched {
-                #[cfg(feature = "preempt")]
-                crate::current().set_preempt_pending(true);
-            }
+        // `wait_queue_push` may abort the blocking, see
+        // `abort_block_if_interrupted`.
+        if curr.is_running() {
+            return;
         }
+        self.resched(false);
     }
 
     #[cfg(feature = "irq")]
-    pub fn sleep_until(&mut self, deadline: axhal::time::TimeValue) {
+    pub fn sleep_until(&self, deadline: axhal::time::TimeValue) {
         let curr = crate::current();
         debug!("task sleep: {}, deadline={:?}", curr.id_name(), deadline);
         assert!(curr.is_running());
@@ -138,8 +431,10 @@ impl AxRunQueue {
 
         let now = axhal::time::wall_time();
         if now < deadline {
-            crate::timers::set_alarm_wakeup(deadline, curr.clone());
+            // The alarm may go off on another CPU at once, so block the task
+            // before setting it.
             curr.set_state(TaskState::Blocked);
+            crate::timers::set_alarm_wakeup(deadline, curr.clone());
             self.resched(false);
         }
     }
@@ -148,22 +443,65 @@ impl AxRunQueue {
 impl AxRunQueue {
     /// Common reschedule subroutine. If `preempt`, keep current task's time
     /// slice, otherwise reset it.
-    fn resched(&mut self, preempt: bool) {
+    fn resched(&self, preempt: bool) {
         let prev = crate::current();
         if prev.is_running() {
             prev.set_state(TaskState::Ready);
             if !prev.is_idle() {
-                self.scheduler.put_prev_task(prev.clone(), preempt);
+                let cpumask = prev.cpumask();
+                if cpumask.get(self.cpu_id) {
+                    self.scheduler.lock().put_prev_task(prev.clone(), preempt);
+                    self.nr_ready.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    // The affinity has been changed while it was running.
+                    #[cfg(feature = "smp")]
+                    defer_migration(prev.clone());
+                    #[cfg(not(feature = "smp"))]
+                    select_run_queue(&cpumask).enqueue(prev.clone());
+                }
             }
         }
-        let next = self.scheduler.pick_next_task().unwrap_or_else(|| unsafe {
+        let next = self.pick_next_task();
+
+        // Nothing to run on this CPU, try to take some work from others.
+        #[cfg(feature = "smp")]
+        let next = next.or_else(|| self.steal_task());
+
+        let next = next.unwrap_or_else(|| unsafe {
             // Safety: IRQs must be disabled at this time.
             IDLE_TASK.current_ref_raw().get_unchecked().clone()
         });
         self.switch_to(prev, next);
     }
 
-    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef) {
+    /// Takes the next task that is allowed to run on this CPU from the
+    /// scheduler.
+    fn pick_next_task(&self) -> Option<AxTaskRef> {
+        loop {
+            let task = self.scheduler.lock().pick_next_task()?;
+            self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+            let cpumask = task.cpumask();
+            if !cpumask.get(self.cpu_id) {
+                // The affinity has been changed while it was waiting, move it
+                // to an allowed CPU. Our scheduler is unlocked here, so that
+                // at most one run queue is locked at a time.
+                let target = select_run_queue(&cpumask);
+                if !core::ptr::eq(target, self) {
+                    // The task switched out just now is still running here.
+                    #[cfg(feature = "smp")]
+                    if task.on_cpu() {
+                        defer_migration(task);
+                        continue;
+                    }
+                    target.enqueue(task);
+                    continue;
+                }
+            }
+            return Some(task);
+        }
+    }
+
+    fn switch_to(&self, prev_task: CurrentTask, next_task: AxTaskRef) {
         trace!(
             "context switch: {} -> {}",
             prev_task.id_name(),
@@ -171,15 +509,46 @@ impl AxRunQueue {
         );
         #[cfg(feature = "preempt")]
         next_task.set_preempt_pending(false);
-        next_task.set_state(TaskState::Running);
+        #[cfg(all(feature = "smp", feature = "preempt"))]
+        {
+            let prio = next_task.rt().effective_priority();
+            self.curr_rt_prio.store(prio, Ordering::Release);
+            self.need_resched.store(false, Ordering::Release);
+        }
         if prev_task.ptr_eq(&next_task) {
+            next_task.set_state(TaskState::Running);
             return;
         }
+        #[cfg(feature = "tickless")]
+        if prev_task.is_idle() {
+            crate::timers::restart_tick();
+        }
+
+        // Tasks are only put into the run queue of another CPU, or stolen by
+        // it, once their contexts are saved. See `unblock_task`,
+        // `defer_migration` and `steal_from`.
+        #[cfg(feature = "smp")]
+        {
+            debug_assert!(!next_task.on_cpu());
+            next_task.set_on_cpu(true);
+        }
+        // A task that is still ready is preempted or yields, otherwise it
+        // blocks or exits on its own.
+        let now_ns = axhal::time::monotonic_time_nanos();
+        let voluntary = !prev_task.is_ready();
+        prev_task.accounting().on_switch_out(now_ns, voluntary);
+        next_task.accounting().on_switch_in(now_ns);
+        next_task.set_state(TaskState::Running);
 
         unsafe {
             let prev_ctx_ptr = prev_task.ctx_mut_ptr();
             let next_ctx_ptr = next_task.ctx_mut_ptr();
 
+            #[cfg(feature = "smp")]
+            {
+                *PREV_TASK.current_ref_mut_raw() = Arc::downgrade(prev_task.as_task_ref());
+            }
+
             // The strong reference count of `prev_task` will be decremented by 1,
             // but won't be dropped until `gc_entry()` is called.
             assert!(Arc::strong_count(prev_task.as_task_ref()) > 1);
@@ -187,17 +556,99 @@ impl AxRunQueue {
 
             CurrentTask::set_current(prev_task, next_task);
             (*prev_ctx_ptr).switch_to(&*next_ctx_ptr);
+
+            // Now we may be running on another CPU.
+            #[cfg(feature = "smp")]
+            clear_prev_task_on_cpu();
+        }
+    }
+}
+
+#[cfg(feature = "smp")]
+impl AxRunQueue {
+    /// Takes a ready task from another CPU, if this CPU has nothing to run.
+    fn steal_task(&self) -> Option<AxTaskRef> {
+        other_run_queues(self.cpu_id)
+            .filter(|rq| rq.nr_ready() > 0)
+            .find_map(|rq| self.steal_from(rq))
+    }
+
+    /// Pulls a task from the busiest CPU into this run queue, if that CPU has
+    /// at least two more ready tasks than this one.
+    #[cfg(feature = "irq")]
+    fn load_balance(&self) {
+        let busiest = other_run_queues(self.cpu_id).max_by_key(|rq| rq.nr_ready());
+        if let Some(busiest) = busiest {
+            if busiest.nr_ready() > self.nr_ready() + 1 {
+                if let Some(task) = self.steal_from(busiest) {
+                    self.enqueue(task);
+                }
+            }
         }
     }
+
+    /// Takes the first task of `victim` that can run on this CPU. IRQs must
+    /// be disabled.
+    ///
+    /// Tasks that are not allowed to run here stay in place, and so does the
+    /// task that `victim` has just switched out of, until its context is
+    /// saved.
+    fn steal_from(&self, victim: &AxRunQueue) -> Option<AxTaskRef> {
+        // Do not spin on a busy run queue, just try another one.
+        let mut scheduler = victim.scheduler.try_lock()?;
+        let task = scheduler.take_task_if(|t| t.cpumask().get(self.cpu_id) && !t.on_cpu())?;
+        victim.nr_ready.fetch_sub(1, Ordering::Relaxed);
+        drop(scheduler);
+
+        debug!(
+            "task migrate: {} from CPU {} to CPU {}",
+            task.id_name(),
+            victim.cpu_id,
+            self.cpu_id
+        );
+        task.set_cpu_id(self.cpu_id);
+        Some(task)
+    }
+}
+
+/// Moves the task being switched out of this CPU to a CPU it is allowed to
+/// run on, once the switch has completed. Preemption must be disabled.
+#[cfg(feature = "smp")]
+fn defer_migration(task: AxTaskRef) {
+    // Safety: preemption is disabled.
+    let migrating = unsafe { MIGRATING_TASK.current_ref_mut_raw() };
+    debug_assert!(migrating.is_none());
+    *migrating = Some(task);
+}
+
+/// Clears the `on_cpu` flag of the task switched out last on this CPU, so
+/// that other CPUs can switch to it, and moves it to another CPU if it is no
+/// longer allowed to run here.
+///
+/// # Safety
+///
+/// It must be called by the next task right after the context switch.
+#[cfg(feature = "smp")]
+pub(crate) unsafe fn clear_prev_task_on_cpu() {
+    PREV_TASK
+        .current_ref_raw()
+        .upgrade()
+        .expect("the previous task has been dropped")
+        .set_on_cpu(false);
+    if let Some(task) = MIGRATING_TASK.current_ref_mut_raw().take() {
+        select_run_queue(&task.cpumask()).enqueue(task);
+    }
 }
 
 fn gc_entry() {
+    // The gc task is pinned, so it always works on the same run queue.
+    let rq = run_queue(axhal::cpu::this_cpu_id());
     loop {
         // Drop all exited tasks and recycle resources.
-        let n = EXITED_TASKS.lock().len();
+        let n = rq.exited_tasks.lock().len();
         for _ in 0..n {
             // Do not do the slow drops in the critical section.
-            let task = EXITED_TASKS.lock().pop_front();
+            let task = rq.exited_tasks.lock().pop_front();
             if let Some(task) = task {
                 if Arc::strong_count(&task) == 1 {
                     // If I'm the last holder of the task, drop it immediately.
@@ -205,36 +656,47 @@ fn gc_entry() {
                 } else {
                     // Otherwise (e.g, `switch_to` is not compeleted, held by the
                     // joiner, etc), push it back and wait for them to drop first.
-                    EXITED_TASKS.lock().push_back(task);
+                    rq.exited_tasks.lock().push_back(task);
                 }
             }
         }
-        WAIT_FOR_EXIT.wait();
+        rq.wait_for_exit.wait();
     }
 }
 
 pub(crate) fn init() {
+    let cpu_id = axhal::cpu::this_cpu_id();
+
     // Create the `idle` task (not current task).
     const IDLE_TASK_STACK_SIZE: usize = 4096;
-    let idle_task = TaskInner::new(|| crate::run_idle(), "idle".into(), IDLE_TASK_STACK_SIZE);
+    let mut idle_task = TaskInner::new(|| crate::run_idle(), "idle".into(), IDLE_TASK_STACK_SIZE);
+    idle_task.pin_to_cpu(cpu_id);
     IDLE_TASK.with_current(|i| {
         i.init_once(idle_task.into_arc());
     });
 
     // Put the subsequent execution into the `main` task.
-    let main_task = TaskInner::new_init("main".into()).into_arc();
+    let main_task = TaskInner::new_init("main".into());
+    main_task.set_cpu_id(cpu_id);
+    let main_task = main_task.into_arc();
     main_task.set_state(TaskState::Running);
     unsafe { CurrentTask::init_current(main_task) };
 
-    RUN_QUEUE.init_once(AxRunQueue::new());
+    RUN_QUEUES[cpu_id].init_once(AxRunQueue::new(cpu_id));
 }
 
 pub(crate) fn init_secondary() {
+    let cpu_id = axhal::cpu::this_cpu_id();
+
     // Put the subsequent execution into the `idle` task.
-    let idle_task = TaskInner::new_init("idle".into()).into_arc();
+    let mut idle_task = TaskInner::new_init("idle".into());
+    idle_task.pin_to_cpu(cpu_id);
+    let idle_task = idle_task.into_arc();
     idle_task.set_state(TaskState::Running);
     IDLE_TASK.with_current(|i| {
         i.init_once(idle_task.clone());
     });
-    unsafe { CurrentTask::init_current(idle_task) }
+    unsafe { CurrentTask::init_current(idle_task) };
+
+    RUN_QUEUES[cpu_id].init_once(AxRunQueue::new(cpu_id));
 }
@@ -0,0 +1,118 @@
+//! The Completely Fair Scheduler.
+
+use alloc::collections::BTreeMap;
+use core::sync::atomic::Ordering;
+
+use super::Scheduler;
+use crate::AxTaskRef;
+
+/// The weight of nice value 0.
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// The weights of nice values from -20 to 19, each step is about 1.25x.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110,
+    87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// A preemptive scheduler, which runs the task with the least virtual runtime,
+/// i.e., the CPU time weighted by the nice value (the priority set by
+/// [`set_priority`](crate::set_priority), from -20 to 19).
+pub struct CFScheduler {
+    /// The ready tasks ordered by their virtual runtimes, and then the order
+    /// they are queued in.
+    ready_queue: BTreeMap<(u64, u64), AxTaskRef>,
+    /// The virtual runtime that new and woken up tasks start from, so that
+    /// they neither starve the others nor wait for long.
+    ///
+    /// The virtual runtimes of the tasks that are not in the queue (e.g., the
+    /// running and blocked ones) are relative to it, so that they keep their
+    /// places when they are moved to other run queues.
+    min_vruntime: u64,
+    next_seq: u64,
+}
+
+impl CFScheduler {
+    pub const fn new() -> Self {
+        Self {
+            ready_queue: BTreeMap::new(),
+            min_vruntime: 0,
+            next_seq: 0,
+        }
+    }
+
+    fn enqueue(&mut self, task: AxTaskRef, vruntime: u64) {
+        let state = task.sched_state();
+        state.vruntime.store(vruntime, Ordering::Release);
+        state.seq.store(self.next_seq, Ordering::Release);
+        self.ready_queue.insert((vruntime, self.next_seq), task);
+        self.next_seq += 1;
+    }
+
+    /// Makes the virtual runtime of a task taken out of the queue relative to
+    /// `min_vruntime`.
+    fn dequeue(&self, task: &AxTaskRef) {
+        let vruntime = &task.sched_state().vruntime;
+        let relative = vruntime
+            .load(Ordering::Acquire)
+            .saturating_sub(self.min_vruntime);
+        vruntime.store(relative, Ordering::Release);
+    }
+}
+
+fn key_of(task: &AxTaskRef) -> (u64, u64) {
+    let state = task.sched_state();
+    (
+        state.vruntime.load(Ordering::Acquire),
+        state.seq.load(Ordering::Acquire),
+    )
+}
+
+impl Scheduler for CFScheduler {
+    fn add_task(&mut self, task: AxTaskRef) {
+        // The task may come from another run queue, see `min_vruntime`.
+        let vruntime = self.min_vruntime + key_of(&task).0;
+        self.enqueue(task, vruntime);
+    }
+
+    fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+        let task = self.ready_queue.remove(&key_of(task))?;
+        self.dequeue(&task);
+        Some(task)
+    }
+
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        let ((vruntime, _), task) = self.ready_queue.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        self.dequeue(&task);
+        Some(task)
+    }
+
+    fn put_prev_task(&mut self, prev: AxTaskRef, _preempt: bool) {
+        self.add_task(prev);
+    }
+
+    fn task_tick(&mut self, current: &AxTaskRef) -> bool {
+        let nice = current.priority().clamp(-20, 19);
+        let weight = NICE_TO_WEIGHT[(nice + 20) as usize];
+        let delta = NICE_0_WEIGHT * NICE_0_WEIGHT / weight;
+        let vruntime = current
+            .sched_state()
+            .vruntime
+            .fetch_add(delta, Ordering::AcqRel)
+            + delta
+            + self.min_vruntime;
+        self.ready_queue
+            .first_key_value()
+            .is_some_and(|(&(min, _), _)| min < vruntime)
+    }
+
+    fn set_priority(&mut self, _task: &AxTaskRef, prio: isize) -> bool {
+        (-20..=19).contains(&prio)
+    }
+
+    fn find_task(&self, pred: &mut dyn FnMut(&AxTaskRef) -> bool) -> Option<AxTaskRef> {
+        self.ready_queue.values().find(|t| pred(t)).cloned()
+    }
+}
@@ -0,0 +1,51 @@
+//! The FIFO cooperative scheduler.
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use super::Scheduler;
+use crate::AxTaskRef;
+
+/// A cooperative scheduler, which runs the tasks in the order they become
+/// ready. The running task is switched out only when it yields or blocks.
+pub struct FifoScheduler {
+    ready_queue: VecDeque<AxTaskRef>,
+}
+
+impl FifoScheduler {
+    pub const fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for FifoScheduler {
+    fn add_task(&mut self, task: AxTaskRef) {
+        self.ready_queue.push_back(task);
+    }
+
+    fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+        let idx = self.ready_queue.iter().position(|t| Arc::ptr_eq(t, task))?;
+        self.ready_queue.remove(idx)
+    }
+
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        self.ready_queue.pop_front()
+    }
+
+    fn put_prev_task(&mut self, prev: AxTaskRef, _preempt: bool) {
+        self.ready_queue.push_back(prev);
+    }
+
+    fn task_tick(&mut self, _current: &AxTaskRef) -> bool {
+        false // no reschedule
+    }
+
+    fn set_priority(&mut self, _task: &AxTaskRef, _prio: isize) -> bool {
+        false
+    }
+
+    fn find_task(&self, pred: &mut dyn FnMut(&AxTaskRef) -> bool) -> Option<AxTaskRef> {
+        self.ready_queue.iter().find(|t| pred(t)).cloned()
+    }
+}
@@ -0,0 +1,172 @@
+//! Schedulers of normal tasks, which can be selected at runtime.
+//!
+//! The built-in schedulers are `fifo`, `rr` and `cfs`, and more can be added
+//! with [`register_scheduler`]. The one used at boot is set by the `scheduler`
+//! config item, or by the cargo features if it is empty. It can be changed
+//! afterwards with [`select_scheduler`].
+
+mod cfs;
+mod fifo;
+mod rr;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicIsize, AtomicU64};
+
+use kspin::SpinNoIrq;
+
+use crate::AxTaskRef;
+
+/// A scheduler of the normal tasks in a run queue.
+///
+/// The running task is not in the scheduler. All methods are called with the
+/// run queue locked and IRQs disabled, so they must not block.
+pub trait Scheduler: Send {
+    /// Adds a task that has become ready.
+    fn add_task(&mut self, task: AxTaskRef);
+
+    /// Removes a task, returns `None` if it is not in the scheduler.
+    fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef>;
+
+    /// Takes the next task to run.
+    fn pick_next_task(&mut self) -> Option<AxTaskRef>;
+
+    /// Puts back the task that was running, which is preempted if `preempt`
+    /// is true, or yields otherwise.
+    fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool);
+
+    /// Advances the states of the current task at a timer tick, returns
+    /// whether it should be preempted.
+    fn task_tick(&mut self, current: &AxTaskRef) -> bool;
+
+    /// Checks the priority of a task before it is set, returns `false` if it
+    /// is not supported.
+    fn set_priority(&mut self, task: &AxTaskRef, prio: isize) -> bool;
+
+    /// Returns the first task in the order they are picked that satisfies
+    /// `pred`, without taking it out.
+    ///
+    /// It is used to find tasks that can be moved to other CPUs. The tasks of
+    /// schedulers that do not implement it are not stolen by other CPUs.
+    fn find_task(&self, _pred: &mut dyn FnMut(&AxTaskRef) -> bool) -> Option<AxTaskRef> {
+        None
+    }
+}
+
+/// Creates a scheduler for a run queue.
+pub type SchedulerCtor = fn() -> Box<dyn Scheduler>;
+
+/// The built-in schedulers.
+const BUILTIN_SCHEDULERS: [(&str, SchedulerCtor); 3] = [
+    ("fifo", || Box::new(fifo::FifoScheduler::new())),
+    ("rr", || Box::new(rr::RRScheduler::new())),
+    ("cfs", || Box::new(cfs::CFScheduler::new())),
+];
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "sched_rr")] {
+        const DEFAULT_SCHEDULER: &str = "rr";
+    } else if #[cfg(feature = "sched_cfs")] {
+        const DEFAULT_SCHEDULER: &str = "cfs";
+    } else {
+        const DEFAULT_SCHEDULER: &str = "fifo";
+    }
+}
+
+/// The schedulers registered by [`register_scheduler`].
+static SCHEDULERS: SpinNoIrq<Vec<(&str, SchedulerCtor)>> = SpinNoIrq::new(Vec::new());
+
+/// The scheduler in use.
+static SELECTED: SpinNoIrq<(&str, SchedulerCtor)> = SpinNoIrq::new(BUILTIN_SCHEDULERS[0]);
+
+/// The states of a task used by the built-in schedulers.
+pub(crate) struct SchedState {
+    /// The remaining timer ticks of the time slice, for `rr`.
+    time_slice: AtomicIsize,
+    /// The virtual runtime, for `cfs`. It is relative to the `min_vruntime`
+    /// of the run queue if the task is not in the queue.
+    vruntime: AtomicU64,
+    /// The order that the task is queued in, to tell tasks of the same
+    /// virtual runtime apart, for `cfs`.
+    seq: AtomicU64,
+}
+
+impl SchedState {
+    pub const fn new() -> Self {
+        Self {
+            time_slice: AtomicIsize::new(rr::MAX_TIME_SLICE),
+            vruntime: AtomicU64::new(0),
+            seq: AtomicU64::new(0),
+        }
+    }
+}
+
+fn find_scheduler(name: &str) -> Option<(&'static str, SchedulerCtor)> {
+    let registered = SCHEDULERS.lock();
+    BUILTIN_SCHEDULERS
+        .iter()
+        .chain(registered.iter())
+        .find(|(n, _)| *n == name)
+        .copied()
+}
+
+/// Registers a scheduler, so that it can be selected by its name.
+///
+/// Returns `false` if the name has been taken.
+pub fn register_scheduler(name: &'static str, ctor: SchedulerCtor) -> bool {
+    if find_scheduler(name).is_some() {
+        return false;
+    }
+    SCHEDULERS.lock().push((name, ctor));
+    true
+}
+
+/// Returns the names of all schedulers, built-in and registered.
+pub fn scheduler_names() -> Vec<&'static str> {
+    BUILTIN_SCHEDULERS
+        .iter()
+        .chain(SCHEDULERS.lock().iter())
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Returns the name of the scheduler in use.
+pub fn scheduler_name() -> &'static str {
+    SELECTED.lock().0
+}
+
+/// Selects the scheduler of normal tasks by its name.
+///
+/// If the task scheduler has been initialized, the schedulers of all run
+/// queues are replaced at once, and their ready tasks are moved to the new
+/// ones. Real-time tasks are not affected.
+///
+/// Returns `false` if there is no such scheduler.
+pub fn select_scheduler(name: &str) -> bool {
+    let Some(sched) = find_scheduler(name) else {
+        return false;
+    };
+    // Keep it locked, so that run queues initialized meanwhile use the same
+    // scheduler.
+    let mut selected = SELECTED.lock();
+    *selected = sched;
+    crate::run_queue::replace_schedulers(sched.1);
+    info!("use {} scheduler", sched.0);
+    true
+}
+
+/// Creates a scheduler of the selected kind for a new run queue.
+pub(crate) fn new_scheduler() -> Box<dyn Scheduler> {
+    (SELECTED.lock().1)()
+}
+
+/// Selects the scheduler at boot, see the module documentation.
+pub(crate) fn init() {
+    let name = match axconfig::SCHEDULER {
+        "" => DEFAULT_SCHEDULER,
+        name => name,
+    };
+    if !select_scheduler(name) {
+        warn!("unknown scheduler {:?}, use {}", name, DEFAULT_SCHEDULER);
+        select_scheduler(DEFAULT_SCHEDULER);
+    }
+}
@@ -0,0 +1,66 @@
+//! The round-robin preemptive scheduler.
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::sync::atomic::Ordering;
+
+use super::Scheduler;
+use crate::AxTaskRef;
+
+/// Timer ticks in a time slice.
+pub(super) const MAX_TIME_SLICE: isize = 5;
+
+/// A preemptive scheduler, which runs the tasks in turn, each for a time
+/// slice at most.
+pub struct RRScheduler {
+    ready_queue: VecDeque<AxTaskRef>,
+}
+
+impl RRScheduler {
+    pub const fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for RRScheduler {
+    fn add_task(&mut self, task: AxTaskRef) {
+        self.ready_queue.push_back(task);
+    }
+
+    fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+        let idx = self.ready_queue.iter().position(|t| Arc::ptr_eq(t, task))?;
+        self.ready_queue.remove(idx)
+    }
+
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        self.ready_queue.pop_front()
+    }
+
+    fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool) {
+        let time_slice = &prev.sched_state().time_slice;
+        if preempt && time_slice.load(Ordering::Acquire) > 0 {
+            // Preempted before its time slice runs out, run it again first.
+            self.ready_queue.push_front(prev);
+        } else {
+            time_slice.store(MAX_TIME_SLICE, Ordering::Release);
+            self.ready_queue.push_back(prev);
+        }
+    }
+
+    fn task_tick(&mut self, current: &AxTaskRef) -> bool {
+        let old_slice = current
+            .sched_state()
+            .time_slice
+            .fetch_sub(1, Ordering::Release);
+        old_slice <= 1
+    }
+
+    fn set_priority(&mut self, _task: &AxTaskRef, _prio: isize) -> bool {
+        false
+    }
+
+    fn find_task(&self, pred: &mut dyn FnMut(&AxTaskRef) -> bool) -> Option<AxTaskRef> {
+        self.ready_queue.iter().find(|t| pred(t)).cloned()
+    }
+}
@@ -0,0 +1,213 @@
+//! Per-task CPU time accounting and scheduler statistics.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use kernel_guard::NoPreempt;
+
+/// The scheduler statistics of a task, returned by
+/// [`TaskInner::sched_stats`].
+///
+/// [`TaskInner::sched_stats`]: crate::TaskInner::sched_stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchedStats {
+    /// The CPU time spent in the task itself.
+    pub utime: Duration,
+    /// The CPU time spent in the kernel on behalf of the task, i.e., while a
+    /// [`KernelModeGuard`] is alive.
+    pub stime: Duration,
+    /// The time spent in run queues, waiting for a CPU.
+    pub wait_time: Duration,
+    /// The number of context switches because the task blocked or exited.
+    pub voluntary_switches: u64,
+    /// The number of context switches because the task was preempted or
+    /// yielded the CPU.
+    pub involuntary_switches: u64,
+}
+
+impl SchedStats {
+    /// The total CPU time, i.e., `utime + stime`.
+    pub fn cpu_time(&self) -> Duration {
+        self.utime + self.stime
+    }
+}
+
+/// The statistics of the tasks that have been dropped, so that they are
+/// still counted in [`total_sched_stats`].
+static DROPPED: TaskAccounting = TaskAccounting::new();
+
+/// The counters of [`SchedStats`] in a task.
+///
+/// All times are in nanoseconds of the monotonic clock. The counters are only
+/// updated by the CPU that runs the task, or the one that makes it ready.
+pub(crate) struct TaskAccounting {
+    utime_ns: AtomicU64,
+    stime_ns: AtomicU64,
+    wait_ns: AtomicU64,
+    nvcsw: AtomicU64,
+    nivcsw: AtomicU64,
+    /// When the CPU time was last charged.
+    last_ns: AtomicU64,
+    /// When the task was last put into a run queue.
+    ready_ns: AtomicU64,
+    in_kernel: AtomicBool,
+}
+
+impl TaskAccounting {
+    pub const fn new() -> Self {
+        Self {
+            utime_ns: AtomicU64::new(0),
+            stime_ns: AtomicU64::new(0),
+            wait_ns: AtomicU64::new(0),
+            nvcsw: AtomicU64::new(0),
+            nivcsw: AtomicU64::new(0),
+            last_ns: AtomicU64::new(0),
+            ready_ns: AtomicU64::new(0),
+            in_kernel: AtomicBool::new(false),
+        }
+    }
+
+    /// Charges the time since the last update to `utime` or `stime`.
+    fn charge(&self, now_ns: u64) {
+        let delta = now_ns.saturating_sub(self.last_ns.swap(now_ns, Ordering::Relaxed));
+        if self.in_kernel.load(Ordering::Relaxed) {
+            self.stime_ns.fetch_add(delta, Ordering::Relaxed);
+        } else {
+            self.utime_ns.fetch_add(delta, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the time when the task becomes ready, e.g., when it is
+    /// spawned or woken up.
+    pub fn on_ready(&self, now_ns: u64) {
+        self.ready_ns.store(now_ns, Ordering::Relaxed);
+    }
+
+    /// Updates the statistics when the task is switched in at `now_ns`.
+    pub fn on_switch_in(&self, now_ns: u64) {
+        let delta = now_ns.saturating_sub(self.ready_ns.load(Ordering::Relaxed));
+        self.wait_ns.fetch_add(delta, Ordering::Relaxed);
+        self.last_ns.store(now_ns, Ordering::Relaxed);
+    }
+
+    /// Updates the statistics when the task is switched out at `now_ns`.
+    ///
+    /// If the switch is involuntary, the task is still ready and waits in a
+    /// run queue from now on.
+    pub fn on_switch_out(&self, now_ns: u64, voluntary: bool) {
+        self.charge(now_ns);
+        if voluntary {
+            self.nvcsw.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.nivcsw.fetch_add(1, Ordering::Relaxed);
+            self.ready_ns.store(now_ns, Ordering::Relaxed);
+        }
+    }
+
+    /// Switches the mode of the current task, returns the previous one.
+    fn set_in_kernel(&self, in_kernel: bool) -> bool {
+        let _guard = NoPreempt::new();
+        if self.in_kernel.load(Ordering::Relaxed) != in_kernel {
+            self.charge(axhal::time::monotonic_time_nanos());
+            self.in_kernel.store(in_kernel, Ordering::Relaxed);
+            !in_kernel
+        } else {
+            in_kernel
+        }
+    }
+
+    /// Adds the statistics of a task.
+    pub fn add(&self, stats: &SchedStats) {
+        self.utime_ns
+            .fetch_add(stats.utime.as_nanos() as u64, Ordering::Relaxed);
+        self.stime_ns
+            .fetch_add(stats.stime.as_nanos() as u64, Ordering::Relaxed);
+        self.wait_ns
+            .fetch_add(stats.wait_time.as_nanos() as u64, Ordering::Relaxed);
+        self.nvcsw
+            .fetch_add(stats.voluntary_switches, Ordering::Relaxed);
+        self.nivcsw
+            .fetch_add(stats.involuntary_switches, Ordering::Relaxed);
+    }
+
+    /// Returns the statistics, including the current run if `running`.
+    pub fn snapshot(&self, running: bool) -> SchedStats {
+        let mut utime_ns = self.utime_ns.load(Ordering::Relaxed);
+        let mut stime_ns = self.stime_ns.load(Ordering::Relaxed);
+        if running {
+            let now_ns = axhal::time::monotonic_time_nanos();
+            let delta = now_ns.saturating_sub(self.last_ns.load(Ordering::Relaxed));
+            if self.in_kernel.load(Ordering::Relaxed) {
+                stime_ns += delta;
+            } else {
+                utime_ns += delta;
+            }
+        }
+        SchedStats {
+            utime: Duration::from_nanos(utime_ns),
+            stime: Duration::from_nanos(stime_ns),
+            wait_time: Duration::from_nanos(self.wait_ns.load(Ordering::Relaxed)),
+            voluntary_switches: self.nvcsw.load(Ordering::Relaxed),
+            involuntary_switches: self.nivcsw.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts the CPU time of a task that has been dropped in
+/// [`total_sched_stats`]. It is called with the task registry locked.
+pub(crate) fn add_dropped(stats: &SchedStats) {
+    DROPPED.add(stats);
+}
+
+/// Returns the sum of the statistics of all tasks except the idle tasks,
+/// including the ones that have exited.
+pub fn total_sched_stats() -> SchedStats {
+    // Read the dropped tasks and the live ones with the registry locked, so
+    // that the tasks dropped meanwhile are counted once, and the totals never
+    // go backwards.
+    crate::registry::with_all_tasks(|tasks| {
+        let mut total = DROPPED.snapshot(false);
+        for task in tasks.filter(|t| !t.is_idle()) {
+            let stats = task.sched_stats();
+            total.utime += stats.utime;
+            total.stime += stats.stime;
+            total.wait_time += stats.wait_time;
+            total.voluntary_switches += stats.voluntary_switches;
+            total.involuntary_switches += stats.involuntary_switches;
+        }
+        total
+    })
+}
+
+/// A guard that charges the CPU time of the current task to `stime` instead
+/// of `utime` while it is alive.
+///
+/// It is used by the system call layer to tell the time spent in the kernel
+/// from the time spent in the application. Guards can be nested.
+pub struct KernelModeGuard {
+    was_in_kernel: bool,
+}
+
+impl KernelModeGuard {
+    /// Charges the CPU time of the current task to `stime` from now on.
+    pub fn new() -> Self {
+        let was_in_kernel = crate::current_may_uninit()
+            .map(|curr| curr.accounting().set_in_kernel(true))
+            .unwrap_or(true);
+        Self { was_in_kernel }
+    }
+}
+
+impl Default for KernelModeGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for KernelModeGuard {
+    fn drop(&mut self) {
+        if !self.was_in_kernel {
+            crate::current().accounting().set_in_kernel(false);
+        }
+    }
+}
@@ -1,31 +1,44 @@
 use alloc::{boxed::Box, string::String, sync::Arc};
 use core::ops::Deref;
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicIsize, AtomicU64, AtomicU8, AtomicUsize};
+use core::sync::atomic::Ordering;
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
 
-#[cfg(feature = "preempt")]
-use core::sync::atomic::AtomicUsize;
-
 #[cfg(feature = "tls")]
 use axhal::tls::TlsArea;
 
 use axhal::arch::TaskContext;
+use kspin::SpinNoIrq;
 use memory_addr::{align_up_4k, VirtAddr};
 
-use crate::task_ext::AxTaskExt;
-use crate::{AxRunQueue, AxTask, AxTaskRef, WaitQueue};
+use crate::cpumask::CpuMask;
+use crate::notify::NotifyState;
+use crate::registry::TaskInfo;
+use crate::rt::RtState;
+use crate::sched::SchedState;
+use crate::stats::{SchedStats, TaskAccounting};
+use crate::task_ext::{AxTaskExt, TaskLocals};
+use crate::{AxTaskRef, WaitQueue};
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct TaskId(u64);
 
+/// The value of `timer_cpu_id` when the task is not in any timer list.
+#[cfg(feature = "irq")]
+const NO_TIMER: usize = usize::MAX;
+
 /// The possible states of a task.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub(crate) enum TaskState {
+pub enum TaskState {
+    /// The task is running on a CPU.
     Running = 1,
+    /// The task is in a run queue, waiting to be scheduled.
     Ready = 2,
+    /// The task is waiting for an event, e.g., in a wait queue or sleeping.
     Blocked = 3,
+    /// The task has exited, but it has not been dropped yet.
     Exited = 4,
 }
 
@@ -39,15 +52,32 @@ pub struct TaskInner {
     entry: Option<*mut dyn FnOnce()>,
     state: AtomicU8,
 
+    /// The CPU whose run queue the task belongs to, or the task last ran on.
+    cpu_id: AtomicUsize,
+    /// The CPUs that the task is allowed to run on.
+    cpumask: SpinNoIrq<CpuMask>,
+    /// Whether the task is running on a CPU, or its context is being saved.
+    #[cfg(feature = "smp")]
+    on_cpu: AtomicBool,
+
     in_wait_queue: AtomicBool,
+    /// The CPU whose timer list has the wakeup event of the task, or
+    /// `NO_TIMER` if there is none.
     #[cfg(feature = "irq")]
-    in_timer_list: AtomicBool,
+    timer_cpu_id: AtomicUsize,
 
     #[cfg(feature = "preempt")]
     need_resched: AtomicBool,
     #[cfg(feature = "preempt")]
     preempt_disable_count: AtomicUsize,
 
+    /// The priority set by `set_priority()`.
+    priority: AtomicIsize,
+    sched_state: SchedState,
+    rt: RtState,
+    accounting: TaskAccounting,
+    notify: NotifyState,
+
     exit_code: AtomicI32,
     wait_for_exit: WaitQueue,
 
@@ -159,6 +189,52 @@ impl TaskInner {
             None
         }
     }
+
+    /// Returns the CPUs that the task is allowed to run on.
+    pub fn cpumask(&self) -> CpuMask {
+        *self.cpumask.lock()
+    }
+
+    /// Sets the CPUs that the task is allowed to run on before it is spawned.
+    ///
+    /// Use [`set_affinity`](crate::set_affinity) to change it afterwards.
+    pub fn set_cpumask(&mut self, cpumask: CpuMask) {
+        *self.cpumask.get_mut() = cpumask;
+    }
+
+    /// Returns whether the task has been cancelled by [`cancel`].
+    ///
+    /// [`cancel`]: crate::cancel
+    pub fn is_cancelled(&self) -> bool {
+        self.notify.is_cancelled()
+    }
+
+    /// Returns the CPU time and scheduler statistics of the task.
+    pub fn sched_stats(&self) -> SchedStats {
+        self.accounting.snapshot(self.is_running())
+    }
+
+    /// Returns a snapshot of the status of the task.
+    pub fn info(&self) -> TaskInfo {
+        let state = self.state();
+        let stats = self.sched_stats();
+        let (stack_size, stack_high_water) = match &self.kstack {
+            Some(stack) => (stack.size(), stack.high_water()),
+            None => (0, 0),
+        };
+        TaskInfo {
+            id: self.id,
+            name: self.name.clone(),
+            state,
+            cpu_id: self.cpu_id(),
+            priority: self.priority(),
+            runtime: stats.cpu_time(),
+            stats,
+            exit_code: (state == TaskState::Exited).then(|| self.exit_code.load(Ordering::Acquire)),
+            stack_size,
+            stack_high_water,
+        }
+    }
 }
 
 // private methods
@@ -171,13 +247,22 @@ impl TaskInner {
             is_init: false,
             entry: None,
             state: AtomicU8::new(TaskState::Ready as u8),
+            cpu_id: AtomicUsize::new(0),
+            cpumask: SpinNoIrq::new(CpuMask::full()),
+            #[cfg(feature = "smp")]
+            on_cpu: AtomicBool::new(false),
             in_wait_queue: AtomicBool::new(false),
             #[cfg(feature = "irq")]
-            in_timer_list: AtomicBool::new(false),
+            timer_cpu_id: AtomicUsize::new(NO_TIMER),
             #[cfg(feature = "preempt")]
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             preempt_disable_count: AtomicUsize::new(0),
+            priority: AtomicIsize::new(0),
+            sched_state: SchedState::new(),
+            rt: RtState::new(),
+            accounting: TaskAccounting::new(),
+            notify: NotifyState::new(),
             exit_code: AtomicI32::new(0),
             wait_for_exit: WaitQueue::new(),
             kstack: None,
@@ -206,7 +291,9 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let task = Arc::new(self);
+        crate::registry::register(&task);
+        task
     }
 
     #[inline]
@@ -219,6 +306,15 @@ impl TaskInner {
         self.state.store(state as u8, Ordering::Release)
     }
 
+    /// Changes the state from `from` to `to` atomically, returns `false` if
+    /// the state is not `from`.
+    #[inline]
+    pub(crate) fn transition_state(&self, from: TaskState, to: TaskState) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
     #[inline]
     pub(crate) fn is_running(&self) -> bool {
         matches!(self.state(), TaskState::Running)
@@ -244,6 +340,75 @@ impl TaskInner {
         self.is_idle
     }
 
+    #[inline]
+    pub(crate) fn cpu_id(&self) -> usize {
+        self.cpu_id.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(crate) fn set_cpu_id(&self, cpu_id: usize) {
+        self.cpu_id.store(cpu_id, Ordering::Release)
+    }
+
+    #[inline]
+    pub(crate) fn priority(&self) -> isize {
+        self.priority.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn set_priority(&self, prio: isize) {
+        self.priority.store(prio, Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub(crate) fn sched_state(&self) -> &SchedState {
+        &self.sched_state
+    }
+
+    #[inline]
+    pub(crate) fn rt(&self) -> &RtState {
+        &self.rt
+    }
+
+    #[inline]
+    pub(crate) fn accounting(&self) -> &TaskAccounting {
+        &self.accounting
+    }
+
+    #[inline]
+    pub(crate) fn notify_state(&self) -> &NotifyState {
+        &self.notify
+    }
+
+    #[inline]
+    pub(crate) fn locals(&self) -> &TaskLocals {
+        self.task_ext.locals()
+    }
+
+    #[inline]
+    pub(crate) fn update_cpumask(&self, cpumask: CpuMask) {
+        *self.cpumask.lock() = cpumask;
+    }
+
+    /// Makes the task only run on the given CPU, it must not be in any run
+    /// queue yet.
+    pub(crate) fn pin_to_cpu(&mut self, cpu_id: usize) {
+        self.set_cpu_id(cpu_id);
+        self.set_cpumask(CpuMask::one_shot(cpu_id));
+    }
+
+    #[inline]
+    #[cfg(feature = "smp")]
+    pub(crate) fn on_cpu(&self) -> bool {
+        self.on_cpu.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    #[cfg(feature = "smp")]
+    pub(crate) fn set_on_cpu(&self, on_cpu: bool) {
+        self.on_cpu.store(on_cpu, Ordering::Release)
+    }
+
     #[inline]
     pub(crate) fn in_wait_queue(&self) -> bool {
         self.in_wait_queue.load(Ordering::Acquire)
@@ -257,13 +422,24 @@ impl TaskInner {
     #[inline]
     #[cfg(feature = "irq")]
     pub(crate) fn in_timer_list(&self) -> bool {
-        self.in_timer_list.load(Ordering::Acquire)
+        self.timer_cpu_id().is_some()
+    }
+
+    /// Returns the CPU whose timer list has the wakeup event of the task.
+    #[inline]
+    #[cfg(feature = "irq")]
+    pub(crate) fn timer_cpu_id(&self) -> Option<usize> {
+        match self.timer_cpu_id.load(Ordering::Acquire) {
+            NO_TIMER => None,
+            cpu_id => Some(cpu_id),
+        }
     }
 
     #[inline]
     #[cfg(feature = "irq")]
-    pub(crate) fn set_in_timer_list(&self, in_timer_list: bool) {
-        self.in_timer_list.store(in_timer_list, Ordering::Release);
+    pub(crate) fn set_timer_cpu_id(&self, cpu_id: Option<usize>) {
+        self.timer_cpu_id
+            .store(cpu_id.unwrap_or(NO_TIMER), Ordering::Release);
     }
 
     #[inline]
@@ -296,17 +472,22 @@ impl TaskInner {
     #[cfg(feature = "preempt")]
     fn current_check_preempt_pending() {
         let curr = crate::current();
+        #[cfg(feature = "smp")]
+        if crate::run_queue::take_remote_resched() {
+            curr.set_preempt_pending(true);
+        }
         if curr.need_resched.load(Ordering::Acquire) && curr.can_preempt(0) {
-            let mut rq = crate::RUN_QUEUE.lock();
+            let rq = crate::current_run_queue();
             if curr.need_resched.load(Ordering::Acquire) {
                 rq.preempt_resched();
             }
         }
     }
 
-    pub(crate) fn notify_exit(&self, exit_code: i32, rq: &mut AxRunQueue) {
+    pub(crate) fn notify_exit(&self, exit_code: i32) {
         self.exit_code.store(exit_code, Ordering::Release);
-        self.wait_for_exit.notify_all_locked(false, rq);
+        self.set_state(TaskState::Exited);
+        self.wait_for_exit.notify_all(false);
     }
 
     #[inline]
@@ -328,6 +509,14 @@ impl TaskInner {
             None => None,
         }
     }
+
+    /// Returns whether `vaddr` is in the guard page below the kernel stack.
+    #[cfg(feature = "paging")]
+    pub(crate) fn stack_guard_contains(&self, vaddr: usize) -> bool {
+        self.kstack
+            .as_ref()
+            .is_some_and(|s| s.guard_page_contains(vaddr))
+    }
 }
 
 impl fmt::Debug for TaskInner {
@@ -343,30 +532,107 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        crate::registry::unregister(self);
     }
 }
 
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
+    /// Whether the stack is allocated by `axmm::alloc_kernel_stack` with a
+    /// guard page below it, rather than from the heap.
+    #[cfg(feature = "paging")]
+    guarded: bool,
 }
 
+/// Whether kernel stacks are allocated with guard pages.
+///
+/// Only x86_64 survives a fault on an overflowed stack, as the double fault
+/// handler runs on its own stack. On other architectures, the trap entry would
+/// save the registers on the same stack, fault again, and corrupt the memory
+/// below the guard page.
+#[cfg(feature = "paging")]
+const GUARDED_STACKS: bool = cfg!(target_arch = "x86_64");
+
+/// The word that unused stacks are filled with, to find the high-water mark.
+const STACK_PAINT: usize = usize::from_ne_bytes([0xab; core::mem::size_of::<usize>()]);
+
 impl TaskStack {
     pub fn alloc(size: usize) -> Self {
         let layout = Layout::from_size_align(size, 16).unwrap();
+        #[cfg(feature = "paging")]
+        let (ptr, guarded) = match GUARDED_STACKS.then(|| axmm::alloc_kernel_stack(size)) {
+            Some(Ok(bottom)) => (NonNull::new(bottom.as_mut_ptr()).unwrap(), true),
+            Some(Err(e)) => {
+                warn!("failed to allocate a kernel stack with guard page: {:?}", e);
+                (Self::alloc_from_heap(layout), false)
+            }
+            None => (Self::alloc_from_heap(layout), false),
+        };
+        #[cfg(not(feature = "paging"))]
+        let ptr = Self::alloc_from_heap(layout);
+
+        if cfg!(feature = "stack-usage") {
+            let words = size / core::mem::size_of::<usize>();
+            unsafe {
+                core::slice::from_raw_parts_mut(ptr.as_ptr() as *mut usize, words)
+                    .fill(STACK_PAINT)
+            };
+        }
         Self {
-            ptr: NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap(),
+            ptr,
             layout,
+            #[cfg(feature = "paging")]
+            guarded,
         }
     }
 
+    fn alloc_from_heap(layout: Layout) -> NonNull<u8> {
+        NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap()
+    }
+
     pub const fn top(&self) -> VirtAddr {
         unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
     }
+
+    pub const fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Returns whether `vaddr` is in the guard page below the stack.
+    #[cfg(feature = "paging")]
+    pub fn guard_page_contains(&self, vaddr: usize) -> bool {
+        let bottom = self.ptr.as_ptr() as usize;
+        self.guarded && (bottom - axhal::mem::PAGE_SIZE_4K..bottom).contains(&vaddr)
+    }
+
+    /// Returns the maximum number of bytes used so far, by finding the lowest
+    /// word that is not the paint. The stack may be in use on another CPU, so
+    /// it is read with volatile loads.
+    ///
+    /// Returns 0 if the `stack-usage` feature is disabled, as the stack is not
+    /// painted then.
+    pub fn high_water(&self) -> usize {
+        if !cfg!(feature = "stack-usage") {
+            return 0;
+        }
+        let base = self.ptr.as_ptr() as *const usize;
+        let words = self.size() / core::mem::size_of::<usize>();
+        let unused = (0..words)
+            .take_while(|&i| unsafe { base.add(i).read_volatile() } == STACK_PAINT)
+            .count();
+        (words - unused) * core::mem::size_of::<usize>()
+    }
 }
 
 impl Drop for TaskStack {
     fn drop(&mut self) {
+        #[cfg(feature = "paging")]
+        if self.guarded {
+            let bottom = VirtAddr::from(self.ptr.as_ptr() as usize);
+            axmm::dealloc_kernel_stack(bottom, self.size());
+            return;
+        }
         unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
     }
 }
@@ -407,6 +673,11 @@ impl CurrentTask {
 
     pub(crate) unsafe fn init_current(init_task: AxTaskRef) {
         assert!(init_task.is_init());
+        #[cfg(feature = "smp")]
+        init_task.set_on_cpu(true);
+        let now_ns = axhal::time::monotonic_time_nanos();
+        init_task.accounting().on_ready(now_ns);
+        init_task.accounting().on_switch_in(now_ns);
         #[cfg(feature = "tls")]
         axhal::arch::write_thread_pointer(init_task.tls.tls_ptr() as usize);
         let ptr = Arc::into_raw(init_task);
@@ -429,8 +700,11 @@ impl Deref for CurrentTask {
 }
 
 extern "C" fn task_entry() -> ! {
-    // release the lock that was implicitly held across the reschedule
-    unsafe { crate::RUN_QUEUE.force_unlock() };
+    // the task is switched to for the first time, finish the context switch
+    #[cfg(feature = "smp")]
+    unsafe {
+        crate::run_queue::clear_prev_task_on_cpu();
+    }
     #[cfg(feature = "irq")]
     axhal::arch::enable_irqs();
     let task = crate::current();
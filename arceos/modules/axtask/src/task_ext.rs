@@ -1,8 +1,12 @@
-//! User-defined task extended data.
+//! User-defined task extended data, and task-local variables.
 
+use alloc::{boxed::Box, collections::BTreeMap};
 use core::alloc::Layout;
+use core::any::Any;
 use core::mem::{align_of, size_of};
 
+use kspin::SpinNoIrq;
+
 #[no_mangle]
 #[linkage = "weak"]
 static __AX_TASK_EXT_SIZE: usize = 0;
@@ -11,9 +15,11 @@ static __AX_TASK_EXT_SIZE: usize = 0;
 #[linkage = "weak"]
 static __AX_TASK_EXT_ALIGN: usize = 0;
 
-/// A wrapper of pointer to the task extended data.
+/// A wrapper of pointer to the task extended data, which also keeps the
+/// task-local variables, so that they are dropped with the task.
 pub(crate) struct AxTaskExt {
     ptr: *mut u8,
+    locals: TaskLocals,
 }
 
 impl AxTaskExt {
@@ -38,6 +44,7 @@ impl AxTaskExt {
     pub const fn empty() -> Self {
         Self {
             ptr: core::ptr::null_mut(),
+            locals: TaskLocals::new(),
         }
     }
 
@@ -48,16 +55,15 @@ impl AxTaskExt {
 
     /// Allocates the space for the task extended data, but does not
     /// initialize the data.
-    pub unsafe fn uninited() -> Self {
+    unsafe fn alloc_data() -> *mut u8 {
         let size = Self::size();
         let align = Self::align();
-        let ptr = if size == 0 {
+        if size == 0 {
             core::ptr::null_mut()
         } else {
             let layout = Layout::from_size_align(size, align).unwrap();
             unsafe { alloc::alloc::alloc(layout) }
-        };
-        Self { ptr }
+        }
     }
 
     /// Gets the raw pointer to the task extended data.
@@ -65,6 +71,11 @@ impl AxTaskExt {
         self.ptr
     }
 
+    /// Returns the task-local variables.
+    pub const fn locals(&self) -> &TaskLocals {
+        &self.locals
+    }
+
     /// Write the given object to the task extended data.
     ///
     /// Returns [`None`] if the data size is zero, otherwise returns a mutable
@@ -84,7 +95,7 @@ impl AxTaskExt {
         }
 
         if self.ptr.is_null() {
-            *self = unsafe { Self::uninited() };
+            self.ptr = unsafe { Self::alloc_data() };
         }
         if data_size > 0 {
             let ptr = self.ptr as *mut T;
@@ -108,6 +119,65 @@ impl Drop for AxTaskExt {
     }
 }
 
+/// The task-local variables of a task, keyed by the addresses of their
+/// `static` keys.
+///
+/// They are only accessed by the task itself, and are dropped when it exits,
+/// or with the task if it does not exit (e.g., it never runs).
+pub(crate) struct TaskLocals {
+    /// [`None`] after the variables have been destroyed.
+    vars: SpinNoIrq<Option<BTreeMap<usize, Box<dyn Any>>>>,
+}
+
+impl TaskLocals {
+    pub const fn new() -> Self {
+        Self {
+            vars: SpinNoIrq::new(Some(BTreeMap::new())),
+        }
+    }
+
+    /// Returns a pointer to the variable of `key`, initializes it with `init`
+    /// first if it is not accessed before.
+    ///
+    /// Returns [`None`] if the variables have been destroyed. The pointer is
+    /// valid until then.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the variable of `key` is not of type `T`.
+    pub fn get_or_init<T: 'static>(
+        &self,
+        key: usize,
+        init: impl FnOnce() -> T,
+    ) -> Option<*const T> {
+        if let Some(var) = self.vars.lock().as_ref()?.get(&key) {
+            return Some(downcast(&**var));
+        }
+        // Do not hold the lock, `init` may access other variables.
+        let mut value = Some(Box::new(init()) as Box<dyn Any>);
+        let ptr = self.vars.lock().as_mut().map(|vars| {
+            let var = vars.entry(key).or_insert_with(|| value.take().unwrap());
+            downcast(&**var)
+        });
+        // Dropped out of the lock, if `init` has initialized the variable
+        // recursively.
+        drop(value);
+        ptr
+    }
+
+    /// Drops all the variables. The variables accessed afterwards (e.g., in
+    /// the destructors) are not initialized again.
+    pub fn destroy(&self) {
+        let vars = self.vars.lock().take();
+        drop(vars);
+    }
+}
+
+fn downcast<T: 'static>(var: &dyn Any) -> *const T {
+    var.downcast_ref::<T>()
+        .expect("task-local variable type mismatch")
+}
+
 /// A trait to convert [`TaskInner::task_ext_ptr`] to the reference of the
 /// concrete type.
 ///
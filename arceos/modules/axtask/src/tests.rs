@@ -1,11 +1,22 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Mutex, Once};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once};
 
-use crate::{api as axtask, current, WaitQueue};
+use crate::{api as axtask, current, AxTaskRef, WaitQueue};
 
 static INIT: Once = Once::new();
 static SERIAL: Mutex<()> = Mutex::new(());
 
+/// Runs `f` with each built-in scheduler, then goes back to the one in use.
+fn for_each_scheduler(f: impl Fn()) {
+    let prev = axtask::scheduler_name();
+    for name in ["fifo", "rr", "cfs"] {
+        assert!(axtask::select_scheduler(name));
+        println!("with {} scheduler:", axtask::scheduler_name());
+        f();
+    }
+    assert!(axtask::select_scheduler(prev));
+}
+
 #[test]
 fn test_sched_fifo() {
     let _lock = SERIAL.lock();
@@ -14,22 +25,25 @@ fn test_sched_fifo() {
     const NUM_TASKS: usize = 10;
     static FINISHED_TASKS: AtomicUsize = AtomicUsize::new(0);
 
-    for i in 0..NUM_TASKS {
-        axtask::spawn_raw(
-            move || {
-                println!("sched_fifo: Hello, task {}! ({})", i, current().id_name());
-                axtask::yield_now();
-                let order = FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
-                assert_eq!(order, i); // FIFO scheduler
-            },
-            format!("T{}", i),
-            0x1000,
-        );
-    }
+    for_each_scheduler(|| {
+        FINISHED_TASKS.store(0, Ordering::Relaxed);
+        for i in 0..NUM_TASKS {
+            axtask::spawn_raw(
+                move || {
+                    println!("sched_fifo: Hello, task {}! ({})", i, current().id_name());
+                    axtask::yield_now();
+                    let order = FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
+                    assert_eq!(order, i); // FIFO order without timer ticks
+                },
+                format!("T{}", i),
+                0x1000,
+            );
+        }
 
-    while FINISHED_TASKS.load(Ordering::Relaxed) < NUM_TASKS {
-        axtask::yield_now();
-    }
+        while FINISHED_TASKS.load(Ordering::Relaxed) < NUM_TASKS {
+            axtask::yield_now();
+        }
+    });
 }
 
 #[test]
@@ -47,20 +61,23 @@ fn test_fp_state_switch() {
     ];
     static FINISHED_TASKS: AtomicUsize = AtomicUsize::new(0);
 
-    for (i, float) in FLOATS.iter().enumerate() {
-        axtask::spawn(move || {
-            let mut value = float + i as f64;
-            axtask::yield_now();
-            value -= i as f64;
+    for_each_scheduler(|| {
+        FINISHED_TASKS.store(0, Ordering::Relaxed);
+        for (i, float) in FLOATS.iter().enumerate() {
+            axtask::spawn(move || {
+                let mut value = float + i as f64;
+                axtask::yield_now();
+                value -= i as f64;
 
-            println!("fp_state_switch: Float {} = {}", i, value);
-            assert!((value - float).abs() < 1e-9);
-            FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
-        });
-    }
-    while FINISHED_TASKS.load(Ordering::Relaxed) < NUM_TASKS {
-        axtask::yield_now();
-    }
+                println!("fp_state_switch: Float {} = {}", i, value);
+                assert!((value - float).abs() < 1e-9);
+                FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        while FINISHED_TASKS.load(Ordering::Relaxed) < NUM_TASKS {
+            axtask::yield_now();
+        }
+    });
 }
 
 #[test]
@@ -74,34 +91,36 @@ fn test_wait_queue() {
     static WQ2: WaitQueue = WaitQueue::new();
     static COUNTER: AtomicUsize = AtomicUsize::new(0);
 
-    for _ in 0..NUM_TASKS {
-        axtask::spawn(move || {
-            COUNTER.fetch_add(1, Ordering::Relaxed);
-            println!("wait_queue: task {:?} started", current().id());
-            WQ1.notify_one(true); // WQ1.wait_until()
-            WQ2.wait();
+    for_each_scheduler(|| {
+        for _ in 0..NUM_TASKS {
+            axtask::spawn(move || {
+                COUNTER.fetch_add(1, Ordering::Relaxed);
+                println!("wait_queue: task {:?} started", current().id());
+                WQ1.notify_one(true); // WQ1.wait_until()
+                WQ2.wait();
 
-            assert!(!current().in_wait_queue());
+                assert!(!current().in_wait_queue());
 
-            COUNTER.fetch_sub(1, Ordering::Relaxed);
-            println!("wait_queue: task {:?} finished", current().id());
-            WQ1.notify_one(true); // WQ1.wait_until()
-        });
-    }
+                COUNTER.fetch_sub(1, Ordering::Relaxed);
+                println!("wait_queue: task {:?} finished", current().id());
+                WQ1.notify_one(true); // WQ1.wait_until()
+            });
+        }
 
-    println!("task {:?} is waiting for tasks to start...", current().id());
-    WQ1.wait_until(|| COUNTER.load(Ordering::Relaxed) == NUM_TASKS);
-    assert_eq!(COUNTER.load(Ordering::Relaxed), NUM_TASKS);
-    assert!(!current().in_wait_queue());
-    WQ2.notify_all(true); // WQ2.wait()
+        println!("task {:?} is waiting for tasks to start...", current().id());
+        WQ1.wait_until(|| COUNTER.load(Ordering::Relaxed) == NUM_TASKS);
+        assert_eq!(COUNTER.load(Ordering::Relaxed), NUM_TASKS);
+        assert!(!current().in_wait_queue());
+        WQ2.notify_all(true); // WQ2.wait()
 
-    println!(
-        "task {:?} is waiting for tasks to finish...",
-        current().id()
-    );
-    WQ1.wait_until(|| COUNTER.load(Ordering::Relaxed) == 0);
-    assert_eq!(COUNTER.load(Ordering::Relaxed), 0);
-    assert!(!current().in_wait_queue());
+        println!(
+            "task {:?} is waiting for tasks to finish...",
+            current().id()
+        );
+        WQ1.wait_until(|| COUNTER.load(Ordering::Relaxed) == 0);
+        assert_eq!(COUNTER.load(Ordering::Relaxed), 0);
+        assert!(!current().in_wait_queue());
+    });
 }
 
 #[test]
@@ -110,21 +129,669 @@ fn test_task_join() {
     INIT.call_once(axtask::init_scheduler);
 
     const NUM_TASKS: usize = 10;
-    let mut tasks = Vec::with_capacity(NUM_TASKS);
 
-    for i in 0..NUM_TASKS {
-        tasks.push(axtask::spawn_raw(
-            move || {
-                println!("task_join: task {}! ({})", i, current().id_name());
+    for_each_scheduler(|| {
+        let mut tasks = Vec::with_capacity(NUM_TASKS);
+        for i in 0..NUM_TASKS {
+            tasks.push(axtask::spawn_raw(
+                move || {
+                    println!("task_join: task {}! ({})", i, current().id_name());
+                    axtask::yield_now();
+                    axtask::exit(i as _);
+                },
+                format!("T{}", i),
+                0x1000,
+            ));
+        }
+
+        for i in 0..NUM_TASKS {
+            assert_eq!(tasks[i].join(), Some(i as _));
+        }
+    });
+}
+
+#[test]
+fn test_affinity() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let task = axtask::spawn_raw(|| axtask::yield_now(), "affinity".into(), 0x1000);
+    assert_eq!(axtask::get_affinity(&task), axtask::CpuMask::full());
+
+    // A mask without any online CPU is rejected.
+    assert!(!axtask::set_affinity(&task, axtask::CpuMask::new()));
+    assert_eq!(axtask::get_affinity(&task), axtask::CpuMask::full());
+
+    let cpumask = axtask::CpuMask::one_shot(0);
+    assert!(axtask::set_affinity(&task, cpumask));
+    assert_eq!(axtask::get_affinity(&task), cpumask);
+    assert!(axtask::set_current_affinity(cpumask));
+    task.join();
+}
+
+#[test]
+fn test_steal_task() {
+    use crate::rt::{ClassScheduler, SchedPolicy};
+    use crate::TaskInner;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    for_each_scheduler(|| {
+        let mut scheduler = ClassScheduler::new(crate::sched::new_scheduler());
+        // A real-time task and normal tasks, some of them pinned to CPU 0.
+        let tasks: Vec<AxTaskRef> = [true, true, false, true, false]
+            .iter()
+            .enumerate()
+            .map(|(i, &pinned)| {
+                let mut task = TaskInner::new(|| {}, format!("steal{}", i), 0x1000);
+                if pinned {
+                    task.pin_to_cpu(0);
+                }
+                let task = task.into_arc();
+                if i == 0 {
+                    task.rt().set_params(SchedPolicy::Fifo, 10);
+                }
+                scheduler.add_task(task.clone());
+                task
+            })
+            .collect();
+
+        // The tasks allowed to run on CPU 1 are stolen in the order they
+        // would run, the pinned ones keep their order.
+        let mut steal = || scheduler.take_task_if(|t| t.cpumask().get(1));
+        assert!(Arc::ptr_eq(&steal().unwrap(), &tasks[2]));
+        assert!(Arc::ptr_eq(&steal().unwrap(), &tasks[4]));
+        assert!(steal().is_none());
+        for i in [0, 1, 3] {
+            assert!(Arc::ptr_eq(&scheduler.pick_next_task().unwrap(), &tasks[i]));
+        }
+        assert!(scheduler.pick_next_task().is_none());
+    });
+}
+
+#[test]
+fn test_cfs_migration() {
+    use crate::{Scheduler, TaskInner};
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let prev = axtask::scheduler_name();
+    assert!(axtask::select_scheduler("cfs"));
+    let (mut src, mut dst) = (crate::sched::new_scheduler(), crate::sched::new_scheduler());
+    assert!(axtask::select_scheduler(prev));
+
+    let new_task = |name: &str| TaskInner::new(|| {}, name.into(), 0x1000).into_arc();
+    // Runs the next task of `sched` for some timer ticks.
+    let run = |sched: &mut Box<dyn Scheduler>, ticks| {
+        let task = sched.pick_next_task().unwrap();
+        for _ in 0..ticks {
+            sched.task_tick(&task);
+        }
+        sched.put_prev_task(task, true);
+    };
+
+    // `old` has run for long on a CPU, and is 10 ticks past the others there.
+    let old = new_task("old");
+    src.add_task(old.clone());
+    run(&mut src, 100);
+    run(&mut src, 10);
+    // `busy` is 20 ticks past the others on another CPU.
+    let busy = new_task("busy");
+    dst.add_task(busy.clone());
+    run(&mut dst, 20);
+
+    // `old` keeps its place when it is moved, so it runs before `busy`.
+    let old = src.remove_task(&old).unwrap();
+    dst.add_task(old.clone());
+    assert!(Arc::ptr_eq(&dst.pick_next_task().unwrap(), &old));
+    assert!(Arc::ptr_eq(&dst.pick_next_task().unwrap(), &busy));
+}
+
+#[test]
+fn test_task_registry() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    let task = axtask::spawn_raw(|| axtask::exit(7), "registry".into(), 0x1000);
+    let id = task.id().as_u64();
+    assert!(axtask::all_tasks().any(|t| t.id().as_u64() == id));
+
+    let info = axtask::find_task(id).unwrap().info();
+    assert_eq!(info.name, "registry");
+    assert_eq!(info.state, axtask::TaskState::Ready);
+    assert_eq!(info.stack_size, 0x1000);
+
+    assert_eq!(task.join(), Some(7));
+    let info = task.info();
+    assert_eq!(info.state, axtask::TaskState::Exited);
+    assert_eq!(info.exit_code, Some(7));
+    assert!(info.stack_high_water > 0);
+}
+
+#[test]
+fn test_sched_stats() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static WOKEN: AtomicUsize = AtomicUsize::new(0);
+
+    let task = axtask::spawn_raw(
+        || {
+            let _guard = axtask::KernelModeGuard::new();
+            WQ.wait_until(|| WOKEN.load(Ordering::Acquire) > 0);
+        },
+        "stats".into(),
+        0x1000,
+    );
+    while !WQ.notify_one(true) {
+        axtask::yield_now();
+    }
+    WOKEN.store(1, Ordering::Release);
+    WQ.notify_one(true);
+    task.join();
+
+    // Blocked at least once, then exited.
+    let stats = task.sched_stats();
+    assert!(stats.voluntary_switches >= 2);
+    let total = axtask::total_sched_stats();
+    assert!(total.voluntary_switches >= stats.voluntary_switches);
+}
+
+#[test]
+fn test_rt_priority() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    const PRIORITIES: [u8; 4] = [0, 10, 50, 10];
+
+    let tasks: Vec<_> = PRIORITIES
+        .iter()
+        .enumerate()
+        .map(|(i, &prio)| {
+            let task = axtask::spawn_raw(
+                move || ORDER.lock().unwrap().push(i),
+                format!("RT{}", i),
+                0x1000,
+            );
+            if prio > 0 {
+                assert!(axtask::set_scheduler(&task, axtask::SchedPolicy::Fifo, prio));
+            }
+            task
+        })
+        .collect();
+
+    assert!(!axtask::set_scheduler(&tasks[0], axtask::SchedPolicy::Fifo, 0));
+    assert!(!axtask::set_scheduler(&tasks[0], axtask::SchedPolicy::Normal, 1));
+    assert_eq!(
+        axtask::get_scheduler(&tasks[2]),
+        (axtask::SchedPolicy::Fifo, 50)
+    );
+
+    // Real-time tasks run first, in the order of priorities.
+    for task in &tasks {
+        task.join();
+    }
+    assert_eq!(*ORDER.lock().unwrap(), [2, 1, 3, 0]);
+}
+
+#[test]
+fn test_select_scheduler() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    /// Runs the task that becomes ready last first.
+    #[derive(Default)]
+    struct LifoScheduler(Vec<AxTaskRef>);
+
+    impl axtask::Scheduler for LifoScheduler {
+        fn add_task(&mut self, task: AxTaskRef) {
+            self.0.push(task);
+        }
+        fn remove_task(&mut self, task: &AxTaskRef) -> Option<AxTaskRef> {
+            let idx = self.0.iter().position(|t| Arc::ptr_eq(t, task))?;
+            Some(self.0.remove(idx))
+        }
+        fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+            self.0.pop()
+        }
+        fn put_prev_task(&mut self, prev: AxTaskRef, _preempt: bool) {
+            self.0.insert(0, prev);
+        }
+        fn task_tick(&mut self, _current: &AxTaskRef) -> bool {
+            false
+        }
+        fn set_priority(&mut self, _task: &AxTaskRef, _prio: isize) -> bool {
+            false
+        }
+    }
+
+    let prev = axtask::scheduler_name();
+    assert!(!axtask::select_scheduler("lifo"));
+    assert!(axtask::register_scheduler("lifo", || Box::new(LifoScheduler::default())));
+    assert!(!axtask::register_scheduler("cfs", || Box::new(LifoScheduler::default())));
+    assert!(axtask::scheduler_names().contains(&"lifo"));
+
+    static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    assert!(axtask::select_scheduler("lifo"));
+    let tasks: Vec<_> = (0..3)
+        .map(|i| axtask::spawn(move || ORDER.lock().unwrap().push(i)))
+        .collect();
+    for task in &tasks {
+        task.join();
+    }
+    assert_eq!(*ORDER.lock().unwrap(), [2, 1, 0]);
+
+    // Only `cfs` supports nice values.
+    assert!(axtask::select_scheduler("cfs"));
+    assert!(axtask::set_priority(-20));
+    assert!(!axtask::set_priority(20));
+    assert!(axtask::set_priority(0));
+    assert!(axtask::select_scheduler("fifo"));
+    assert!(!axtask::set_priority(0));
+    assert!(axtask::select_scheduler(prev));
+}
+
+#[test]
+fn test_cancel() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+
+    let task = axtask::spawn_raw(
+        || {
+            assert_eq!(WQ.wait_interruptible(), Err(axtask::Interrupted));
+            assert!(axtask::is_cancelled());
+            axtask::exit(1);
+        },
+        "cancel".into(),
+        0x1000,
+    );
+    axtask::yield_now(); // let it wait
+    assert!(!task.is_cancelled());
+    axtask::cancel(&task);
+    assert!(task.is_cancelled());
+    assert_eq!(task.join(), Some(1));
+    assert!(!WQ.notify_one(false)); // removed from the wait queue
+}
+
+#[test]
+fn test_notifications() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static READY: AtomicUsize = AtomicUsize::new(0);
+
+    let task = axtask::spawn_raw(
+        || {
+            assert_eq!(axtask::set_notification_mask(0b10), 0);
+            // Masked notifications do not interrupt waits.
+            let res = WQ.wait_until_interruptible(|| READY.load(Ordering::Acquire) > 0);
+            assert_eq!(res, Ok(()));
+            assert_eq!(axtask::pending_notifications(), 0b10);
+
+            assert_eq!(WQ.wait_interruptible(), Err(axtask::Interrupted));
+            assert_eq!(axtask::take_notifications(0b1), 0b1);
+            assert_eq!(axtask::pending_notifications(), 0b10);
+            assert!(!axtask::is_cancelled());
+        },
+        "notify".into(),
+        0x1000,
+    );
+    axtask::yield_now(); // let it wait until ready
+    axtask::send_notification(&task, 0b10);
+    READY.store(1, Ordering::Release);
+    WQ.notify_one(true);
+    axtask::yield_now(); // let it wait again
+    axtask::send_notification(&task, 0b1);
+    task.join();
+}
+
+#[cfg(feature = "smp")]
+#[test]
+fn test_notification_race() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const ROUNDS: usize = 1000;
+    static WQ: WaitQueue = WaitQueue::new();
+    static ROUND: AtomicUsize = AtomicUsize::new(0);
+    ROUND.store(0, Ordering::Relaxed);
+
+    let waiter = axtask::spawn_raw(
+        || {
+            for round in 1..=ROUNDS {
+                // Woken up by the notification, or sees it before blocking.
+                assert_eq!(WQ.wait_interruptible(), Err(axtask::Interrupted));
+                assert_eq!(axtask::take_notifications(0b1), 0b1);
+                ROUND.store(round, Ordering::Release);
+            }
+        },
+        "race".into(),
+        0x1000,
+    );
+    // Another CPU (a host thread here) notifies the waiter once per round,
+    // as soon as it starts waiting, so that some notifications land between
+    // its check and its blocking.
+    let task = waiter.clone();
+    let notifier = std::thread::spawn(move || {
+        for round in 0..ROUNDS {
+            while ROUND.load(Ordering::Acquire) != round {
+                core::hint::spin_loop();
+            }
+            axtask::send_notification(&task, 0b1);
+        }
+    });
+    waiter.join();
+    notifier.join().unwrap();
+    assert!(!WQ.notify_one(false));
+}
+
+#[test]
+fn test_executor() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_FUTURES: usize = 10;
+    static WQ: WaitQueue = WaitQueue::new();
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let executor = crate::future::Executor::new();
+    for _ in 0..NUM_FUTURES {
+        executor.spawn(async {
+            WQ.wait_until_async(|| COUNTER.load(Ordering::Acquire) > 0)
+                .await;
+            crate::future::yield_now().await;
+            COUNTER.fetch_add(1, Ordering::AcqRel);
+        });
+    }
+    let notifier = axtask::spawn(|| {
+        COUNTER.store(1, Ordering::Release);
+        WQ.notify_all(true);
+    });
+    executor.run();
+    assert_eq!(executor.pending(), 0);
+    assert_eq!(COUNTER.load(Ordering::Acquire), NUM_FUTURES + 1);
+    notifier.join();
+
+    let value = crate::future::block_on(async {
+        crate::future::yield_now().await;
+        42
+    });
+    assert_eq!(value, 42);
+}
+
+#[test]
+fn test_dropped_waker() {
+    use core::future::Future;
+    use core::task::{Context, Waker};
+    use std::task::Wake;
+
+    struct Counter(AtomicUsize);
+
+    impl Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    let wq = WaitQueue::new();
+    let (a, b) = (Arc::new(Counter(AtomicUsize::new(0))), Arc::new(Counter(AtomicUsize::new(0))));
+    let (waker_a, waker_b) = (Waker::from(a.clone()), Waker::from(b.clone()));
+    let mut future_a = Box::pin(wq.wait_until_async(|| false));
+    let mut future_b = Box::pin(wq.wait_until_async(|| false));
+    // A future keeps one waker however many times it is polled.
+    for _ in 0..3 {
+        assert!(future_a.as_mut().poll(&mut Context::from_waker(&waker_a)).is_pending());
+    }
+    assert!(future_b.as_mut().poll(&mut Context::from_waker(&waker_b)).is_pending());
+
+    // The waker of a dropped future does not take the notification.
+    drop(future_a);
+    assert!(wq.notify_one(false));
+    assert_eq!(a.0.load(Ordering::Acquire), 0);
+    assert_eq!(b.0.load(Ordering::Acquire), 1);
+    assert!(!wq.notify_one(false));
+}
+
+#[test]
+fn test_task_local() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 5;
+    static KEY: u8 = 0;
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Local(usize);
+
+    impl Drop for Local {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(self.0, Ordering::AcqRel);
+            // Not initialized again while exiting.
+            assert_eq!(axtask::with_current_local(&KEY as *const _ as usize, || 0, |_| ()), None);
+        }
+    }
+
+    let key = &KEY as *const _ as usize;
+    let tasks = (1..=NUM_TASKS)
+        .map(|i| {
+            axtask::spawn(move || {
+                let get = || axtask::with_current_local(key, || Local(i), |l| l.0);
+                assert_eq!(get(), Some(i));
                 axtask::yield_now();
-                axtask::exit(i as _);
-            },
-            format!("T{}", i),
-            0x1000,
-        ));
+                assert_eq!(get(), Some(i)); // initialized only once
+            })
+        })
+        .collect::<Vec<_>>();
+    for t in tasks {
+        t.join();
+    }
+    assert_eq!(DROPPED.load(Ordering::Acquire), NUM_TASKS * (NUM_TASKS + 1) / 2);
+}
+
+#[test]
+fn test_futex() {
+    use crate::{FutexError, FUTEX_BITSET_MATCH_ANY as ANY};
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static FUTEX: AtomicU32 = AtomicU32::new(0);
+    static FUTEX2: AtomicU32 = AtomicU32::new(0);
+    static WOKEN: AtomicUsize = AtomicUsize::new(0);
+
+    // Does not block if the value has changed.
+    assert_eq!(axtask::futex_wait(&FUTEX, 1, None, ANY), Err(FutexError::WouldBlock));
+    assert_eq!(axtask::futex_wait(&FUTEX, 0, None, 0), Err(FutexError::InvalidInput));
+
+    let waiters = (0..4u32)
+        .map(|i| {
+            axtask::spawn(move || {
+                assert_eq!(axtask::futex_wait(&FUTEX, 0, None, 1 << (i % 2)), Ok(()));
+                WOKEN.fetch_add(1, Ordering::AcqRel);
+            })
+        })
+        .collect::<Vec<_>>();
+    axtask::yield_now(); // let them wait
+
+    // Only the waiters with matching bitsets.
+    assert_eq!(axtask::futex_wake(&FUTEX, usize::MAX, 0b10), Ok(2));
+    // Wake up one, and move the other to `FUTEX2`.
+    assert_eq!(axtask::futex_requeue(&FUTEX, 1, &FUTEX2, 1, Some(1)), Err(FutexError::WouldBlock));
+    assert_eq!(axtask::futex_requeue(&FUTEX, 1, &FUTEX2, 1, Some(0)), Ok(2));
+    assert_eq!(axtask::futex_wake(&FUTEX, 1, ANY), Ok(0));
+    assert_eq!(axtask::futex_wake(&FUTEX2, 1, ANY), Ok(1));
+    for t in waiters {
+        t.join();
     }
+    assert_eq!(WOKEN.load(Ordering::Acquire), 4);
+}
+
+#[test]
+fn test_futex_pi() {
+    use core::sync::atomic::AtomicU64;
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static OWNER: AtomicU64 = AtomicU64::new(0);
+    static ORDER: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+    let low = axtask::spawn(|| {
+        axtask::futex_lock_pi(&OWNER);
+        axtask::yield_now();
+        ORDER.lock().unwrap().push("low");
+        axtask::futex_unlock_pi(&OWNER);
+    });
+    while OWNER.load(Ordering::Acquire) == 0 {
+        axtask::yield_now();
+    }
+    assert_eq!(OWNER.load(Ordering::Acquire), low.id().as_u64());
+
+    let high = axtask::spawn(|| {
+        axtask::futex_lock_pi(&OWNER);
+        ORDER.lock().unwrap().push("high");
+        axtask::futex_unlock_pi(&OWNER);
+    });
+    let medium = axtask::spawn(|| ORDER.lock().unwrap().push("medium"));
+    assert!(axtask::set_scheduler(&high, axtask::SchedPolicy::Fifo, 50));
+    assert!(axtask::set_scheduler(&medium, axtask::SchedPolicy::Fifo, 10));
+
+    // `low` runs with the priority of `high` once `high` waits for it.
+    high.join();
+    medium.join();
+    low.join();
+    assert_eq!(*ORDER.lock().unwrap(), ["low", "high", "medium"]);
+    assert_eq!(OWNER.load(Ordering::Acquire), 0);
+}
+
+/// Runs the expired timer events by hand until `cond` holds, as there are no
+/// timer interrupts in tests.
+#[cfg(feature = "irq")]
+fn tick_until(cond: impl Fn() -> bool) {
+    while !cond() {
+        axtask::on_timer_tick();
+        axtask::yield_now();
+    }
+}
+
+#[cfg(feature = "irq")]
+#[test]
+fn test_timers() {
+    use axhal::time::wall_time;
+    use core::time::Duration;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const PERIOD: Duration = Duration::from_millis(5);
+    static ONE_SHOT: AtomicUsize = AtomicUsize::new(0);
+    static PERIODIC: AtomicUsize = AtomicUsize::new(0);
+    static CANCELLED: AtomicUsize = AtomicUsize::new(0);
+
+    // A one-shot timer fires once, no earlier than its deadline.
+    let deadline = wall_time() + PERIOD;
+    let timer = axtask::set_timer(deadline, move |now| {
+        assert!(now >= deadline);
+        ONE_SHOT.fetch_add(1, Ordering::AcqRel);
+    });
+    assert!(timer.is_active());
+    tick_until(|| ONE_SHOT.load(Ordering::Acquire) == 1);
+    assert!(!timer.is_active());
+    assert!(!timer.cancel());
+
+    // A periodic timer fires until it is cancelled.
+    let timer = axtask::set_periodic_timer(wall_time() + PERIOD, PERIOD, |_| {
+        PERIODIC.fetch_add(1, Ordering::AcqRel);
+    });
+    tick_until(|| PERIODIC.load(Ordering::Acquire) >= 3);
+    assert!(timer.is_active());
+    assert!(timer.cancel());
+    assert!(!timer.is_active());
+    assert!(!timer.cancel());
+
+    // Cancelled timers do not fire any more.
+    let count = PERIODIC.load(Ordering::Acquire);
+    let timer = axtask::set_timer(wall_time() + PERIOD, |_| {
+        CANCELLED.fetch_add(1, Ordering::AcqRel);
+    });
+    assert!(timer.cancel());
+    let deadline = wall_time() + PERIOD * 3;
+    tick_until(|| wall_time() >= deadline);
+    assert_eq!(PERIODIC.load(Ordering::Acquire), count);
+    assert_eq!(ONE_SHOT.load(Ordering::Acquire), 1);
+    assert_eq!(CANCELLED.load(Ordering::Acquire), 0);
+}
+
+#[cfg(feature = "irq")]
+#[test]
+fn test_timer_events() {
+    use axhal::time::wall_time;
+    use core::time::Duration;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static ORDER: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+    ORDER.lock().unwrap().clear();
 
-    for i in 0..NUM_TASKS {
-        assert_eq!(tasks[i].join(), Some(i as _));
+    // Wakeups of blocked tasks and timer callbacks share the timer list, and
+    // run in the order of their deadlines.
+    let sleeper = axtask::spawn(|| {
+        axtask::sleep(Duration::from_millis(20));
+        ORDER.lock().unwrap().push("wakeup");
+    });
+    axtask::yield_now(); // let it sleep
+    let timer = axtask::set_timer(wall_time() + Duration::from_millis(5), |_| {
+        ORDER.lock().unwrap().push("callback");
+    });
+    tick_until(|| ORDER.lock().unwrap().len() == 2);
+    sleeper.join();
+    assert!(!timer.is_active());
+    assert_eq!(*ORDER.lock().unwrap(), ["callback", "wakeup"]);
+}
+
+#[cfg(feature = "tickless")]
+#[test]
+fn test_tickless_timer() {
+    use axhal::time::{epochoffset_nanos, monotonic_time_nanos, wall_time, NANOS_PER_SEC};
+    use core::time::Duration;
+    use kernel_guard::NoPreemptIrqSave;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static FIRED: AtomicUsize = AtomicUsize::new(0);
+    FIRED.store(0, Ordering::Relaxed);
+    let cpu_id = axhal::cpu::this_cpu_id();
+    axtask::on_timer_tick(); // program the timer interrupt
+
+    let deadline = wall_time() + Duration::from_millis(50);
+    let deadline_ns = deadline.as_nanos() as u64 - epochoffset_nanos();
+    let _timer = axtask::set_timer(deadline, |_| {
+        FIRED.fetch_add(1, Ordering::Release);
+    });
+
+    // Once there is nothing else to run, the idle CPU stops its tick and only
+    // wakes up for the timer.
+    while !crate::timers::tick_stopped(cpu_id) {
+        axtask::yield_now();
+        let _guard = NoPreemptIrqSave::new();
+        crate::run_queue::current_run_queue().stop_tick_if_idle();
     }
+    assert_eq!(crate::timers::armed_ns(), deadline_ns);
+
+    // A new task restarts the tick.
+    let task = axtask::spawn(|| {});
+    assert!(!crate::timers::tick_stopped(cpu_id));
+    let tick_ns = NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+    assert!(crate::timers::armed_ns() <= monotonic_time_nanos() + tick_ns);
+    task.join();
+
+    tick_until(|| FIRED.load(Ordering::Acquire) == 1);
+    assert!(wall_time() >= deadline);
 }
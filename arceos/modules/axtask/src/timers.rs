@@ -1,48 +1,346 @@
-use alloc::sync::Arc;
-use axhal::time::wall_time;
+//! Per-CPU timer lists.
+//!
+//! Each CPU checks the events in its own timer list in its timer interrupt.
+//! The interrupt is programmed for the next periodic tick or the next event,
+//! whichever is earlier, so timers are not limited to the tick resolution.
+//!
+//! With the `tickless` feature, an idle CPU stops its periodic tick, and only
+//! wakes up for the next event, or an IPI from other CPUs that put tasks into
+//! its run queue. With multiple CPUs it still wakes up every
+//! [`MAX_IDLE_TICKS`] ticks, to steal tasks from busy CPUs.
+
+use alloc::{boxed::Box, sync::Arc};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use axhal::time::{epochoffset_nanos, monotonic_time_nanos, wall_time, NANOS_PER_SEC};
+use kernel_guard::NoPreemptIrqSave;
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use timer_list::{TimeValue, TimerEvent, TimerList};
 
-use crate::{AxTaskRef, RUN_QUEUE};
+use crate::AxTaskRef;
+
+/// Nanoseconds between two periodic ticks.
+const TICK_INTERVAL_NANOS: u64 = NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+
+/// Periodic ticks that an idle CPU sleeps for at most with its tick stopped,
+/// if there are multiple CPUs, so that it still balances the load now and
+/// then.
+#[cfg(feature = "tickless")]
+const MAX_IDLE_TICKS: u64 = 10;
+
+/// Whether the periodic tick of each CPU is stopped.
+#[cfg(feature = "tickless")]
+static TICK_STOPPED: [AtomicBool; axconfig::SMP] =
+    [const { AtomicBool::new(false) }; axconfig::SMP];
+
+/// Timer lists of all CPUs, indexed by the CPU ID.
+static CPU_TIMERS: [LazyInit<SpinNoIrq<CpuTimers>>; axconfig::SMP] =
+    [const { LazyInit::new() }; axconfig::SMP];
+
+struct CpuTimers {
+    events: TimerList<AxTimerEvent>,
+    /// Monotonic time of the next periodic tick, in nanoseconds. It is
+    /// `u64::MAX` if the tick is stopped and never due.
+    next_tick_ns: u64,
+    /// Monotonic time that the timer interrupt is programmed for, in
+    /// nanoseconds. It is 0 before the first timer interrupt.
+    armed_ns: u64,
+}
+
+enum AxTimerEvent {
+    /// Wakes up a task blocked with a timeout.
+    Wakeup(AxTaskRef),
+    /// Runs the callback of a timer, with the deadline it is set for.
+    Callback(Arc<TimerInner>, TimeValue),
+}
+
+struct TimerInner {
+    /// The CPU whose timer list has the timer. Periodic timers are re-armed
+    /// on the same CPU.
+    cpu_id: usize,
+    interval: Option<Duration>,
+    active: AtomicBool,
+    callback: SpinNoIrq<Box<dyn FnMut(TimeValue) + Send>>,
+}
+
+/// A handle to a timer set by [`set_timer`] or [`set_periodic_timer`].
+///
+/// Dropping the handle does not cancel the timer.
+pub struct TimerHandle(Arc<TimerInner>);
+
+impl TimerHandle {
+    /// Cancels the timer.
+    ///
+    /// Returns `false` if it has been cancelled, or it is a one-shot timer
+    /// that has fired. The callback may still be running on another CPU when
+    /// this function returns.
+    pub fn cancel(&self) -> bool {
+        let timer = &self.0;
+        if !timer.active.swap(false, Ordering::AcqRel) {
+            return false;
+        }
+        CPU_TIMERS[timer.cpu_id]
+            .lock()
+            .events
+            .cancel(|e| matches!(e, AxTimerEvent::Callback(t, _) if Arc::ptr_eq(t, timer)));
+        true
+    }
+
+    /// Returns whether the timer is still going to fire.
+    pub fn is_active(&self) -> bool {
+        self.0.active.load(Ordering::Acquire)
+    }
+}
+
+impl CpuTimers {
+    fn new() -> Self {
+        Self {
+            events: TimerList::new(),
+            next_tick_ns: 0,
+            armed_ns: 0,
+        }
+    }
+
+    fn add_event(&mut self, deadline: TimeValue, event: AxTimerEvent) {
+        self.events.set(deadline, event);
+        // Before the first timer interrupt, it will be programmed there.
+        let deadline_ns = wall_to_monotonic_nanos(deadline);
+        if self.armed_ns != 0 && deadline_ns < self.armed_ns {
+            self.arm(deadline_ns);
+        }
+    }
+
+    /// Programs the timer interrupt for the next periodic tick or the next
+    /// event, whichever is earlier.
+    fn arm_next(&mut self) {
+        let deadline_ns = match self.events.next_deadline() {
+            Some(deadline) => wall_to_monotonic_nanos(deadline).min(self.next_tick_ns),
+            None => self.next_tick_ns,
+        };
+        self.arm(deadline_ns);
+    }
+
+    fn arm(&mut self, deadline_ns: u64) {
+        self.armed_ns = deadline_ns.max(1);
+        // Nothing to wait for, the next event added will program it.
+        if deadline_ns != u64::MAX {
+            axhal::time::set_oneshot_timer(deadline_ns);
+        }
+    }
+}
 
-// TODO: per-CPU
-static TIMER_LIST: LazyInit<SpinNoIrq<TimerList<TaskWakeupEvent>>> = LazyInit::new();
+/// Returns the monotonic time of the next periodic tick, in nanoseconds,
+/// after the tick is stopped at `now_ns`.
+#[cfg(feature = "tickless")]
+fn idle_tick_ns(now_ns: u64) -> u64 {
+    if cfg!(feature = "smp") {
+        now_ns + MAX_IDLE_TICKS * TICK_INTERVAL_NANOS
+    } else {
+        u64::MAX
+    }
+}
 
-struct TaskWakeupEvent(AxTaskRef);
+impl TimerEvent for AxTimerEvent {
+    fn callback(self, now: TimeValue) {
+        match self {
+            Self::Wakeup(task) => {
+                task.set_timer_cpu_id(None);
+                crate::run_queue::unblock_task(task, true);
+            }
+            Self::Callback(timer, deadline) => {
+                if timer.interval.is_none() {
+                    // A one-shot timer is no longer active once it fires.
+                    if !timer.active.swap(false, Ordering::AcqRel) {
+                        return;
+                    }
+                } else if !timer.active.load(Ordering::Acquire) {
+                    return;
+                }
+                (timer.callback.lock())(now);
 
-impl TimerEvent for TaskWakeupEvent {
-    fn callback(self, _now: TimeValue) {
-        let mut rq = RUN_QUEUE.lock();
-        self.0.set_in_timer_list(false);
-        rq.unblock_task(self.0, true);
+                if let Some(interval) = timer.interval {
+                    // Skip the periods that have been missed.
+                    let mut next = deadline + interval;
+                    if next <= now {
+                        next = now + interval;
+                    }
+                    let mut timers = CPU_TIMERS[timer.cpu_id].lock();
+                    if timer.active.load(Ordering::Acquire) {
+                        timers.add_event(next, Self::Callback(timer.clone(), next));
+                    }
+                }
+            }
+        }
     }
 }
 
+fn wall_to_monotonic_nanos(time: TimeValue) -> u64 {
+    (time.as_nanos() as u64).saturating_sub(epochoffset_nanos())
+}
+
+/// Runs `f` on the timer list of the current CPU.
+fn with_current_timers<R>(f: impl FnOnce(usize, &mut CpuTimers) -> R) -> R {
+    // Disable preemption before reading the CPU ID, so that the timer
+    // interrupt of the same CPU is reprogrammed.
+    let _guard = NoPreemptIrqSave::new();
+    let cpu_id = axhal::cpu::this_cpu_id();
+    f(cpu_id, &mut CPU_TIMERS[cpu_id].lock())
+}
+
+fn new_timer(
+    deadline: TimeValue,
+    interval: Option<Duration>,
+    callback: Box<dyn FnMut(TimeValue) + Send>,
+) -> TimerHandle {
+    with_current_timers(|cpu_id, timers| {
+        let timer = Arc::new(TimerInner {
+            cpu_id,
+            interval,
+            active: AtomicBool::new(true),
+            callback: SpinNoIrq::new(callback),
+        });
+        timers.add_event(deadline, AxTimerEvent::Callback(timer.clone(), deadline));
+        TimerHandle(timer)
+    })
+}
+
+/// Sets a one-shot timer, which calls `callback` with the current time once
+/// `deadline` is reached.
+///
+/// The callback runs in the timer interrupt of the current CPU, so it must
+/// not block.
+pub fn set_timer<F>(deadline: TimeValue, callback: F) -> TimerHandle
+where
+    F: FnOnce(TimeValue) + Send + 'static,
+{
+    let mut callback = Some(callback);
+    new_timer(
+        deadline,
+        None,
+        Box::new(move |now| {
+            if let Some(f) = callback.take() {
+                f(now)
+            }
+        }),
+    )
+}
+
+/// Sets a periodic timer, which calls `callback` with the current time at
+/// `deadline`, and every `interval` after that until it is cancelled.
+///
+/// The callback runs in the timer interrupt of the current CPU, so it must
+/// not block. If the callback is delayed for more than `interval`, the
+/// missed periods are skipped.
+///
+/// # Panics
+///
+/// Panics if `interval` is zero.
+pub fn set_periodic_timer<F>(deadline: TimeValue, interval: Duration, callback: F) -> TimerHandle
+where
+    F: FnMut(TimeValue) + Send + 'static,
+{
+    assert!(!interval.is_zero(), "the interval of a timer must not be zero");
+    new_timer(deadline, Some(interval), Box::new(callback))
+}
+
 pub fn set_alarm_wakeup(deadline: TimeValue, task: AxTaskRef) {
-    let mut timers = TIMER_LIST.lock();
-    task.set_in_timer_list(true);
-    timers.set(deadline, TaskWakeupEvent(task));
+    with_current_timers(|cpu_id, timers| {
+        task.set_timer_cpu_id(Some(cpu_id));
+        timers.add_event(deadline, AxTimerEvent::Wakeup(task));
+    })
 }
 
 pub fn cancel_alarm(task: &AxTaskRef) {
-    let mut timers = TIMER_LIST.lock();
-    task.set_in_timer_list(false);
-    timers.cancel(|t| Arc::ptr_eq(&t.0, task));
+    // The task itself cancels its alarm, so it cannot be set again meanwhile.
+    if let Some(cpu_id) = task.timer_cpu_id() {
+        let mut timers = CPU_TIMERS[cpu_id].lock();
+        task.set_timer_cpu_id(None);
+        timers
+            .events
+            .cancel(|e| matches!(e, AxTimerEvent::Wakeup(t) if Arc::ptr_eq(t, task)));
+    }
+}
+
+/// Stops the periodic tick of the current CPU, which is going to be idle, so
+/// that its timer interrupt is only programmed for the next event. IRQs must
+/// be disabled.
+#[cfg(feature = "tickless")]
+pub fn stop_tick() {
+    let cpu_id = axhal::cpu::this_cpu_id();
+    let mut timers = CPU_TIMERS[cpu_id].lock();
+    TICK_STOPPED[cpu_id].store(true, Ordering::Release);
+    timers.next_tick_ns = idle_tick_ns(monotonic_time_nanos());
+    timers.arm_next();
+}
+
+/// Restarts the periodic tick of the current CPU if it is stopped. IRQs must
+/// be disabled.
+#[cfg(feature = "tickless")]
+pub fn restart_tick() {
+    let cpu_id = axhal::cpu::this_cpu_id();
+    if TICK_STOPPED[cpu_id].swap(false, Ordering::AcqRel) {
+        let mut timers = CPU_TIMERS[cpu_id].lock();
+        timers.next_tick_ns = monotonic_time_nanos() + TICK_INTERVAL_NANOS;
+        timers.arm_next();
+    }
+}
+
+/// Returns whether the periodic tick of the given CPU is stopped.
+#[cfg(feature = "tickless")]
+pub fn tick_stopped(cpu_id: usize) -> bool {
+    TICK_STOPPED[cpu_id].load(Ordering::Acquire)
+}
+
+/// Returns the monotonic time that the timer interrupt of the current CPU is
+/// programmed for, in nanoseconds.
+#[cfg(test)]
+pub fn armed_ns() -> u64 {
+    CPU_TIMERS[axhal::cpu::this_cpu_id()].lock().armed_ns
 }
 
-pub fn check_events() {
+/// Runs the expired events of the current CPU, and programs its next timer
+/// interrupt. IRQs must be disabled.
+///
+/// Returns whether a periodic tick is due. Ticks are still due now and then
+/// when the tick is stopped with multiple CPUs.
+pub fn check_events() -> bool {
+    let cpu_id = axhal::cpu::this_cpu_id();
+    let timers = &CPU_TIMERS[cpu_id];
     loop {
         let now = wall_time();
-        let event = TIMER_LIST.lock().expire_one(now);
+        // Do not run the event with the list locked, it may set new timers.
+        let event = timers.lock().events.expire_one(now);
         if let Some((_deadline, event)) = event {
             event.callback(now);
         } else {
             break;
         }
     }
+
+    let mut timers = timers.lock();
+    let now_ns = monotonic_time_nanos();
+    let tick = now_ns >= timers.next_tick_ns;
+    #[cfg(feature = "tickless")]
+    if tick && tick_stopped(cpu_id) {
+        timers.next_tick_ns = idle_tick_ns(now_ns);
+        timers.arm_next();
+        return true;
+    }
+    if tick {
+        timers.next_tick_ns = if now_ns >= timers.next_tick_ns + TICK_INTERVAL_NANOS {
+            // Skip the missed ticks.
+            now_ns + TICK_INTERVAL_NANOS
+        } else {
+            timers.next_tick_ns + TICK_INTERVAL_NANOS
+        };
+    }
+    timers.arm_next();
+    tick
 }
 
+/// Initializes the timer list of the current CPU.
 pub fn init() {
-    TIMER_LIST.init_once(SpinNoIrq::new(TimerList::new()));
+    CPU_TIMERS[axhal::cpu::this_cpu_id()].init_once(SpinNoIrq::new(CpuTimers::new()));
 }
@@ -1,8 +1,12 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
-use kspin::SpinRaw;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+use core::task::Waker;
+use kspin::SpinNoIrq;
 
-use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
+use crate::notify::{abort_block_if_interrupted, Interrupted};
+use crate::run_queue::unblock_task;
+use crate::{current_run_queue, AxTaskRef, CurrentTask};
 
 /// A queue to store sleeping tasks.
 ///
@@ -27,21 +31,52 @@ use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
 /// assert_eq!(VALUE.load(Ordering::Relaxed), 1);
 /// ```
 pub struct WaitQueue {
-    queue: SpinRaw<VecDeque<AxTaskRef>>, // we already disabled IRQs when lock the `RUN_QUEUE`
+    queue: SpinNoIrq<VecDeque<AxTaskRef>>,
+    /// The wakers of the futures waiting, see [`wait_until_async`].
+    ///
+    /// [`wait_until_async`]: WaitQueue::wait_until_async
+    wakers: SpinNoIrq<Wakers>,
+    /// The number of wakers, so that notifications do not lock them if there
+    /// is none.
+    nr_wakers: AtomicUsize,
+}
+
+/// The wakers of the futures waiting in a [`WaitQueue`].
+struct Wakers {
+    /// Each future has at most one waker, identified by a token.
+    list: VecDeque<(u64, Waker)>,
+    next_token: u64,
+}
+
+impl Wakers {
+    const fn new() -> Self {
+        Self {
+            list: VecDeque::new(),
+            next_token: 0,
+        }
+    }
+
+    fn position(&self, token: u64) -> Option<usize> {
+        self.list.iter().position(|(t, _)| *t == token)
+    }
 }
 
 impl WaitQueue {
     /// Creates an empty wait queue.
     pub const fn new() -> Self {
         Self {
-            queue: SpinRaw::new(VecDeque::new()),
+            queue: SpinNoIrq::new(VecDeque::new()),
+            wakers: SpinNoIrq::new(Wakers::new()),
+            nr_wakers: AtomicUsize::new(0),
         }
     }
 
     /// Creates an empty wait queue with space for at least `capacity` elements.
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            queue: SpinRaw::new(VecDeque::with_capacity(capacity)),
+            queue: SpinNoIrq::new(VecDeque::with_capacity(capacity)),
+            wakers: SpinNoIrq::new(Wakers::new()),
+            nr_wakers: AtomicUsize::new(0),
         }
     }
 
@@ -50,8 +85,6 @@ impl WaitQueue {
         // the event from another queue.
         if curr.in_wait_queue() {
             // wake up by timer (timeout).
-            // `RUN_QUEUE` is not locked here, so disable IRQs.
-            let _guard = kernel_guard::IrqSave::new();
             self.queue.lock().retain(|t| !curr.ptr_eq(t));
             curr.set_in_wait_queue(false);
         }
@@ -62,10 +95,25 @@ impl WaitQueue {
         }
     }
 
+    /// Takes the first task with the highest real-time priority, which is
+    /// the first task if there are only normal tasks.
+    fn pop_next(&self) -> Option<AxTaskRef> {
+        let mut wq = self.queue.lock();
+        let (idx, _) = wq
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, t)| t.rt().effective_priority())?;
+        let task = wq.remove(idx)?;
+        drop(wq);
+        task.set_in_wait_queue(false);
+        Some(task)
+    }
+
     /// Blocks the current task and put it into the wait queue, until other task
     /// notifies it.
     pub fn wait(&self) {
-        RUN_QUEUE.lock().block_current(|task| {
+        current_run_queue().block_current(|task| {
             task.set_in_wait_queue(true);
             self.queue.lock().push_back(task)
         });
@@ -82,18 +130,80 @@ impl WaitQueue {
         F: Fn() -> bool,
     {
         loop {
-            let mut rq = RUN_QUEUE.lock();
+            let rq = current_run_queue();
+            // Keep the wait queue locked until the task is in it, so that
+            // notifications after checking the condition will not be missed.
+            let mut wq = self.queue.lock();
             if condition() {
                 break;
             }
-            rq.block_current(|task| {
+            rq.block_current(move |task| {
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                wq.push_back(task);
             });
         }
         self.cancel_events(crate::current());
     }
 
+    /// Blocks the current task and put it into the wait queue, until other task
+    /// notifies it, or it is interrupted.
+    ///
+    /// Returns [`Interrupted`] if the current task is cancelled or has unmasked
+    /// notifications pending, before or while it waits.
+    pub fn wait_interruptible(&self) -> Result<(), Interrupted> {
+        let curr = crate::current();
+        if curr.notify_state().is_interrupted() {
+            return Err(Interrupted);
+        }
+        curr.notify_state().set_interruptible(true);
+        current_run_queue().block_current(|task| {
+            task.set_in_wait_queue(true);
+            self.queue.lock().push_back(task.clone());
+            abort_block_if_interrupted(&task);
+        });
+        curr.notify_state().set_interruptible(false);
+        let interrupted = curr.in_wait_queue(); // still in the wait queue, not notified
+        self.cancel_events(curr);
+        if interrupted {
+            Err(Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Blocks the current task and put it into the wait queue, until the given
+    /// `condition` becomes true, or it is interrupted.
+    ///
+    /// Returns [`Interrupted`] if the current task is cancelled or has unmasked
+    /// notifications pending, before or while it waits. The condition is
+    /// checked first, so it returns `Ok` if both happen.
+    pub fn wait_until_interruptible<F>(&self, condition: F) -> Result<(), Interrupted>
+    where
+        F: Fn() -> bool,
+    {
+        let curr = crate::current();
+        curr.notify_state().set_interruptible(true);
+        let res = loop {
+            let rq = current_run_queue();
+            let mut wq = self.queue.lock();
+            if condition() {
+                break Ok(());
+            }
+            if curr.notify_state().is_interrupted() {
+                break Err(Interrupted);
+            }
+            rq.block_current(move |task| {
+                task.set_in_wait_queue(true);
+                wq.push_back(task.clone());
+                drop(wq);
+                abort_block_if_interrupted(&task);
+            });
+        };
+        curr.notify_state().set_interruptible(false);
+        self.cancel_events(curr);
+        res
+    }
+
     /// Blocks the current task and put it into the wait queue, until other tasks
     /// notify it, or the given duration has elapsed.
     #[cfg(feature = "irq")]
@@ -105,11 +215,13 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        RUN_QUEUE.lock().block_current(|task| {
+        current_run_queue().block_current(|task| {
             task.set_in_wait_queue(true);
-            self.queue.lock().push_back(task)
+            self.queue.lock().push_back(task.clone());
+            // the alarm may go off on another CPU at once, so set it after
+            // the task is blocked.
+            crate::timers::set_alarm_wakeup(deadline, task);
         });
         let timeout = curr.in_wait_queue(); // still in the wait queue, must have timed out
         self.cancel_events(curr);
@@ -133,51 +245,185 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
         let mut timeout = true;
         while axhal::time::wall_time() < deadline {
-            let mut rq = RUN_QUEUE.lock();
+            let rq = current_run_queue();
+            let mut wq = self.queue.lock();
             if condition() {
                 timeout = false;
                 break;
             }
-            rq.block_current(|task| {
+            rq.block_current(move |task| {
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                wq.push_back(task.clone());
+                drop(wq);
+                if !task.in_timer_list() {
+                    crate::timers::set_alarm_wakeup(deadline, task);
+                }
             });
         }
         self.cancel_events(curr);
         timeout
     }
 
-    /// Wakes up one task in the wait queue, usually the first one.
+    /// Blocks the current task and put it into the wait queue, until other tasks
+    /// notify it, the given duration has elapsed, or it is interrupted.
+    ///
+    /// Returns whether it has timed out, or [`Interrupted`] if the current task
+    /// is cancelled or has unmasked notifications pending, before or while it
+    /// waits.
+    #[cfg(feature = "irq")]
+    pub fn wait_timeout_interruptible(
+        &self,
+        dur: core::time::Duration,
+    ) -> Result<bool, Interrupted> {
+        let curr = crate::current();
+        if curr.notify_state().is_interrupted() {
+            return Err(Interrupted);
+        }
+        let deadline = axhal::time::wall_time() + dur;
+        debug!(
+            "task wait_timeout_interruptible: {} deadline={:?}",
+            curr.id_name(),
+            deadline
+        );
+
+        curr.notify_state().set_interruptible(true);
+        current_run_queue().block_current(|task| {
+            task.set_in_wait_queue(true);
+            self.queue.lock().push_back(task.clone());
+            crate::timers::set_alarm_wakeup(deadline, task.clone());
+            abort_block_if_interrupted(&task);
+        });
+        curr.notify_state().set_interruptible(false);
+        // still in the wait queue, must have timed out or been interrupted
+        let res = if !curr.in_wait_queue() {
+            Ok(false)
+        } else if curr.notify_state().is_interrupted() {
+            Err(Interrupted)
+        } else {
+            Ok(true)
+        };
+        self.cancel_events(curr);
+        res
+    }
+
+    /// Returns a future that waits until the given `condition` becomes true,
+    /// it is the async version of [`wait_until`](WaitQueue::wait_until).
+    ///
+    /// The future is woken up by the notifications, after the tasks in the
+    /// wait queue. Its waker is removed once it completes or is dropped, but
+    /// if it is dropped after [`notify_one`](WaitQueue::notify_one) wakes it
+    /// up, that notification is lost.
+    pub fn wait_until_async<F>(&self, condition: F) -> crate::future::WaitUntil<'_, F>
+    where
+        F: Fn() -> bool,
+    {
+        crate::future::WaitUntil::new(self, condition)
+    }
+
+    /// Registers the waker of a future, unless the given `condition` is
+    /// already true, which is checked with the wakers locked.
+    ///
+    /// A future has at most one waker registered, identified by `token`. It
+    /// is set when the waker is added, and cleared when the condition is true.
+    /// The future must call [`unregister_waker`](Self::unregister_waker) with
+    /// it when it is dropped.
+    pub(crate) fn register_waker(
+        &self,
+        token: &mut Option<u64>,
+        waker: &Waker,
+        condition: impl Fn() -> bool,
+    ) -> bool {
+        let mut wakers = self.wakers.lock();
+        // Count the waker in before checking the condition, see `has_wakers`.
+        self.nr_wakers.store(wakers.list.len() + 1, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        let pos = token.and_then(|t| wakers.position(t));
+        let ready = condition();
+        if ready {
+            if let Some(i) = pos {
+                wakers.list.remove(i);
+            }
+            *token = None;
+        } else if let Some(i) = pos {
+            if !wakers.list[i].1.will_wake(waker) {
+                wakers.list[i].1 = waker.clone();
+            }
+        } else {
+            // It has not registered, or has been woken up.
+            let t = wakers.next_token;
+            wakers.next_token += 1;
+            wakers.list.push_back((t, waker.clone()));
+            *token = Some(t);
+        }
+        self.nr_wakers.store(wakers.list.len(), Ordering::Relaxed);
+        !ready
+    }
+
+    /// Removes the waker registered with `token`, if it has not been woken up.
+    pub(crate) fn unregister_waker(&self, token: u64) {
+        if self.nr_wakers.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let mut wakers = self.wakers.lock();
+        if let Some(i) = wakers.position(token) {
+            wakers.list.remove(i);
+            self.nr_wakers.store(wakers.list.len(), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns whether there may be futures to wake up.
+    fn has_wakers(&self) -> bool {
+        // Pairs with `register_waker`: either it sees the condition that the
+        // notifier has changed, or the waker is counted here.
+        fence(Ordering::SeqCst);
+        self.nr_wakers.load(Ordering::Relaxed) != 0
+    }
+
+    /// Wakes up one task in the wait queue, usually the first one. Real-time
+    /// tasks are woken up before normal tasks, and higher priorities first.
+    /// If there is no task, the first future waiting is woken up instead.
     ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_one(&self, resched: bool) -> bool {
-        let mut rq = RUN_QUEUE.lock();
-        if !self.queue.lock().is_empty() {
-            self.notify_one_locked(resched, &mut rq)
-        } else {
-            false
+        if let Some(task) = self.pop_next() {
+            unblock_task(task, resched);
+            return true;
+        }
+        if !self.has_wakers() {
+            return false;
         }
+        // Do not wake it up with the wakers locked.
+        let waker = {
+            let mut wakers = self.wakers.lock();
+            let waker = wakers.list.pop_front();
+            self.nr_wakers.store(wakers.list.len(), Ordering::Relaxed);
+            waker
+        };
+        waker.map(|(_, waker)| waker.wake()).is_some()
     }
 
-    /// Wakes all tasks in the wait queue.
+    /// Wakes all tasks and futures in the wait queue.
     ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_all(&self, resched: bool) {
-        loop {
-            let mut rq = RUN_QUEUE.lock();
-            if let Some(task) = self.queue.lock().pop_front() {
-                task.set_in_wait_queue(false);
-                rq.unblock_task(task, resched);
-            } else {
-                break;
-            }
-            drop(rq); // we must unlock `RUN_QUEUE` after unlocking `self.queue`.
+        while let Some(task) = self.pop_next() {
+            unblock_task(task, resched);
+        }
+        if !self.has_wakers() {
+            return;
+        }
+        let wakers = {
+            let mut wakers = self.wakers.lock();
+            self.nr_wakers.store(0, Ordering::Relaxed);
+            core::mem::take(&mut wakers.list)
+        };
+        for (_, waker) in wakers {
+            waker.wake();
         }
     }
 
@@ -186,31 +432,17 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_task(&mut self, resched: bool, task: &AxTaskRef) -> bool {
-        let mut rq = RUN_QUEUE.lock();
-        let mut wq = self.queue.lock();
-        if let Some(index) = wq.iter().position(|t| Arc::ptr_eq(t, task)) {
+        let task = {
+            let mut wq = self.queue.lock();
+            let index = wq.iter().position(|t| Arc::ptr_eq(t, task));
+            index.and_then(|i| wq.remove(i))
+        };
+        if let Some(task) = task {
             task.set_in_wait_queue(false);
-            rq.unblock_task(wq.remove(index).unwrap(), resched);
+            unblock_task(task, resched);
             true
         } else {
             false
         }
     }
-
-    pub(crate) fn notify_one_locked(&self, resched: bool, rq: &mut AxRunQueue) -> bool {
-        if let Some(task) = self.queue.lock().pop_front() {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(task, resched);
-            true
-        } else {
-            false
-        }
-    }
-
-    pub(crate) fn notify_all_locked(&self, resched: bool, rq: &mut AxRunQueue) {
-        while let Some(task) = self.queue.lock().pop_front() {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(task, resched);
-        }
-    }
 }
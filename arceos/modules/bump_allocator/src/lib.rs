@@ -1,6 +1,8 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
-use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
+use core::alloc::Layout;
+use core::ptr::NonNull;
 
 /// Early memory allocator
 /// Use it before formal bytes-allocator and pages-allocator can work!
@@ -16,12 +18,15 @@ use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
 /// When it goes down to ZERO, free bytes-used area.
 /// For pages area, it will never be freed!
 ///
+/// Once the formal allocators are ready, the avail-area can be handed over
+/// to them by [`EarlyAllocator::take_free_region`].
 pub struct EarlyAllocator<const PAGE_SIZE: usize> {
     base: usize,
     size: usize,
     b_pos: usize,
     p_pos: usize,
     b_count: usize,
+    released: usize,
 }
 
 impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
@@ -32,8 +37,35 @@ impl<const PAGE_SIZE: usize> EarlyAllocator<PAGE_SIZE> {
             b_pos: 0,
             p_pos: 0,
             b_count: 0,
+            released: 0,
         }
     }
+
+    const fn end(&self) -> usize {
+        self.base + self.size
+    }
+
+    /// Takes the page-aligned part of the avail-area out of this allocator,
+    /// and returns it as `(start, size)`.
+    ///
+    /// After that, the allocator only serves from the memory left below the
+    /// returned region. Returns `None` if no whole page is available.
+    pub fn take_free_region(&mut self) -> Option<(usize, usize)> {
+        let start = align_up(self.b_pos, PAGE_SIZE);
+        let end = align_down(self.p_pos, PAGE_SIZE);
+        if start >= end {
+            return None;
+        }
+        self.released += end - start;
+        self.p_pos = start;
+        Some((start, end - start))
+    }
+}
+
+impl<const PAGE_SIZE: usize> Default for EarlyAllocator<PAGE_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
@@ -43,29 +75,31 @@ impl<const PAGE_SIZE: usize> BaseAllocator for EarlyAllocator<PAGE_SIZE> {
         self.b_pos = start;
         self.p_pos = start + size;
         self.b_count = 0;
+        self.released = 0;
     }
 
-    fn add_memory(&mut self, start: usize, size: usize) -> allocator::AllocResult {
+    fn add_memory(&mut self, _start: usize, _size: usize) -> AllocResult {
         Ok(())
     }
 }
 
 impl<const PAGE_SIZE: usize> ByteAllocator for EarlyAllocator<PAGE_SIZE> {
-    fn alloc(&mut self, layout: core::alloc::Layout) -> allocator::AllocResult<core::ptr::NonNull<u8>> {
-        let size = layout.size();
-        let pos = self.b_pos + size;
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let addr = align_up(self.b_pos, layout.align());
+        let pos = addr
+            .checked_add(layout.size())
+            .ok_or(AllocError::NoMemory)?;
         if pos > self.p_pos {
-            return Err(allocator::AllocError::NoMemory);
+            return Err(AllocError::NoMemory);
         }
-        let addr = self.b_pos;
         self.b_pos = pos;
         self.b_count += 1;
-        unsafe { Ok(core::ptr::NonNull::new_unchecked(addr as *mut u8)) }
+        NonNull::new(addr as *mut u8).ok_or(AllocError::NoMemory)
     }
 
-    fn dealloc(&mut self, pos: core::ptr::NonNull<u8>, layout: core::alloc::Layout) {
+    fn dealloc(&mut self, pos: NonNull<u8>, _layout: Layout) {
         let pos = pos.as_ptr() as usize;
-        if pos > self.b_pos {
+        if pos < self.base || pos >= self.b_pos || self.b_count == 0 {
             return;
         }
         self.b_count -= 1;
@@ -75,43 +109,142 @@ impl<const PAGE_SIZE: usize> ByteAllocator for EarlyAllocator<PAGE_SIZE> {
     }
 
     fn total_bytes(&self) -> usize {
-        0
+        self.size - self.released
     }
 
     fn used_bytes(&self) -> usize {
-        0
+        self.b_pos - self.base
     }
 
     fn available_bytes(&self) -> usize {
-        0
+        self.p_pos - self.b_pos
     }
 }
 
 impl<const PAGE_SIZE: usize> PageAllocator for EarlyAllocator<PAGE_SIZE> {
     const PAGE_SIZE: usize = PAGE_SIZE;
 
-    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> allocator::AllocResult<usize> {
-        let pos = self.p_pos - num_pages * Self::PAGE_SIZE;
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if align_pow2 % Self::PAGE_SIZE != 0 || !align_pow2.is_power_of_two() {
+            return Err(AllocError::InvalidParam);
+        }
+        let size = num_pages
+            .checked_mul(Self::PAGE_SIZE)
+            .ok_or(AllocError::NoMemory)?;
+        let pos = self
+            .p_pos
+            .checked_sub(size)
+            .map(|pos| align_down(pos, align_pow2))
+            .ok_or(AllocError::NoMemory)?;
         if pos < self.b_pos {
-            return Err(allocator::AllocError::NoMemory);
+            return Err(AllocError::NoMemory);
         }
         self.p_pos = pos;
-        return Ok(pos);
+        Ok(pos)
     }
 
-    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
-        ()
+    fn dealloc_pages(&mut self, _pos: usize, _num_pages: usize) {
+        // pages are never freed
     }
 
     fn total_pages(&self) -> usize {
-        0
+        (self.size - self.released) / Self::PAGE_SIZE
     }
 
     fn used_pages(&self) -> usize {
-        0
+        (self.end() - self.p_pos - self.released).div_ceil(Self::PAGE_SIZE)
     }
 
     fn available_pages(&self) -> usize {
-        0
+        let start = align_up(self.b_pos, Self::PAGE_SIZE);
+        let end = align_down(self.p_pos, Self::PAGE_SIZE);
+        end.saturating_sub(start) / Self::PAGE_SIZE
+    }
+}
+
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+const fn align_down(addr: usize, align: usize) -> usize {
+    addr & !(align - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: usize = 0x10_0000;
+    const SIZE: usize = 0x1_0000;
+
+    fn new_allocator() -> EarlyAllocator<0x1000> {
+        let mut early = EarlyAllocator::new();
+        early.init(BASE, SIZE);
+        early
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut early = new_allocator();
+        let a = early.alloc(layout(1, 1)).unwrap();
+        let b = early.alloc(layout(8, 64)).unwrap();
+        assert_eq!(a.as_ptr() as usize, BASE);
+        assert_eq!(b.as_ptr() as usize, BASE + 64);
+        assert_eq!(early.used_bytes(), 72);
+        assert_eq!(early.available_bytes(), SIZE - 72);
+        assert!(early.alloc(layout(SIZE, 1)).is_err());
+
+        // The bytes area is freed only when all allocations are freed.
+        early.dealloc(b, layout(8, 64));
+        assert_eq!(early.used_bytes(), 72);
+        early.dealloc(a, layout(1, 1));
+        assert_eq!(early.used_bytes(), 0);
+        assert_eq!(early.available_bytes(), SIZE);
+    }
+
+    #[test]
+    fn test_pages() {
+        let mut early = new_allocator();
+        assert_eq!(early.total_pages(), 16);
+        assert_eq!(early.alloc_pages(1, 0x1000), Ok(BASE + 0xf000));
+        assert_eq!(early.alloc_pages(1, 0x4000), Ok(BASE + 0xc000));
+        assert_eq!(early.used_pages(), 4);
+        assert_eq!(early.available_pages(), 12);
+        assert_eq!(early.alloc_pages(1, 0x800), Err(AllocError::InvalidParam));
+        assert_eq!(early.alloc_pages(1, 0x3000), Err(AllocError::InvalidParam));
+
+        // Bytes and pages do not overlap.
+        early.alloc(layout(0xb001, 1)).unwrap();
+        assert_eq!(early.available_pages(), 0);
+        assert_eq!(early.alloc_pages(1, 0x1000), Err(AllocError::NoMemory));
+    }
+
+    #[test]
+    fn test_take_free_region() {
+        let mut early = new_allocator();
+        early.alloc(layout(0x10, 8)).unwrap();
+        early.alloc_pages(1, 0x1000).unwrap();
+
+        let (start, size) = early.take_free_region().unwrap();
+        assert_eq!((start, size), (BASE + 0x1000, 0xe000));
+        assert_eq!(early.total_bytes(), SIZE - 0xe000);
+        assert_eq!(early.total_pages(), 2);
+        assert_eq!(early.used_pages(), 1);
+        assert_eq!(early.available_pages(), 0);
+        assert_eq!(early.take_free_region(), None);
+
+        // The region can be handed over to another allocator, and this one
+        // only serves from the memory below it.
+        let mut next = EarlyAllocator::<0x1000>::new();
+        next.init(start, size);
+        assert_eq!(next.alloc_pages(14, 0x1000), Ok(start));
+        assert_eq!(early.alloc_pages(1, 0x1000), Err(AllocError::NoMemory));
+        let ptr = early.alloc(layout(0x100, 8)).unwrap().as_ptr() as usize;
+        assert!(ptr + 0x100 <= start);
+        assert!(early.alloc(layout(0x1000, 8)).is_err());
     }
 }
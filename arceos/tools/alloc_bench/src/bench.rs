@@ -0,0 +1,97 @@
+//! Replays operations on an allocator and collects the statistics.
+
+use std::alloc::Layout;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::subject::Subject;
+use crate::trace::Op;
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Number of successful allocations.
+    pub allocs: usize,
+    /// Number of deallocations.
+    pub frees: usize,
+    /// Number of failed allocations.
+    pub failures: usize,
+    /// Number of finished rounds.
+    pub rounds: usize,
+    /// Maximum of the bytes used by the allocator.
+    pub peak_used: usize,
+    /// Fragmentation at the time of [`peak_used`](Self::peak_used).
+    pub peak_fragmentation: f64,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Allocations and deallocations per second.
+    pub fn throughput(&self) -> f64 {
+        (self.allocs + self.frees) as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Returns the fraction of the used memory that is not requested, i.e.,
+/// wasted by the rounding and metadata of the allocator.
+fn fragmentation(requested: usize, used: usize) -> f64 {
+    if used == 0 {
+        0.0
+    } else {
+        1.0 - requested as f64 / used as f64
+    }
+}
+
+/// Runs `ops` on `subject`.
+///
+/// If `stop_on_failure` is set, it stops at the first allocation failure,
+/// otherwise failed allocations are counted and their frees are skipped.
+pub fn run(
+    subject: &mut dyn Subject,
+    ops: impl IntoIterator<Item = Op>,
+    stop_on_failure: bool,
+) -> Report {
+    let mut report = Report::default();
+    let mut live: HashMap<usize, (usize, Layout)> = HashMap::new();
+    let mut requested = 0;
+
+    let start = Instant::now();
+    for op in ops {
+        match op {
+            Op::Alloc { id, size, align } => {
+                let Ok(layout) = Layout::from_size_align(size, align) else {
+                    report.failures += 1;
+                    continue;
+                };
+                let Some(addr) = subject.alloc(layout) else {
+                    report.failures += 1;
+                    if stop_on_failure {
+                        break;
+                    }
+                    continue;
+                };
+                live.insert(id, (addr, layout));
+                report.allocs += 1;
+                requested += size;
+                let used = subject.used_bytes();
+                if used > report.peak_used {
+                    report.peak_used = used;
+                    report.peak_fragmentation = fragmentation(requested, used);
+                }
+            }
+            Op::Free { id } => {
+                if let Some((addr, layout)) = live.remove(&id) {
+                    subject.dealloc(addr, layout);
+                    report.frees += 1;
+                    requested -= layout.size();
+                }
+            }
+            Op::Round => report.rounds += 1,
+        }
+    }
+    report.elapsed = start.elapsed();
+
+    for (addr, layout) in live.into_values() {
+        subject.dealloc(addr, layout);
+    }
+    report
+}
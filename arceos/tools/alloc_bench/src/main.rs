@@ -0,0 +1,203 @@
+//! Host-side benchmark of the ArceOS allocators.
+//!
+//! See `README.md` for the usage.
+
+mod bench;
+mod subject;
+mod trace;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::process::exit;
+
+use crate::subject::{new_subject, ALLOCATORS};
+use crate::trace::Op;
+
+const DEFAULT_HEAP_SIZE: usize = 32 * 1024 * 1024; // 32M
+
+const USAGE: &str = "\
+usage: alloc_bench [options]
+
+options:
+    --allocator <name>    tlsf, slab, buddy, early, bitmap or all (default: all)
+    --heap-size <bytes>   size of the heap, accepts K/M/G suffixes (default: 32M)
+    --workload lab1       run the lab1 workload until out of memory (default)
+    --trace <file>        replay a kernel log captured with heap tracing";
+
+enum Workload {
+    Lab1,
+    Trace(Vec<Op>),
+}
+
+struct Options {
+    allocators: Vec<&'static str>,
+    heap_size: usize,
+    workload: Workload,
+}
+
+fn parse_size(s: &str) -> Option<usize> {
+    let (num, shift) = match s.as_bytes().last()? {
+        b'K' | b'k' => (&s[..s.len() - 1], 10),
+        b'M' | b'm' => (&s[..s.len() - 1], 20),
+        b'G' | b'g' => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    let num: usize = match num.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+        None => num.parse().ok()?,
+    };
+    num.checked_mul(1 << shift)
+}
+
+/// Parses the command line arguments (without the program name), returns
+/// `None` if the usage is asked for.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut opts = Options {
+        allocators: ALLOCATORS.to_vec(),
+        heap_size: DEFAULT_HEAP_SIZE,
+        workload: Workload::Lab1,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--allocator" => {
+                let name = value()?;
+                if name != "all" {
+                    if !ALLOCATORS.contains(&name.as_str()) {
+                        return Err(format!("unknown allocator: {}", name));
+                    }
+                    opts.allocators = ALLOCATORS.iter().copied().filter(|a| *a == name).collect();
+                }
+            }
+            "--heap-size" => {
+                let size = value()?;
+                opts.heap_size =
+                    parse_size(&size).ok_or_else(|| format!("invalid heap size: {}", size))?;
+            }
+            "--workload" => {
+                let name = value()?;
+                if name != "lab1" {
+                    return Err(format!("unknown workload: {}", name));
+                }
+                opts.workload = Workload::Lab1;
+            }
+            "--trace" => {
+                let path = value()?;
+                let file =
+                    File::open(&path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+                let ops = trace::parse_log(BufReader::new(file))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                opts.workload = Workload::Trace(ops);
+            }
+            "-h" | "--help" => return Ok(None),
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+    Ok(Some(opts))
+}
+
+fn main() {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(opts)) => opts,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(msg) => {
+            eprintln!("alloc_bench: {}\n\n{}", msg, USAGE);
+            exit(1);
+        }
+    };
+
+    println!(
+        "{:<8} {:>8} {:>10} {:>10} {:>10} {:>8} {:>12}",
+        "alloc", "rounds", "allocs", "failures", "peak(KB)", "frag", "ops/s"
+    );
+    for name in opts.allocators {
+        let mut subject = new_subject(name, opts.heap_size).unwrap();
+        let report = match &opts.workload {
+            Workload::Lab1 => bench::run(subject.as_mut(), trace::lab1(), true),
+            Workload::Trace(ops) => bench::run(subject.as_mut(), ops.iter().copied(), false),
+        };
+        println!(
+            "{:<8} {:>8} {:>10} {:>10} {:>10} {:>7.1}% {:>12.0}",
+            name,
+            report.rounds,
+            report.allocs,
+            report.failures,
+            report.peak_used / 1024,
+            report.peak_fragmentation * 100.0,
+            report.throughput(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Option<Options>, String> {
+        parse_args(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let opts = parse("").unwrap().unwrap();
+        assert_eq!(opts.allocators, ALLOCATORS);
+        assert_eq!(opts.heap_size, DEFAULT_HEAP_SIZE);
+        assert!(matches!(opts.workload, Workload::Lab1));
+
+        let opts = parse("--allocator tlsf --heap-size 4M --workload lab1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(opts.allocators, ["tlsf"]);
+        assert_eq!(opts.heap_size, 4 << 20);
+        assert_eq!(parse("--heap-size 0x1000").unwrap().unwrap().heap_size, 0x1000);
+        assert!(parse("--allocator tlsf --help").unwrap().is_none());
+
+        assert!(parse("--allocator foo").is_err());
+        assert!(parse("--heap-size 4X").is_err());
+        assert!(parse("--heap-size").is_err());
+        assert!(parse("--workload foo").is_err());
+        assert!(parse("--trace /nonexistent/heap.log").is_err());
+        assert!(parse("--foo").is_err());
+    }
+
+    #[test]
+    fn test_lab1() {
+        for &name in ALLOCATORS {
+            let mut subject = new_subject(name, 8 << 20).unwrap();
+            let report = bench::run(subject.as_mut(), trace::lab1(), true);
+            assert!(report.rounds > 0, "{}: no round finished", name);
+            assert_eq!(report.failures, 1, "{}", name);
+            assert!(report.allocs > 0 && report.frees > 0, "{}", name);
+            assert!(report.peak_used <= 8 << 20, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let log = "\
+            booting...
+            [heap-trace] a 0x1000 16 8
+            [heap-trace] a 0x2000 4096 4096
+            [heap-trace] f 0x1000
+            [heap-trace] f 0x3000
+            [heap-trace] a 0x1000 32 8
+        ";
+        let ops = trace::parse_log(log.as_bytes()).unwrap();
+        assert_eq!(ops.len(), 4);
+        assert_eq!(ops[2], Op::Free { id: 0 });
+        assert_eq!(ops[3], Op::Alloc { id: 2, size: 32, align: 8 });
+        assert!(trace::parse_log("[heap-trace] x 0x1000".as_bytes()).is_err());
+
+        let mut subject = new_subject("tlsf", 1 << 20).unwrap();
+        let report = bench::run(subject.as_mut(), ops, false);
+        assert_eq!((report.allocs, report.frees, report.failures), (3, 1, 0));
+    }
+}
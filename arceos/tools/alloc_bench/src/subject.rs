@@ -0,0 +1,128 @@
+//! Allocators under test.
+
+use std::alloc::Layout;
+use std::ptr::NonNull;
+
+use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
+use allocator::{BitmapPageAllocator, BuddyByteAllocator, SlabByteAllocator, TlsfByteAllocator};
+use bump_allocator::EarlyAllocator;
+
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// Names accepted by [`new_subject`].
+pub const ALLOCATORS: &[&str] = &["tlsf", "slab", "buddy", "early", "bitmap"];
+
+/// An allocator driven by the benchmark.
+pub trait Subject {
+    fn alloc(&mut self, layout: Layout) -> Option<usize>;
+    fn dealloc(&mut self, addr: usize, layout: Layout);
+    /// Bytes taken from the heap, including the rounding of the allocator.
+    fn used_bytes(&self) -> usize;
+}
+
+/// The memory managed by the allocator under test.
+struct HeapRegion {
+    start: usize,
+    layout: Layout,
+}
+
+impl HeapRegion {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, PAGE_SIZE).expect("invalid heap size");
+        let start = unsafe { std::alloc::alloc(layout) };
+        if start.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        Self {
+            start: start as usize,
+            layout,
+        }
+    }
+}
+
+impl Drop for HeapRegion {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.start as *mut u8, self.layout) };
+    }
+}
+
+/// Drives a [`ByteAllocator`].
+struct ByteSubject<A> {
+    inner: A,
+    _heap: HeapRegion,
+}
+
+impl<A: ByteAllocator> ByteSubject<A> {
+    fn new(mut inner: A, heap_size: usize) -> Self {
+        let heap = HeapRegion::new(heap_size);
+        inner.init(heap.start, heap_size);
+        Self { inner, _heap: heap }
+    }
+}
+
+impl<A: ByteAllocator> Subject for ByteSubject<A> {
+    fn alloc(&mut self, layout: Layout) -> Option<usize> {
+        self.inner
+            .alloc(layout)
+            .ok()
+            .map(|ptr| ptr.as_ptr() as usize)
+    }
+
+    fn dealloc(&mut self, addr: usize, layout: Layout) {
+        let ptr = NonNull::new(addr as *mut u8).unwrap();
+        self.inner.dealloc(ptr, layout);
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.inner.used_bytes()
+    }
+}
+
+/// Drives a [`PageAllocator`], rounding each allocation up to pages.
+struct PageSubject<A> {
+    inner: A,
+    _heap: HeapRegion,
+}
+
+impl<A: PageAllocator> PageSubject<A> {
+    fn new(mut inner: A, heap_size: usize) -> Self {
+        let heap = HeapRegion::new(heap_size);
+        inner.init(heap.start, heap_size);
+        Self { inner, _heap: heap }
+    }
+}
+
+impl<A: PageAllocator> Subject for PageSubject<A> {
+    fn alloc(&mut self, layout: Layout) -> Option<usize> {
+        let num_pages = layout.size().div_ceil(PAGE_SIZE).max(1);
+        let align = layout.align().max(PAGE_SIZE);
+        self.inner.alloc_pages(num_pages, align).ok()
+    }
+
+    fn dealloc(&mut self, addr: usize, layout: Layout) {
+        let num_pages = layout.size().div_ceil(PAGE_SIZE).max(1);
+        self.inner.dealloc_pages(addr, num_pages);
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.inner.used_pages() * PAGE_SIZE
+    }
+}
+
+/// Creates the allocator named `name` over a heap of `heap_size` bytes.
+pub fn new_subject(name: &str, heap_size: usize) -> Option<Box<dyn Subject>> {
+    Some(match name {
+        "tlsf" => Box::new(ByteSubject::new(TlsfByteAllocator::new(), heap_size)),
+        "slab" => Box::new(ByteSubject::new(SlabByteAllocator::new(), heap_size)),
+        "buddy" => Box::new(ByteSubject::new(BuddyByteAllocator::new(), heap_size)),
+        "early" => Box::new(ByteSubject::new(
+            EarlyAllocator::<PAGE_SIZE>::new(),
+            heap_size,
+        )),
+        "bitmap" => Box::new(PageSubject::new(
+            BitmapPageAllocator::<PAGE_SIZE>::new(),
+            heap_size,
+        )),
+        _ => return None,
+    })
+}
@@ -0,0 +1,102 @@
+//! Allocation traces: recorded workloads and traces captured from a kernel.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// The prefix of trace lines printed by `axalloc::set_heap_tracing`.
+const TRACE_PREFIX: &str = "[heap-trace]";
+
+/// An operation in a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Allocates `size` bytes aligned to `align`, identified by `id`.
+    Alloc { id: usize, size: usize, align: usize },
+    /// Frees the allocation identified by `id`.
+    Free { id: usize },
+    /// Marks the end of a round of the workload.
+    Round,
+}
+
+/// Parses a console log of a kernel with heap tracing switched on.
+///
+/// Lines without the `[heap-trace]` prefix are ignored, so that the whole
+/// log of QEMU can be used directly. Allocations are identified by their
+/// addresses in the kernel, which are renumbered since an address may be
+/// reused after it is freed.
+pub fn parse_log(reader: impl BufRead) -> Result<Vec<Op>, String> {
+    let mut ops = Vec::new();
+    let mut live = HashMap::new();
+    let mut next_id = 0;
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let Some(pos) = line.find(TRACE_PREFIX) else {
+            continue;
+        };
+        let err = || format!("line {}: invalid trace: {}", lineno + 1, line);
+        let mut fields = line[pos + TRACE_PREFIX.len()..].split_whitespace();
+        let kind = fields.next().ok_or_else(err)?;
+        let addr = fields.next().and_then(parse_num).ok_or_else(err)?;
+        match kind {
+            "a" => {
+                let size = fields.next().and_then(parse_num).ok_or_else(err)?;
+                let align = fields.next().and_then(parse_num).ok_or_else(err)?;
+                live.insert(addr, next_id);
+                ops.push(Op::Alloc {
+                    id: next_id,
+                    size,
+                    align,
+                });
+                next_id += 1;
+            }
+            "f" => {
+                // Memory allocated before tracing started is not known.
+                if let Some(id) = live.remove(&addr) {
+                    ops.push(Op::Free { id });
+                }
+            }
+            _ => return Err(err()),
+        }
+    }
+    Ok(ops)
+}
+
+fn parse_num(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// The workload of the `lab1` application of the allocator challenge.
+///
+/// In round `n`, it allocates a group of blocks of `32 + n`, `64 + n`, ...,
+/// `512K + n` bytes, then frees every other block of the group and keeps the
+/// rest. It never ends, the number of rounds finished before running out of
+/// memory is the indicator of the challenge.
+pub fn lab1() -> impl Iterator<Item = Op> {
+    const MIN_BLOCK_SIZE: usize = 32;
+    const MAX_BLOCK_SIZE: usize = 512 * 1024;
+
+    let mut next_id = 0;
+    (0..).flat_map(move |round| {
+        let first_id = next_id;
+        let allocs = std::iter::successors(Some(MIN_BLOCK_SIZE), |&size| {
+            (size < MAX_BLOCK_SIZE).then_some(size * 2)
+        })
+        .map(|base| base + round)
+        .enumerate()
+        .map(move |(i, size)| Op::Alloc {
+            id: first_id + i,
+            size,
+            align: 8,
+        })
+        .collect::<Vec<_>>();
+        next_id += allocs.len();
+        let frees = (0..allocs.len())
+            .rev()
+            .filter(|i| i % 2 == 0)
+            .map(move |i| Op::Free { id: first_id + i })
+            .collect::<Vec<_>>();
+        allocs.into_iter().chain(frees).chain([Op::Round])
+    })
+}
@@ -13,6 +13,7 @@ const SYS_WRITEV: usize = 66;
 const SYS_EXIT: usize = 93;
 const SYS_EXIT_GROUP: usize = 94;
 const SYS_SET_TID_ADDRESS: usize = 96;
+const SYS_FUTEX: usize = 98;
 
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
@@ -20,6 +21,14 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
     let ret = match syscall_num {
          SYS_IOCTL => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
         SYS_SET_TID_ADDRESS => sys_set_tid_address(tf.arg0() as _),
+        SYS_FUTEX => sys_futex(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+            tf.arg5() as _,
+        ),
         SYS_WRITEV => sys_writev(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         SYS_EXIT_GROUP => {
             ax_println!("[SYS_EXIT_GROUP]: system is exiting ..");
@@ -47,6 +56,20 @@ pub(crate) fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
     curr.id().as_u64() as isize
 }
 
+/// Futexes are keyed by their user addresses only, which is fine as there is
+/// a single user address space here. With several processes, the futexes of
+/// different address spaces at the same address would wake up each other.
+fn sys_futex(
+    uaddr: *mut u32,
+    op: i32,
+    val: u32,
+    timeout: *const api::ctypes::timespec,
+    uaddr2: *mut u32,
+    val3: u32,
+) -> isize {
+    unsafe { api::sys_futex(uaddr, op, val, timeout, uaddr2, val3) as isize }
+}
+
 fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> i32 {
     ax_println!("Unimplemented syscall: SYS_IOCTL");
     0
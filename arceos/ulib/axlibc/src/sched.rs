@@ -0,0 +1,78 @@
+use crate::{ctypes, utils::e};
+use arceos_posix_api::{sys_sched_getaffinity, sys_sched_setaffinity};
+use arceos_posix_api::{sys_sched_get_priority_max, sys_sched_get_priority_min};
+use arceos_posix_api::{sys_sched_getparam, sys_sched_getscheduler};
+use arceos_posix_api::{sys_sched_setparam, sys_sched_setscheduler};
+use core::ffi::c_int;
+
+/// Sets the CPUs that the thread `pid` (or the current thread if `pid` is 0)
+/// is allowed to run on.
+#[no_mangle]
+pub unsafe extern "C" fn sched_setaffinity(
+    pid: ctypes::pid_t,
+    cpusetsize: usize,
+    cpuset: *const ctypes::cpu_set_t,
+) -> c_int {
+    e(sys_sched_setaffinity(pid, cpusetsize, cpuset))
+}
+
+/// Gets the CPUs that the thread `pid` (or the current thread if `pid` is 0)
+/// is allowed to run on.
+#[no_mangle]
+pub unsafe extern "C" fn sched_getaffinity(
+    pid: ctypes::pid_t,
+    cpusetsize: usize,
+    cpuset: *mut ctypes::cpu_set_t,
+) -> c_int {
+    e(sys_sched_getaffinity(pid, cpusetsize, cpuset))
+}
+
+/// Sets the scheduling policy and the priority of the thread `pid` (or the
+/// current thread if `pid` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn sched_setscheduler(
+    pid: ctypes::pid_t,
+    policy: c_int,
+    param: *const ctypes::sched_param,
+) -> c_int {
+    e(sys_sched_setscheduler(pid, policy, param))
+}
+
+/// Gets the scheduling policy of the thread `pid` (or the current thread if
+/// `pid` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn sched_getscheduler(pid: ctypes::pid_t) -> c_int {
+    e(sys_sched_getscheduler(pid))
+}
+
+/// Sets the priority of the thread `pid` (or the current thread if `pid` is
+/// 0).
+#[no_mangle]
+pub unsafe extern "C" fn sched_setparam(
+    pid: ctypes::pid_t,
+    param: *const ctypes::sched_param,
+) -> c_int {
+    e(sys_sched_setparam(pid, param))
+}
+
+/// Gets the priority of the thread `pid` (or the current thread if `pid` is
+/// 0).
+#[no_mangle]
+pub unsafe extern "C" fn sched_getparam(
+    pid: ctypes::pid_t,
+    param: *mut ctypes::sched_param,
+) -> c_int {
+    e(sys_sched_getparam(pid, param))
+}
+
+/// Returns the highest priority of the scheduling policy.
+#[no_mangle]
+pub unsafe extern "C" fn sched_get_priority_max(policy: c_int) -> c_int {
+    e(sys_sched_get_priority_max(policy))
+}
+
+/// Returns the lowest priority of the scheduling policy.
+#[no_mangle]
+pub unsafe extern "C" fn sched_get_priority_min(policy: c_int) -> c_int {
+    e(sys_sched_get_priority_min(policy))
+}
@@ -162,6 +162,16 @@ impl File {
     pub fn metadata(&self) -> Result<Metadata> {
         api::ax_file_attr(&self.inner).map(Metadata)
     }
+
+    /// Reads some bytes into `buf` like [`Read::read`], but in chunks, and
+    /// yields to other futures between them.
+    ///
+    /// The future can be run on the executor of `axtask::future`, see
+    /// [`arceos::modules`](crate::os::arceos::modules).
+    #[cfg(feature = "multitask")]
+    pub async fn read_async(&mut self, buf: &mut [u8]) -> Result<usize> {
+        api::ax_read_file_async(&mut self.inner, buf).await
+    }
 }
 
 impl Read for File {
@@ -43,6 +43,21 @@ impl TcpStream {
     pub fn shutdown(&self) -> io::Result<()> {
         api::ax_tcp_shutdown(&self.0)
     }
+
+    /// Reads some bytes like [`Read::read`], but waits asynchronously.
+    ///
+    /// The future can be run on the executor of `axtask::future`, see
+    /// [`arceos::modules`](crate::os::arceos::modules).
+    #[cfg(feature = "multitask")]
+    pub async fn read_async(&self, buf: &mut [u8]) -> io::Result<usize> {
+        api::ax_tcp_recv_async(&self.0, buf).await
+    }
+
+    /// Writes some bytes like [`Write::write`], but waits asynchronously.
+    #[cfg(feature = "multitask")]
+    pub async fn write_async(&self, buf: &[u8]) -> io::Result<usize> {
+        api::ax_tcp_send_async(&self.0, buf).await
+    }
 }
 
 impl Read for TcpStream {
@@ -102,4 +117,13 @@ impl TcpListener {
     pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
         api::ax_tcp_accept(&self.0).map(|(a, b)| (TcpStream(a), b))
     }
+
+    /// Accepts a new incoming connection like [`accept`](Self::accept), but
+    /// waits asynchronously instead of blocking the calling thread.
+    #[cfg(feature = "multitask")]
+    pub async fn accept_async(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        api::ax_tcp_accept_async(&self.0)
+            .await
+            .map(|(a, b)| (TcpStream(a), b))
+    }
 }
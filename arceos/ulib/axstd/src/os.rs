@@ -5,4 +5,43 @@ pub mod arceos {
     pub use arceos_api as api;
     #[doc(no_inline)]
     pub use arceos_api::modules;
+
+    /// Heap profiling and debugging.
+    #[cfg(feature = "alloc")]
+    pub mod heap {
+        /// Switches heap profiling on or off at runtime.
+        ///
+        /// It is off by default. While it is on, allocations are recorded by
+        /// their call sites, which are reported by [`dump_heap_profile`].
+        #[cfg(feature = "heap-profile")]
+        pub fn set_heap_profiling(enabled: bool) {
+            arceos_api::mem::ax_set_heap_profiling(enabled)
+        }
+
+        /// Switches heap tracing on or off at runtime.
+        ///
+        /// It is off by default. While it is on, every allocation and
+        /// deallocation is printed to the console as a line starting with
+        /// `[heap-trace]`, which can be replayed by the `alloc_bench` tool.
+        #[cfg(feature = "heap-profile")]
+        pub fn set_heap_tracing(enabled: bool) {
+            arceos_api::mem::ax_set_heap_tracing(enabled)
+        }
+
+        /// Prints a report of the recorded heap statistics to the console.
+        #[cfg(feature = "heap-profile")]
+        pub fn dump_heap_profile() {
+            arceos_api::mem::ax_dump_heap_profile()
+        }
+
+        /// Switches guard pages on or off for new allocations of at least one
+        /// page.
+        ///
+        /// It is on by default. Each of these allocations is followed by an
+        /// unmapped guard page, so that overflows fault immediately.
+        #[cfg(all(feature = "debug-heap", feature = "paging"))]
+        pub fn set_guard_pages(enabled: bool) {
+            arceos_api::mem::ax_set_guard_pages(enabled)
+        }
+    }
 }
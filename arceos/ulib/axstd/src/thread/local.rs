@@ -0,0 +1,112 @@
+//! Thread-local storage.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::fmt;
+
+use arceos_api::task as api;
+
+/// A thread-local storage key which owns its contents.
+///
+/// It is declared by the [`thread_local!`] macro. Each thread gets its own
+/// value, which is initialized on the first access by [`with`], and dropped
+/// when the thread exits.
+///
+/// The values are kept in the task-local storage of `axtask`, so the `tls`
+/// feature is not required.
+///
+/// [`with`]: LocalKey::with
+/// [`thread_local!`]: crate::thread_local
+pub struct LocalKey<T: 'static> {
+    init: fn() -> T,
+}
+
+/// An error returned by [`LocalKey::try_with`], if the value is accessed while
+/// the thread is exiting (e.g., in the destructors of other values).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("already destroyed")
+    }
+}
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+
+    /// Acquires a reference to the value in this thread, initializes it first
+    /// if it has not been accessed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value is accessed while the thread is exiting.
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.try_with(f)
+            .expect("cannot access a thread local variable while the thread is exiting")
+    }
+
+    /// Acquires a reference to the value in this thread, initializes it first
+    /// if it has not been accessed.
+    ///
+    /// Returns [`AccessError`] if the value is accessed while the thread is
+    /// exiting.
+    pub fn try_with<F, R>(&'static self, f: F) -> Result<R, AccessError>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let key = self as *const Self as usize;
+        let mut ret = None;
+        api::ax_with_current_local(
+            key,
+            || Box::new((self.init)()),
+            |var| ret = Some(f(var.downcast_ref().unwrap())),
+        );
+        ret.ok_or(AccessError)
+    }
+}
+
+impl<T: 'static> fmt::Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalKey").finish_non_exhaustive()
+    }
+}
+
+/// Declares new thread-local storage keys of type [`LocalKey`].
+///
+/// # Examples
+///
+/// ```ignore
+/// use core::cell::Cell;
+/// use axstd::thread_local;
+///
+/// thread_local! {
+///     static COUNTER: Cell<u32> = Cell::new(0);
+/// }
+///
+/// COUNTER.with(|c| c.set(c.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! thread_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::thread_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::thread_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::thread::LocalKey<$t> = {
+            fn __init() -> $t {
+                $init
+            }
+            $crate::thread::LocalKey::new(__init)
+        };
+    };
+}
@@ -1,8 +1,12 @@
 //! Native threads.
 
+#[cfg(feature = "multitask")]
+mod local;
 #[cfg(feature = "multitask")]
 mod multi;
 #[cfg(feature = "multitask")]
+pub use local::{AccessError, LocalKey};
+#[cfg(feature = "multitask")]
 pub use multi::*;
 
 use arceos_api::task as api;
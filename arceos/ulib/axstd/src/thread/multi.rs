@@ -9,6 +9,8 @@ use core::{cell::UnsafeCell, num::NonZeroU64};
 use arceos_api::task::{self as api, AxTaskHandle};
 use axerrno::ax_err_type;
 
+pub use arceos_api::task::AxCpuMask as CpuMask;
+
 /// A unique identifier for a running thread.
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub struct ThreadId(NonZeroU64);
@@ -49,6 +51,8 @@ pub struct Builder {
     name: Option<String>,
     // The size of the stack for the spawned thread in bytes
     stack_size: Option<usize>,
+    // The CPUs that the spawned thread is allowed to run on
+    affinity: Option<CpuMask>,
 }
 
 impl Builder {
@@ -58,6 +62,7 @@ impl Builder {
         Builder {
             name: None,
             stack_size: None,
+            affinity: None,
         }
     }
 
@@ -73,6 +78,14 @@ impl Builder {
         self
     }
 
+    /// Sets the CPUs that the new thread is allowed to run on.
+    ///
+    /// By default, it can run on all CPUs.
+    pub fn affinity(mut self, cpumask: CpuMask) -> Builder {
+        self.affinity = Some(cpumask);
+        self
+    }
+
     /// Spawns a new thread by taking ownership of the `Builder`, and returns an
     /// [`io::Result`] to its [`JoinHandle`].
     ///
@@ -115,7 +128,10 @@ impl Builder {
             drop(their_packet);
         };
 
-        let task = api::ax_spawn(main, name, stack_size);
+        let task = match self.affinity {
+            Some(cpumask) => api::ax_spawn_with_affinity(main, name, stack_size, cpumask)?,
+            None => api::ax_spawn(main, name, stack_size),
+        };
         Ok(JoinHandle {
             thread: Thread::from_id(task.id()),
             native: task,
@@ -130,6 +146,34 @@ pub fn current() -> Thread {
     Thread::from_id(id)
 }
 
+/// Returns the CPUs that the current thread is allowed to run on.
+pub fn affinity() -> CpuMask {
+    api::ax_get_affinity(None)
+}
+
+/// Sets the CPUs that the current thread is allowed to run on.
+///
+/// If the current CPU is not in `cpumask`, the thread is migrated to one of
+/// them before this function returns.
+pub fn set_affinity(cpumask: CpuMask) -> io::Result<()> {
+    api::ax_set_affinity(None, cpumask)
+}
+
+/// Returns the name of the scheduler of normal threads in use.
+pub fn scheduler() -> &'static str {
+    api::ax_scheduler_name()
+}
+
+/// Selects the scheduler of normal threads by its name, i.e., `"fifo"`,
+/// `"rr"`, `"cfs"`, or one registered by the kernel.
+///
+/// The ready threads are moved to the new scheduler at once. The preemptive
+/// schedulers only take effect at yields and blocks if the `preempt` feature
+/// is disabled.
+pub fn set_scheduler(name: &str) -> io::Result<()> {
+    api::ax_select_scheduler(name)
+}
+
 /// Spawns a new thread, returning a [`JoinHandle`] for it.
 ///
 /// The join handle provides a [`join`] method that can be used to join the